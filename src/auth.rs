@@ -0,0 +1,197 @@
+//! Authentication for the HTTP API.
+//!
+//! Routes that change the device's state (recalibrating sensors, selecting a
+//! WiFi access point, changing the configuration, etc.) require an admin
+//! password. The password is set during first-run provisioning, when the WiFi
+//! access point is selected, and is stored in NVS.
+//!
+//! Requests are authenticated using either HTTP Basic authentication (with
+//! the username [`USERNAME`]) or a bearer token containing the admin password.
+use crate::nvs::{Nvs, Partition};
+use anyhow::Context;
+use std::sync::Mutex;
+
+/// The username for HTTP Basic authentication.
+pub const USERNAME: &str = "admin";
+
+/// The admin credential.
+pub struct Admin {
+    nvs: Mutex<Nvs>,
+    password: Mutex<Option<String>>,
+}
+
+/// The result of checking a request's credentials.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Authorization {
+    /// The request carried the admin password.
+    Authorized,
+    /// The request did not carry credentials, or the credentials were wrong.
+    Unauthorized,
+    /// No admin password has been set yet.
+    Unprovisioned,
+}
+
+const TARGET: &str = "eclss::auth";
+
+// === impl Admin ===
+
+impl Admin {
+    const NAMESPACE: &'static str = "eclss";
+    const KEY: &'static str = "admin_password";
+
+    pub const MIN_PASSWORD_LEN: usize = 8;
+    pub const MAX_PASSWORD_LEN: usize = 64;
+
+    /// Loads the admin credential from NVS.
+    pub fn new(partition: Partition) -> anyhow::Result<Self> {
+        let nvs = Nvs::new(partition, Self::NAMESPACE, true)
+            .context("failed to open admin credential NVS namespace")?;
+        let mut buf = [0; Self::MAX_PASSWORD_LEN];
+        let password = nvs
+            .get_raw(Self::KEY, &mut buf)
+            .context("failed to read admin password from NVS")?
+            .map(|password| {
+                std::str::from_utf8(password)
+                    .map(String::from)
+                    .context("stored admin password is not UTF-8")
+            })
+            .transpose()?;
+        if password.is_none() {
+            log::warn!(target: TARGET, "no admin password has been set; mutating HTTP routes are disabled until one is");
+        }
+
+        Ok(Self {
+            nvs: Mutex::new(nvs),
+            password: Mutex::new(password),
+        })
+    }
+
+    /// Returns `true` if an admin password has been set.
+    #[must_use]
+    pub fn is_provisioned(&self) -> bool {
+        self.password.lock().unwrap().is_some()
+    }
+
+    /// Validates and saves a new admin password to NVS.
+    pub fn set_password(&self, password: &str) -> anyhow::Result<()> {
+        validate_password(password)?;
+        self.nvs
+            .lock()
+            .unwrap()
+            .set_raw(Self::KEY, password.as_bytes())
+            .context("failed to write admin password to NVS")?;
+        *self.password.lock().unwrap() = Some(password.to_string());
+        log::info!(target: TARGET, "admin password changed");
+        Ok(())
+    }
+
+    /// Checks the value of a request's `Authorization` header.
+    #[must_use]
+    pub fn check(&self, authorization: Option<&str>) -> Authorization {
+        let password = self.password.lock().unwrap();
+        let Some(password) = password.as_deref() else {
+            return Authorization::Unprovisioned;
+        };
+
+        if authorization.map_or(false, |header| check_header(password, header)) {
+            Authorization::Authorized
+        } else {
+            Authorization::Unauthorized
+        }
+    }
+}
+
+pub fn validate_password(password: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (Admin::MIN_PASSWORD_LEN..=Admin::MAX_PASSWORD_LEN).contains(&password.len()),
+        "admin password must be between {} and {} bytes long",
+        Admin::MIN_PASSWORD_LEN,
+        Admin::MAX_PASSWORD_LEN,
+    );
+    Ok(())
+}
+
+/// Returns `true` if an `Authorization` header value carries `password`.
+fn check_header(password: &str, header: &str) -> bool {
+    let Some((scheme, credentials)) = header.trim().split_once(' ') else {
+        return false;
+    };
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return constant_time_eq(password.as_bytes(), credentials.as_bytes());
+    }
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let Ok(decoded) = base64::decode(credentials) else {
+            return false;
+        };
+        let Some(split) = decoded.iter().position(|&b| b == b':') else {
+            return false;
+        };
+        let (username, password_provided) = (&decoded[..split], &decoded[split + 1..]);
+        // don't short-circuit, so that a wrong username takes as long to
+        // reject as a wrong password.
+        let username_ok = constant_time_eq(USERNAME.as_bytes(), username);
+        let password_ok = constant_time_eq(password.as_bytes(), password_provided);
+        return username_ok & password_ok;
+    }
+
+    false
+}
+
+/// Compares two byte strings without returning early at the first mismatch,
+/// so that the comparison doesn't leak how much of a guessed password was
+/// correct.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "hunter22";
+
+    #[test]
+    fn accepts_basic_auth() {
+        let header = format!("Basic {}", base64::encode("admin:hunter22"));
+        assert!(check_header(PASSWORD, &header));
+        let header = format!("basic {}", base64::encode("admin:hunter22"));
+        assert!(check_header(PASSWORD, &header));
+    }
+
+    #[test]
+    fn accepts_bearer_token() {
+        assert!(check_header(PASSWORD, "Bearer hunter22"));
+    }
+
+    #[test]
+    fn rejects_wrong_credentials() {
+        let header = format!("Basic {}", base64::encode("admin:hunter2"));
+        assert!(!check_header(PASSWORD, &header));
+        let header = format!("Basic {}", base64::encode("root:hunter22"));
+        assert!(!check_header(PASSWORD, &header));
+        assert!(!check_header(PASSWORD, "Bearer hunter2"));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(!check_header(PASSWORD, ""));
+        assert!(!check_header(PASSWORD, "hunter22"));
+        assert!(!check_header(PASSWORD, "Basic not-base64!"));
+        let header = format!("Basic {}", base64::encode("adminhunter22"));
+        assert!(!check_header(PASSWORD, &header));
+        assert!(!check_header(PASSWORD, "Digest hunter22"));
+    }
+
+    #[test]
+    fn validates_password_length() {
+        assert!(validate_password("short").is_err());
+        assert!(validate_password(&"a".repeat(Admin::MAX_PASSWORD_LEN + 1)).is_err());
+        validate_password(PASSWORD).unwrap();
+    }
+}
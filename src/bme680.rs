@@ -1,60 +1,104 @@
-use crate::{metrics::Gauge, sensor::Sensor, units, I2cBus, I2cRef, SensorMetrics};
-use esp_idf_hal::delay::Ets;
+use crate::{
+    compensation::COMPENSATION,
+    config::SensorConfig,
+    metrics::{Gauge, SensorLabel},
+    sensor::Sensor,
+    units, Delay, I2cBus, I2cRef, SensorMetrics,
+};
+use anyhow::anyhow;
+use embassy_time::Duration;
+use embedded_hal::blocking::i2c::WriteRead;
 use std::num::Wrapping;
 
 pub struct Bme680 {
-    sensor: bosch_bme680::Bme680<I2cRef<'static>, Ets>,
+    sensor: bosch_bme680::Bme680<I2cRef<'static>, Delay>,
+    busman: &'static I2cBus,
+    addr: u8,
+    config: bosch_bme680::Configuration,
+    /// The ambient temperature (in degrees Celcius) that the gas sensor heater
+    /// is currently configured for.
+    ambient_temp: f32,
+    label: SensorLabel,
     pressure_gauge: &'static Gauge,
     temp_gauge: &'static Gauge,
     rel_humidity_gauge: &'static Gauge,
     abs_humidity_gauge: &'static Gauge,
     gas_resistance_gauge: &'static Gauge,
     polls: Wrapping<usize>,
+    poll_interval: Duration,
 }
 
 const NAME: &str = "BME680";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// The ambient temperature used to configure the gas sensor heater if no
+/// other sensor has measured it recently.
+const DEFAULT_AMBIENT_TEMP: f32 = 20.0;
+/// How much the ambient temperature must change (in degrees Celcius) before
+/// the gas sensor heater is reconfigured.
+const AMBIENT_TEMP_THRESHOLD: f32 = 2.0;
 
 impl Sensor for Bme680 {
     type ControlMessage = ();
 
     const NAME: &'static str = NAME;
+    const ADDRESSES: &'static [u8] = &[
+        // the default I2C address of the Adafruit BME680 breakout board
+        // is the "secondary" address, 0x77.
+        0x77, 0x76,
+    ];
 
-    fn init(busman: &'static I2cBus, metrics: &'static SensorMetrics) -> anyhow::Result<Self> {
-        let config = bosch_bme680::Configuration::default();
-        log::info!(target: NAME, "connecting to BME680 with config {config:#?}");
-        let i2c = busman.acquire_i2c();
-        let sensor = bosch_bme680::Bme680::new(
-            i2c,
-            // the default I2C address of the Adafruit BME680 breakout board
-            // is the "secondary" address, 0x77.
-            bosch_bme680::DeviceAddress::Secondary,
-            Ets,
-            &config,
-            // TODO(eliza): can we get the ambient temperature from a SCD30 measurement?
-            20,
-        )
-        .map_err(|error| anyhow::anyhow!("failed to connect to BME680: {error:?}"))?;
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+        const CHIP_ID_REG: u8 = 0xD0;
+        const CHIP_ID: u8 = 0x61;
+        let mut chip_id = [0];
+        i2c.write_read(addr, &[CHIP_ID_REG], &mut chip_id)
+            .map_err(|error| anyhow!("failed to read chip ID: {error:?}"))?;
+        log::debug!(target: NAME, "probe: chip ID at {addr:#04x} is {:#04x}", chip_id[0]);
+        Ok(chip_id[0] == CHIP_ID)
+    }
+
+    fn init(
+        busman: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self> {
+        let label = SensorLabel::new(NAME, addr);
+        let bme_config = bosch_bme680::Configuration::default();
+        log::info!(target: NAME, "connecting to {label} with config {bme_config:#?}");
+        // the gas sensor heater's resistance is calculated from the ambient
+        // temperature, so use the temperature measured by another sensor if
+        // there is one.
+        let ambient_temp = ambient_temp(label);
+        log::info!(target: NAME, "using ambient temperature {ambient_temp:>3.2} \u{00B0}C for {label}");
+        let sensor = connect(busman, addr, &bme_config, ambient_temp)?;
 
         Ok(Self {
             sensor,
-            pressure_gauge: metrics
-                .pressure
-                .register(Self::LABEL)
-                .expect("can't register"),
-            temp_gauge: metrics.temp.register(Self::LABEL).expect("can't register"),
+            busman,
+            addr,
+            config: bme_config,
+            ambient_temp,
+            label,
+            pressure_gauge: metrics.pressure.register(label).expect("can't register"),
+            temp_gauge: metrics.temp.register(label).expect("can't register"),
             rel_humidity_gauge: metrics
                 .rel_humidity
-                .register(Self::LABEL)
+                .register(label)
                 .expect("can't register"),
             abs_humidity_gauge: metrics
                 .abs_humidity
-                .register(Self::LABEL)
+                .register(label)
                 .expect("can't register"),
             gas_resistance_gauge: metrics
                 .gas_resistance
-                .register(Self::LABEL)
+                .register(label)
                 .expect("can't register"),
             polls: Wrapping(0),
+            poll_interval: config
+                .poll_interval_secs
+                .map(|secs| Duration::from_secs(secs.into()))
+                .unwrap_or(DEFAULT_POLL_INTERVAL),
         })
     }
 
@@ -67,7 +111,7 @@ impl Sensor for Bme680 {
         } = self
             .sensor
             .measure()
-            .map_err(|error| anyhow::anyhow!("error reading from BME680: {error:?}"))?;
+            .map_err(|error| anyhow!("error reading from BME680: {error:?}"))?;
         self.polls += 1;
 
         // pretty sure the `bosch-bme680` library is off by a factor of 100 when
@@ -78,6 +122,8 @@ impl Sensor for Bme680 {
         self.pressure_gauge.set_value(pressure.into());
         self.temp_gauge.set_value(temperature.into());
         self.rel_humidity_gauge.set_value(humidity.into());
+        COMPENSATION.pressure.record(self.label, pressure);
+        COMPENSATION.temperature.record(self.label, temperature);
 
         if let Some(gas) = gas_resistance {
             log::info!(target: NAME, "Gas resistance: {gas:>3.2} \u{2126}");
@@ -89,17 +135,133 @@ impl Sensor for Bme680 {
         if self.polls.0 % units::ABS_HUMIDITY_INTERVAL == 0 {
             let abs_humidity = units::absolute_humidity(temperature, humidity);
             self.abs_humidity_gauge.set_value(abs_humidity.into());
+            COMPENSATION.abs_humidity.record(self.label, abs_humidity);
             log::info!(target: NAME, "Absolute Humidity: {abs_humidity:>3.2} g/𝑚³");
         }
+
+        // the measurement was successful even if reconfiguring the heater
+        // wasn't, so don't fail the poll.
+        if let Err(error) = self.update_heater(ambient_temp(self.label)) {
+            log::warn!(target: NAME, "{error:#}");
+        }
+
+        Ok(())
+    }
+
+    fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    fn set_poll_interval(&mut self, interval: Duration) -> anyhow::Result<()> {
+        self.poll_interval = interval;
         Ok(())
     }
 
-    fn poll_interval(&self) -> embassy_time::Duration {
-        embassy_time::Duration::from_secs(2)
+    fn check_poll_interval(_: Duration) -> anyhow::Result<()> {
+        Ok(())
     }
 
     fn handle_control_message(&mut self, _: &Self::ControlMessage) -> anyhow::Result<()> {
-        // TODO(eliza): calibrate with ambient temp?
         anyhow::bail!("not yet implemented")
     }
 }
+
+impl Bme680 {
+    /// Reconfigures the gas sensor heater for a new ambient temperature, if
+    /// the ambient temperature has changed significantly.
+    ///
+    /// The driver only calculates the heater's resistance when it's created,
+    /// so this reconnects to the sensor.
+    fn update_heater(&mut self, ambient_temp: f32) -> anyhow::Result<()> {
+        if (ambient_temp - self.ambient_temp).abs() < AMBIENT_TEMP_THRESHOLD {
+            return Ok(());
+        }
+
+        self.sensor =
+            connect(self.busman, self.addr, &self.config, ambient_temp).map_err(|error| {
+                error.context(format!(
+                    "failed to reconfigure {} heater for {ambient_temp:>3.2} \u{00B0}C",
+                    self.label
+                ))
+            })?;
+        log::info!(target: NAME, "reconfigured {} heater for ambient temperature {ambient_temp:>3.2} \u{00B0}C", self.label);
+        self.ambient_temp = ambient_temp;
+        Ok(())
+    }
+}
+
+/// Returns the ambient temperature (in degrees Celcius) measured by sensors
+/// other than the BME680 labeled `label`, or [`DEFAULT_AMBIENT_TEMP`] if no
+/// other sensor has measured it recently.
+///
+/// The BME680's own temperature measurement is skewed by its gas sensor
+/// heater, so it isn't used to configure the heater.
+fn ambient_temp(label: SensorLabel) -> f32 {
+    COMPENSATION
+        .temperature
+        .get_excluding(label)
+        .unwrap_or(DEFAULT_AMBIENT_TEMP)
+}
+
+/// Connects to the BME680 at `addr`, configuring its gas sensor heater for
+/// `ambient_temp` (in degrees Celcius).
+fn connect(
+    busman: &'static I2cBus,
+    addr: u8,
+    config: &bosch_bme680::Configuration,
+    ambient_temp: f32,
+) -> anyhow::Result<bosch_bme680::Bme680<I2cRef<'static>, Delay>> {
+    let address = match addr {
+        0x76 => bosch_bme680::DeviceAddress::Primary,
+        0x77 => bosch_bme680::DeviceAddress::Secondary,
+        addr => anyhow::bail!("invalid BME680 address {addr:#04x}"),
+    };
+    bosch_bme680::Bme680::new(
+        busman.acquire_i2c(),
+        address,
+        Delay,
+        config,
+        ambient_temp.round() as _,
+    )
+    .map_err(|error| anyhow!("failed to connect to BME680: {error:?}"))
+}
+
+#[cfg(all(test, not(target_os = "espidf")))]
+mod tests {
+    use super::*;
+    use crate::sim;
+
+    #[test]
+    fn heater_follows_ambient_temperature() {
+        let bus = sim::SimBus::new();
+        let bme680 = sim::Bme680::new();
+        let addr = sim::bme680::ADDRS[1];
+        bus.attach(addr, bme680.clone());
+        let metrics: &'static SensorMetrics = Box::leak(Box::new(SensorMetrics::new()));
+        let mut sensor = Bme680::init(
+            bus.leak_bus_manager(),
+            addr,
+            metrics,
+            &SensorConfig::default(),
+        )
+        .unwrap();
+        // the sensor was configured for whatever temperature other tests
+        // have recorded, so start from a known one.
+        sensor.ambient_temp = DEFAULT_AMBIENT_TEMP;
+        let configured = bme680.heater_configs();
+
+        // small changes don't reconfigure the heater.
+        sensor.update_heater(DEFAULT_AMBIENT_TEMP + 1.0).unwrap();
+        assert_eq!(sensor.ambient_temp, DEFAULT_AMBIENT_TEMP);
+        assert_eq!(bme680.heater_configs(), configured);
+
+        sensor.update_heater(DEFAULT_AMBIENT_TEMP + 15.0).unwrap();
+        assert_eq!(sensor.ambient_temp, DEFAULT_AMBIENT_TEMP + 15.0);
+        assert!(bme680.heater_configs() > configured);
+
+        // if the sensor is gone, the old configuration is kept.
+        bus.detach(addr);
+        assert!(sensor.update_heater(DEFAULT_AMBIENT_TEMP).is_err());
+        assert_eq!(sensor.ambient_temp, DEFAULT_AMBIENT_TEMP + 15.0);
+    }
+}
@@ -0,0 +1,301 @@
+//! Board support profiles.
+//!
+//! Everything that differs between the ESP32 boards ECLSS can run on (which
+//! chip it has, what kind of status LED it has and where, which pins its
+//! Stemma QT/Qwiic connector uses, and any on-board peripherals that have to
+//! be powered up) is described by a [`Board`]. The board is selected at build
+//! time by enabling one of the `board-*` cargo features, and the selected
+//! profile is available as [`BOARD`].
+//!
+//! The QT Py ESP32-C3 is the default; enabling any other board's feature
+//! takes precedence over it.
+use std::fmt;
+
+/// Describes the hardware on a particular ESP32 board.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Board {
+    /// A short identifier for the board, advertised in the `board` mDNS TXT
+    /// record.
+    pub id: &'static str,
+    /// The board's human-readable name.
+    pub name: &'static str,
+    pub chip: Chip,
+    pub status_led: StatusLed,
+    /// The GPIO number of the SDA pin on the board's I2C connector, used by
+    /// the default I2C bus.
+    pub i2c_sda: u8,
+    /// The GPIO number of the SCL pin on the board's I2C connector, used by
+    /// the default I2C bus.
+    pub i2c_scl: u8,
+    /// GPIOs that must be driven high to power on-board peripherals (such as
+    /// the status LED or the I2C connector) before they can be used.
+    pub power_pins: &'static [u8],
+}
+
+/// The ESP32 variants that ECLSS supports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Chip {
+    Esp32c3,
+    Esp32s3,
+}
+
+/// The kind of status LED on a board, used by the
+/// [`indicator`](crate::indicator).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StatusLed {
+    /// The board doesn't have an LED that can be controlled.
+    None,
+    /// A WS2812 ("NeoPixel") RGB LED.
+    NeoPixel { pin: u8 },
+}
+
+/// [Adafruit QT Py ESP32-C3](https://www.adafruit.com/product/5405).
+pub const QT_PY_C3: Board = Board {
+    id: "qtpy-esp32c3",
+    name: "Adafruit QT Py ESP32-C3",
+    chip: Chip::Esp32c3,
+    status_led: StatusLed::NeoPixel { pin: 2 },
+    // Stemma QT connector.
+    i2c_sda: 5,
+    i2c_scl: 6,
+    power_pins: &[],
+};
+
+/// [Espressif ESP32-C3-DevKitM-1](https://docs.espressif.com/projects/esp-idf/en/latest/esp32c3/hw-reference/esp32c3/user-guide-devkitm-1.html).
+pub const C3_DEVKITM: Board = Board {
+    id: "esp32-c3-devkitm-1",
+    name: "Espressif ESP32-C3-DevKitM-1",
+    chip: Chip::Esp32c3,
+    status_led: StatusLed::NeoPixel { pin: 8 },
+    // the DevKitM doesn't have an I2C connector, so these are just a pair of
+    // pins that aren't used for strapping or USB.
+    i2c_sda: 4,
+    i2c_scl: 5,
+    power_pins: &[],
+};
+
+/// [Seeed Studio XIAO ESP32C3](https://wiki.seeedstudio.com/XIAO_ESP32C3_Getting_Started/).
+pub const XIAO_C3: Board = Board {
+    id: "xiao-esp32c3",
+    name: "Seeed Studio XIAO ESP32C3",
+    chip: Chip::Esp32c3,
+    // the XIAO's only LED is the charge LED, which isn't connected to a GPIO.
+    status_led: StatusLed::None,
+    // pins D4 and D5.
+    i2c_sda: 6,
+    i2c_scl: 7,
+    power_pins: &[],
+};
+
+/// [Adafruit ESP32-S3 Feather](https://www.adafruit.com/product/5323).
+///
+/// The Feather also has a MAX17048 battery monitor at `0x36` on the Stemma QT
+/// bus, which is left alone for now.
+pub const S3_FEATHER: Board = Board {
+    id: "feather-esp32s3",
+    name: "Adafruit ESP32-S3 Feather",
+    chip: Chip::Esp32s3,
+    status_led: StatusLed::NeoPixel { pin: 33 },
+    // Stemma QT connector.
+    i2c_sda: 3,
+    i2c_scl: 4,
+    // NEOPIXEL_POWER and I2C_POWER.
+    power_pins: &[21, 7],
+};
+
+/// The board this firmware was built for.
+#[cfg(feature = "board-c3-devkitm")]
+pub const BOARD: Board = C3_DEVKITM;
+/// The board this firmware was built for.
+#[cfg(feature = "board-xiao-c3")]
+pub const BOARD: Board = XIAO_C3;
+/// The board this firmware was built for.
+#[cfg(feature = "board-feather-s3")]
+pub const BOARD: Board = S3_FEATHER;
+/// The board this firmware was built for.
+#[cfg(not(any(
+    feature = "board-c3-devkitm",
+    feature = "board-xiao-c3",
+    feature = "board-feather-s3"
+)))]
+pub const BOARD: Board = QT_PY_C3;
+
+#[cfg(not(any(
+    feature = "board-qtpy-c3",
+    feature = "board-c3-devkitm",
+    feature = "board-xiao-c3",
+    feature = "board-feather-s3"
+)))]
+compile_error!("one of the 'board-*' features must be enabled to select a board");
+
+#[cfg(any(
+    all(feature = "board-c3-devkitm", feature = "board-xiao-c3"),
+    all(feature = "board-c3-devkitm", feature = "board-feather-s3"),
+    all(feature = "board-xiao-c3", feature = "board-feather-s3"),
+))]
+compile_error!("only one of the 'board-*' features may be enabled");
+
+#[cfg(all(target_os = "espidf", esp32c3))]
+const _: () = assert!(
+    matches!(BOARD.chip, Chip::Esp32c3),
+    "the selected board doesn't have an ESP32-C3; check the build target"
+);
+
+#[cfg(all(target_os = "espidf", esp32s3))]
+const _: () = assert!(
+    matches!(BOARD.chip, Chip::Esp32s3),
+    "the selected board doesn't have an ESP32-S3; check the build target"
+);
+
+// === impl Board ===
+
+impl Board {
+    /// Returns `true` if `pin` is used by the board's status LED or is one of
+    /// its power pins, and so can't be used for anything else.
+    #[must_use]
+    pub fn is_reserved(&self, pin: u8) -> bool {
+        matches!(self.status_led, StatusLed::NeoPixel { pin: led } if led == pin)
+            || self.power_pins.contains(&pin)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl Board {
+    /// Drives the board's [power pins](Board::power_pins) high.
+    pub fn power_on(&self) -> anyhow::Result<()> {
+        use anyhow::Context;
+        use esp_idf_hal::gpio::{AnyOutputPin, PinDriver};
+
+        for &pin in self.power_pins {
+            // Safety: power pins are reserved (see `Board::is_reserved`), so
+            // the config can't assign them to an I2C bus.
+            let mut driver = PinDriver::output(unsafe { AnyOutputPin::new(pin.into()) })
+                .with_context(|| format!("failed to configure power pin GPIO{pin}"))?;
+            driver.set_high()?;
+            // dropping the driver would reset the pin, turning the power back
+            // off.
+            std::mem::forget(driver);
+        }
+        Ok(())
+    }
+
+    /// Returns the board's status LED, if it has one that ECLSS knows how to
+    /// drive.
+    pub fn status_led(
+        &self,
+        channel: impl esp_idf_hal::peripheral::Peripheral<P = impl esp_idf_hal::rmt::RmtChannel>
+            + 'static,
+    ) -> anyhow::Result<Option<crate::ws2812::NeoPixel<'static>>> {
+        match self.status_led {
+            StatusLed::None => Ok(None),
+            StatusLed::NeoPixel { pin } => {
+                // Safety: the status LED pin is reserved (see
+                // `Board::is_reserved`), so the config can't assign it to an
+                // I2C bus.
+                let pin = unsafe { esp_idf_hal::gpio::AnyOutputPin::new(pin.into()) };
+                crate::ws2812::NeoPixel::new(pin, channel).map(Some)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.chip)
+    }
+}
+
+// === impl Chip ===
+
+impl Chip {
+    /// The chip's identifier, as used by ESP-IDF and `espflash` (e.g.
+    /// `esp32c3`).
+    #[must_use]
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::Esp32c3 => "esp32c3",
+            Self::Esp32s3 => "esp32s3",
+        }
+    }
+
+    /// Returns `true` if `pin` is a GPIO that exists on this chip and isn't
+    /// used for the SPI flash or USB.
+    #[must_use]
+    pub fn is_usable_gpio(self, pin: u8) -> bool {
+        match self {
+            // GPIO12-17 are the SPI flash, and GPIO18-19 are USB.
+            Self::Esp32c3 => matches!(pin, 0..=11 | 20..=21),
+            // GPIO19-20 are USB, GPIO22-25 don't exist, and GPIO26-32 are the
+            // SPI flash and PSRAM.
+            Self::Esp32s3 => matches!(pin, 0..=18 | 21 | 33..=48),
+        }
+    }
+
+    /// The number of I2C peripherals on this chip.
+    #[must_use]
+    pub const fn i2c_peripherals(self) -> u8 {
+        match self {
+            Self::Esp32c3 => 1,
+            Self::Esp32s3 => 2,
+        }
+    }
+
+    /// The chip ID in the header of app images built for this chip
+    /// (`ESP_CHIP_ID_*`).
+    #[must_use]
+    pub const fn image_chip_id(self) -> u16 {
+        match self {
+            Self::Esp32c3 => 0x0005,
+            Self::Esp32s3 => 0x0009,
+        }
+    }
+}
+
+impl fmt::Display for Chip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Esp32c3 => "ESP32-C3",
+            Self::Esp32s3 => "ESP32-S3",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_only_use_usable_pins() {
+        for board in [QT_PY_C3, C3_DEVKITM, XIAO_C3, S3_FEATHER] {
+            let mut pins = vec![board.i2c_sda, board.i2c_scl];
+            if let StatusLed::NeoPixel { pin } = board.status_led {
+                pins.push(pin);
+            }
+            pins.extend_from_slice(board.power_pins);
+            for pin in pins {
+                assert!(
+                    board.chip.is_usable_gpio(pin),
+                    "{board} uses GPIO{pin}, which isn't usable"
+                );
+            }
+            assert!(!board.is_reserved(board.i2c_sda));
+            assert!(!board.is_reserved(board.i2c_scl));
+        }
+    }
+
+    #[test]
+    fn boards_dont_reuse_pins() {
+        for board in [QT_PY_C3, C3_DEVKITM, XIAO_C3, S3_FEATHER] {
+            let mut pins = vec![board.i2c_sda, board.i2c_scl];
+            if let StatusLed::NeoPixel { pin } = board.status_led {
+                pins.push(pin);
+            }
+            pins.extend_from_slice(board.power_pins);
+
+            let len = pins.len();
+            pins.sort_unstable();
+            pins.dedup();
+            assert_eq!(pins.len(), len, "{board} uses a pin more than once");
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! I2C bus health supervision.
+//!
+//! If a sensor is unplugged in the middle of a transaction, it can be left
+//! holding SDA low, and then *every* transaction on the bus fails. Each
+//! sensor's [`Manager`](crate::sensor::Manager) would just keep backing off
+//! independently, forever. Instead, the bus [`Supervisor`] notices when all
+//! the sensors on the bus are failing at once, and recovers the bus by
+//! clocking SCL until whatever is holding SDA lets go (the standard "9 clocks"
+//! recovery procedure) and reinstalling the I2C driver.
+use crate::{
+    config::Config,
+    metrics::{BusLabel, Counter},
+    retry::ExpBackoff,
+    sensor::{Status, STATUSES},
+    SensorMetrics,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+#[cfg(target_os = "espidf")]
+mod esp;
+#[cfg(target_os = "espidf")]
+pub use self::esp::{I2c, Peripherals};
+
+/// An I2C bus that can be recovered when a device is holding it stuck.
+pub trait Recover {
+    /// Returns `true` if a device is holding SDA or SCL low while the bus is
+    /// idle.
+    fn is_held(&mut self) -> bool;
+
+    /// Recovers the bus, returning an error if it's still stuck afterwards.
+    fn recover(&mut self) -> anyhow::Result<()>;
+}
+
+/// Watches for correlated sensor failures on an I2C bus, and recovers the bus
+/// when they indicate that it's stuck.
+pub struct Supervisor<B> {
+    bus: B,
+    label: BusLabel,
+    config: &'static Config,
+    recoveries: &'static Counter,
+}
+
+/// How often to check whether the bus is stuck.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long all the sensors on the bus must be failing before it's considered
+/// stuck, so that the sensors have a chance to notice that each other are
+/// back after a recovery.
+const STUCK_FOR: Duration = Duration::from_secs(5);
+
+/// The minimum number of failing sensors for the bus to be considered stuck
+/// based on their statuses alone.
+///
+/// A single failing sensor has most likely just been unplugged, so if fewer
+/// sensors than this are failing (such as when there's only one sensor on the
+/// bus), the bus is only considered stuck if a bus line is actually being held
+/// low.
+const MIN_FAILING: usize = 2;
+
+const TARGET: &str = "eclss::bus";
+
+// === impl Supervisor ===
+
+impl<B: Recover> Supervisor<B> {
+    /// Returns a new supervisor for the bus named `label`.
+    ///
+    /// Only the sensors that `config` assigns to this bus are considered when
+    /// deciding whether it's stuck.
+    pub fn new(
+        bus: B,
+        label: &'static str,
+        config: &'static Config,
+        metrics: &'static SensorMetrics,
+    ) -> anyhow::Result<Self> {
+        let label = BusLabel(label);
+        let recoveries = metrics.bus_recoveries.register(label).ok_or_else(|| {
+            anyhow::anyhow!("insufficient space in bus recovery metrics map for {label}")
+        })?;
+        Ok(Self {
+            bus,
+            label,
+            config,
+            recoveries,
+        })
+    }
+
+    /// Supervises the bus until the end of time.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        let label = self.label;
+        // if recovering the bus doesn't fix it, don't keep banging on it.
+        let mut backoff = ExpBackoff::new(Duration::from_secs(10))
+            .with_max(Duration::from_secs(10 * 60))
+            .with_target(TARGET);
+        let mut stuck_since = None;
+
+        loop {
+            Timer::after(CHECK_INTERVAL).await;
+
+            let statuses = STATUSES
+                .iter()
+                .filter(|(sensor, _)| self.is_on_bus(sensor.sensor))
+                .map(|(_, status)| status.status())
+                .collect::<Vec<_>>();
+            if !is_stuck(statuses, || self.bus.is_held()) {
+                if stuck_since.take().is_some() {
+                    log::debug!(target: TARGET, "{label} is no longer stuck");
+                    backoff.reset();
+                }
+                continue;
+            }
+
+            let since = *stuck_since.get_or_insert_with(Instant::now);
+            if since.elapsed() < STUCK_FOR {
+                continue;
+            }
+
+            log::warn!(target: TARGET, "every sensor on {label} is failing; recovering the bus...");
+            self.recoveries.fetch_add(1);
+            match self.bus.recover() {
+                Ok(()) => log::info!(target: TARGET, "recovered {label}"),
+                Err(error) => log::warn!(target: TARGET, "failed to recover {label}: {error:#}"),
+            }
+
+            // give the sensors a chance to come back before trying again.
+            backoff.wait().await;
+            stuck_since = None;
+        }
+    }
+
+    fn is_on_bus(&self, sensor: &str) -> bool {
+        self.config
+            .bus_for(sensor)
+            .map_or(false, |bus| bus.name == self.label.0)
+    }
+}
+
+/// Returns `true` if the statuses of the sensors on a bus indicate that the
+/// bus is stuck: none of them are working, and either several of them are
+/// failing, or fewer are failing but `is_held` says that a bus line is being
+/// held low.
+fn is_stuck(statuses: impl IntoIterator<Item = Status>, is_held: impl FnOnce() -> bool) -> bool {
+    let mut failing = 0;
+    for status in statuses {
+        match status {
+            Status::Up | Status::Calibrating | Status::WarmingUp => return false,
+            status if status.is_error() => failing += 1,
+            _ => {}
+        }
+    }
+    failing >= MIN_FAILING || (failing > 0 && is_held())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held() -> bool {
+        true
+    }
+
+    fn not_held() -> bool {
+        false
+    }
+
+    #[test]
+    fn stuck_if_several_sensors_are_failing() {
+        assert!(is_stuck([Status::BusError, Status::BusError], not_held));
+        assert!(is_stuck(
+            [
+                Status::BusError,
+                Status::ProtocolError,
+                Status::Missing,
+                Status::Disabled,
+            ],
+            not_held
+        ));
+
+        // a single failing sensor was probably just unplugged.
+        assert!(!is_stuck([Status::BusError, Status::Missing], not_held));
+        // if any sensor is still working, the bus isn't stuck.
+        assert!(!is_stuck(
+            [Status::BusError, Status::BusError, Status::Up],
+            held
+        ));
+        assert!(!is_stuck(
+            [Status::BusError, Status::BusError, Status::WarmingUp],
+            held
+        ));
+        assert!(!is_stuck([], held));
+    }
+
+    #[test]
+    fn stuck_if_a_lone_sensor_is_failing_and_the_bus_is_held() {
+        // e.g. the SCD30 alone on a slow bus.
+        assert!(is_stuck([Status::BusError], held));
+        assert!(!is_stuck([Status::BusError], not_held));
+        assert!(is_stuck([Status::BusError, Status::Missing], held));
+
+        // the bus lines aren't checked unless a sensor is failing.
+        assert!(!is_stuck([Status::Missing], || panic!("checked bus lines")));
+        assert!(!is_stuck([Status::Up], || panic!("checked bus lines")));
+    }
+}
@@ -0,0 +1,280 @@
+//! The ESP32's I2C buses, which can be recovered when they get stuck.
+use super::Recover;
+use crate::config::BusConfig;
+use anyhow::Context;
+use embedded_hal::blocking::{delay::DelayUs, i2c};
+#[cfg(not(any(esp32c2, esp32c3)))]
+use esp_idf_hal::i2c::I2C1;
+use esp_idf_hal::{
+    delay::Ets,
+    gpio::{AnyIOPin, PinDriver},
+    i2c::{I2cDriver, I2cError, I2C0},
+    prelude::*,
+    units::Hertz,
+};
+use esp_idf_sys::{EspError, ESP_ERR_INVALID_STATE};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+/// The ESP32's I2C peripherals, which are handed out to [`I2c`] buses.
+///
+/// A peripheral is in use for as long as any handle to the [`I2c`] bus using
+/// it exists. Once every handle to a bus has been dropped, its peripheral may
+/// be used by a new bus.
+pub struct Peripherals {
+    _i2c0: I2C0,
+    #[cfg(not(any(esp32c2, esp32c3)))]
+    _i2c1: I2C1,
+    in_use: Arc<[AtomicBool; PERIPHERALS]>,
+}
+
+/// A handle to one of the ESP32's I2C buses.
+///
+/// Cloning an `I2c` returns another handle to the same bus, so that the bus
+/// [`Supervisor`](super::Supervisor) can recover the bus while the sensors
+/// use it through `shared_bus`.
+#[derive(Clone)]
+pub struct I2c(Arc<Mutex<Inner>>);
+
+struct Inner {
+    /// This is `None` if the driver couldn't be reinstalled after recovering
+    /// the bus.
+    driver: Option<I2cDriver<'static>>,
+    peripheral: u8,
+    sda: u8,
+    scl: u8,
+    baudrate: Hertz,
+    /// Released when the bus is dropped (see [`Peripherals`]).
+    in_use: Arc<[AtomicBool; PERIPHERALS]>,
+}
+
+#[cfg(any(esp32c2, esp32c3))]
+const PERIPHERALS: usize = 1;
+#[cfg(not(any(esp32c2, esp32c3)))]
+const PERIPHERALS: usize = 2;
+
+/// Half of an SCL clock period while recovering the bus (100 kHz).
+const HALF_PERIOD_US: u32 = 5;
+
+/// How many times to sample the bus lines when checking whether they're held
+/// low.
+const LINE_SAMPLES: usize = 10;
+
+// === impl Peripherals ===
+
+impl Peripherals {
+    #[cfg(any(esp32c2, esp32c3))]
+    pub fn new(i2c0: I2C0) -> Self {
+        Self {
+            _i2c0: i2c0,
+            in_use: Default::default(),
+        }
+    }
+
+    #[cfg(not(any(esp32c2, esp32c3)))]
+    pub fn new(i2c0: I2C0, i2c1: I2C1) -> Self {
+        Self {
+            _i2c0: i2c0,
+            _i2c1: i2c1,
+            in_use: Default::default(),
+        }
+    }
+
+    /// Marks the I2C peripheral numbered `peripheral` as in use, returning an
+    /// error if it doesn't exist on this chip or is already in use.
+    fn take(&self, peripheral: u8) -> anyhow::Result<()> {
+        let in_use = self
+            .in_use
+            .get(peripheral as usize)
+            .with_context(|| format!("this chip doesn't have an I2C{peripheral} peripheral"))?;
+        anyhow::ensure!(
+            !in_use.swap(true, Ordering::AcqRel),
+            "I2C{peripheral} is already in use by another bus"
+        );
+        Ok(())
+    }
+}
+
+// === impl I2c ===
+
+impl I2c {
+    /// Installs the I2C driver for the bus described by `config`, using the
+    /// peripheral, pins, and baud rate it specifies.
+    ///
+    /// The driver is uninstalled and reinstalled when the bus is recovered,
+    /// so the I2C peripheral stays in use until every handle to the bus has
+    /// been dropped.
+    ///
+    /// `config` must have been validated by
+    /// [`Config::validate`](crate::config::Config::validate), which ensures
+    /// that its pins are usable GPIOs that aren't used by the board or by
+    /// another bus.
+    pub fn new(peripherals: &mut Peripherals, config: &BusConfig) -> anyhow::Result<Self> {
+        peripherals
+            .take(config.peripheral)
+            .with_context(|| format!("can't use I2C bus {}", config.name))?;
+        // if installing the driver fails, dropping `inner` releases the
+        // peripheral again.
+        let mut inner = Inner {
+            driver: None,
+            peripheral: config.peripheral,
+            sda: config.sda,
+            scl: config.scl,
+            baudrate: config.baudrate_khz.kHz().into(),
+            in_use: peripherals.in_use.clone(),
+        };
+        inner.driver = Some(inner.install()?);
+        Ok(Self(Arc::new(Mutex::new(inner))))
+    }
+
+    fn with_driver<T>(
+        &self,
+        f: impl FnOnce(&mut I2cDriver<'static>) -> Result<T, I2cError>,
+    ) -> Result<T, I2cError> {
+        let mut inner = self.0.lock().unwrap();
+        let driver = inner
+            .driver
+            .as_mut()
+            .ok_or_else(|| I2cError::other(EspError::from_infallible::<ESP_ERR_INVALID_STATE>()))?;
+        f(driver)
+    }
+}
+
+impl Recover for I2c {
+    fn is_held(&mut self) -> bool {
+        // holding the lock ensures that no transaction is in progress, so
+        // both lines should be pulled up. only consider the bus held if a line
+        // stays low for every sample, so that a glitch doesn't count.
+        let inner = self.0.lock().unwrap();
+        (0..LINE_SAMPLES).all(|_| {
+            let held = is_low(inner.sda) || is_low(inner.scl);
+            delay();
+            held
+        })
+    }
+
+    fn recover(&mut self) -> anyhow::Result<()> {
+        let mut inner = self.0.lock().unwrap();
+        // the driver must be uninstalled so that the pins can be bit-banged.
+        drop(inner.driver.take());
+        let clocked = inner.clock_out();
+        // reinstall the driver even if SDA is still stuck, so that the sensors
+        // can keep trying.
+        inner.driver = Some(inner.install()?);
+        clocked
+    }
+}
+
+impl i2c::Write for I2c {
+    type Error = I2cError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.with_driver(|driver| i2c::Write::write(driver, addr, bytes))
+    }
+}
+
+impl i2c::Read for I2c {
+    type Error = I2cError;
+
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_driver(|driver| i2c::Read::read(driver, addr, buf))
+    }
+}
+
+impl i2c::WriteRead for I2c {
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_driver(|driver| i2c::WriteRead::write_read(driver, addr, bytes, buf))
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    fn install(&self) -> anyhow::Result<I2cDriver<'static>> {
+        let config = esp_idf_hal::i2c::I2cConfig::new().baudrate(self.baudrate);
+        // Safety: this bus has the I2C peripheral marked as in use (see
+        // `I2c::new`), the pins come from a validated config that ensures
+        // they aren't used by the board or another bus, and any previously
+        // installed driver has been dropped.
+        let (sda, scl) = unsafe {
+            (
+                AnyIOPin::new(self.sda.into()),
+                AnyIOPin::new(self.scl.into()),
+            )
+        };
+        let driver = match self.peripheral {
+            0 => I2cDriver::new(unsafe { I2C0::new() }, sda, scl, &config),
+            #[cfg(not(any(esp32c2, esp32c3)))]
+            1 => I2cDriver::new(unsafe { I2C1::new() }, sda, scl, &config),
+            peripheral => anyhow::bail!("this chip doesn't have an I2C{peripheral} peripheral"),
+        };
+        driver.context("failed to install I2C driver")
+    }
+
+    /// Clocks SCL until the device holding SDA low lets go of it (at most 9
+    /// times, enough to finish any byte it was in the middle of sending), and
+    /// then sends a STOP condition.
+    fn clock_out(&self) -> anyhow::Result<()> {
+        // Safety: the I2C driver has been uninstalled, and the config was
+        // validated to ensure that the pins aren't used by the board or
+        // another bus, so nothing else is using them.
+        let (sda, scl) = unsafe {
+            (
+                AnyIOPin::new(self.sda.into()),
+                AnyIOPin::new(self.scl.into()),
+            )
+        };
+        let mut sda = PinDriver::input_output_od(sda).context("failed to configure SDA")?;
+        let mut scl = PinDriver::input_output_od(scl).context("failed to configure SCL")?;
+        sda.set_high()?;
+        scl.set_high()?;
+        delay();
+
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low()?;
+            delay();
+            scl.set_high()?;
+            delay();
+        }
+
+        // STOP condition: SDA goes high while SCL is high.
+        scl.set_low()?;
+        delay();
+        sda.set_low()?;
+        delay();
+        scl.set_high()?;
+        delay();
+        sda.set_high()?;
+        delay();
+
+        anyhow::ensure!(
+            sda.is_high(),
+            "SDA is still held low after clocking SCL 9 times"
+        );
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // uninstall the driver before anything else can use the peripheral.
+        drop(self.driver.take());
+        self.in_use[self.peripheral as usize].store(false, Ordering::Release);
+    }
+}
+
+fn is_low(pin: u8) -> bool {
+    // Safety: reading a pin's input level doesn't change its configuration.
+    unsafe { esp_idf_sys::gpio_get_level(pin.into()) == 0 }
+}
+
+fn delay() {
+    DelayUs::<u32>::delay_us(&mut Ets, HALF_PERIOD_US);
+}
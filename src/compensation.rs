@@ -0,0 +1,197 @@
+//! Cross-sensor environmental compensation.
+//!
+//! Several sensors produce more accurate measurements when they're told about
+//! the environment they're in, and other sensors on the bus measure exactly
+//! that environment:
+//!
+//! - the SCD30's CO2 measurements are compensated for ambient pressure,
+//!   which is measured by the BME680,
+//! - the BME680's gas sensor heater is configured based on the ambient
+//!   temperature, which is measured by the SCD30's onboard SHT31 (or by
+//!   another BME680),
+//! - the SGP30's tVOC measurements are compensated for absolute humidity,
+//!   which is measured by the SCD30 and BME680.
+//!
+//! Sensors [`record`](Input::record) their measurements in the [`Input`]s of
+//! [`COMPENSATION`], and other sensors [`get`](Input::get) the average of the
+//! recent measurements from every sensor. Measurements older than an input's
+//! maximum age are ignored, so that a sensor which has gone down doesn't
+//! continue to compensate other sensors with a stale measurement forever.
+use crate::metrics::SensorLabel;
+use embassy_time::{Duration, Instant};
+use std::sync::Mutex;
+
+/// Environmental measurements shared between sensors.
+pub static COMPENSATION: Compensation = Compensation::new();
+
+pub struct Compensation {
+    /// Ambient temperature, in degrees Celcius.
+    pub temperature: Input,
+    /// Barometric pressure, in hectopascals (hPa).
+    pub pressure: Input,
+    /// Absolute humidity, in grams per cubic meter.
+    pub abs_humidity: Input,
+}
+
+/// One kind of environmental measurement, recorded by any number of sensors.
+pub struct Input {
+    name: &'static str,
+    max_age: Duration,
+    readings: Mutex<Vec<Reading>>,
+}
+
+#[derive(Debug)]
+struct Reading {
+    sensor: SensorLabel,
+    value: f32,
+    at: Instant,
+}
+
+const TARGET: &str = "eclss::compensation";
+
+// === impl Compensation ===
+
+impl Compensation {
+    pub const fn new() -> Self {
+        Self {
+            temperature: Input::new("temperature", Duration::from_secs(2 * 60)),
+            // pressure changes slowly, so older measurements are still useful.
+            pressure: Input::new("pressure", Duration::from_secs(10 * 60)),
+            abs_humidity: Input::new("absolute humidity", Duration::from_secs(2 * 60)),
+        }
+    }
+}
+
+impl Default for Compensation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// === impl Input ===
+
+impl Input {
+    pub const fn new(name: &'static str, max_age: Duration) -> Self {
+        Self {
+            name,
+            max_age,
+            readings: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a new measurement from `sensor`, replacing its previous one.
+    pub fn record(&self, sensor: SensorLabel, value: f32) {
+        self.record_at(sensor, value, Instant::now())
+    }
+
+    /// Returns the average of every sensor's most recent measurement, ignoring
+    /// measurements that are too old, or `None` if there are no recent
+    /// measurements.
+    pub fn get(&self) -> Option<f32> {
+        self.get_at(Instant::now())
+    }
+
+    /// Returns the average of every sensor's most recent measurement, like
+    /// [`get`](Self::get), but ignoring measurements from `sensor`.
+    ///
+    /// This is for sensors that also record this input, but are compensated
+    /// for it themselves, so that their own measurement isn't fed back into
+    /// them.
+    pub fn get_excluding(&self, sensor: SensorLabel) -> Option<f32> {
+        self.average_at(Instant::now(), Some(sensor))
+    }
+
+    fn record_at(&self, sensor: SensorLabel, value: f32, at: Instant) {
+        if !value.is_finite() {
+            log::debug!(target: TARGET, "ignoring {} of {value} from {sensor}", self.name);
+            return;
+        }
+
+        let mut readings = self.readings.lock().unwrap();
+        match readings.iter_mut().find(|reading| reading.sensor == sensor) {
+            Some(reading) => {
+                reading.value = value;
+                reading.at = at;
+            }
+            None => readings.push(Reading { sensor, value, at }),
+        }
+    }
+
+    fn get_at(&self, now: Instant) -> Option<f32> {
+        self.average_at(now, None)
+    }
+
+    fn average_at(&self, now: Instant, exclude: Option<SensorLabel>) -> Option<f32> {
+        let readings = self.readings.lock().unwrap();
+        let (sum, count) = readings
+            .iter()
+            .filter(|reading| Some(reading.sensor) != exclude)
+            .filter(|reading| now.saturating_duration_since(reading.at) <= self.max_age)
+            .fold((0.0, 0), |(sum, count), reading| {
+                (sum + reading.value, count + 1)
+            });
+        if count == 0 {
+            return None;
+        }
+
+        let value = sum / count as f32;
+        log::trace!(target: TARGET, "{} is {value} (from {count} sensors)", self.name);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHT31: SensorLabel = SensorLabel::onboard("SHT31", "SCD30", 0x61);
+    const BME680: SensorLabel = SensorLabel::new("BME680", 0x77);
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    #[test]
+    fn averages_sensors() {
+        let input = Input::new("temperature", Duration::from_secs(60));
+        assert_eq!(input.get_at(at(0)), None);
+
+        input.record_at(SHT31, 20.0, at(0));
+        input.record_at(BME680, 22.0, at(1));
+        assert_eq!(input.get_at(at(2)), Some(21.0));
+
+        // a new measurement replaces the sensor's previous one.
+        input.record_at(SHT31, 24.0, at(3));
+        assert_eq!(input.get_at(at(4)), Some(23.0));
+    }
+
+    #[test]
+    fn ignores_stale_measurements() {
+        let input = Input::new("pressure", Duration::from_secs(60));
+        input.record_at(BME680, 1013.0, at(0));
+        input.record_at(SHT31, 1000.0, at(50));
+        assert_eq!(input.get_at(at(60)), Some(1006.5));
+        assert_eq!(input.get_at(at(61)), Some(1000.0));
+        assert_eq!(input.get_at(at(111)), None);
+    }
+
+    #[test]
+    fn excludes_sensors() {
+        let input = Input::new("temperature", Duration::from_secs(60));
+        input.record_at(SHT31, 20.0, at(0));
+        input.record_at(BME680, 30.0, at(0));
+        assert_eq!(input.average_at(at(1), Some(BME680)), Some(20.0));
+        assert_eq!(input.average_at(at(1), Some(SHT31)), Some(30.0));
+
+        let input = Input::new("temperature", Duration::from_secs(60));
+        input.record_at(BME680, 30.0, at(0));
+        assert_eq!(input.average_at(at(1), Some(BME680)), None);
+    }
+
+    #[test]
+    fn ignores_non_finite_measurements() {
+        let input = Input::new("absolute humidity", Duration::from_secs(60));
+        input.record_at(SHT31, f32::NAN, at(0));
+        assert_eq!(input.get_at(at(0)), None);
+    }
+}
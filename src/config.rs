@@ -0,0 +1,868 @@
+//! Persistent, versioned device configuration.
+//!
+//! The configuration is stored as a JSON blob in the NVS partition, so that
+//! deployed nodes can be reconfigured over the network without reflashing.
+//! Every stored configuration carries a schema `version`; when a configuration
+//! written by an older firmware is loaded, it is migrated forward to the
+//! current schema before it is deserialized.
+use crate::{
+    board::{Board, BOARD},
+    nvs::{Nvs, Partition},
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The current configuration schema version.
+///
+/// When making a change to the configuration schema that cannot be handled by
+/// `#[serde(default)]` alone (i.e. renaming, moving, or changing the type of a
+/// field), increment this version and add a [`Migration`] to [`MIGRATIONS`].
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Migrations from each previous schema version to the next one.
+///
+/// The migration at index `i` migrates a configuration from schema version
+/// `i + 1` to version `i + 2`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+const _: () = assert!(
+    MIGRATIONS.len() + 1 == CURRENT_VERSION as usize,
+    "every previous config version must have a migration"
+);
+
+/// The maximum number of entries in the reading history (24 hours at 1 minute
+/// resolution).
+const MAX_HISTORY_DEPTH: u16 = 24 * 60;
+
+/// A function that migrates a serialized configuration from one schema version
+/// to the next.
+type Migration = fn(&mut serde_json::Value) -> anyhow::Result<()>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The schema version of this configuration.
+    pub version: u32,
+    pub wifi: WifiConfig,
+    pub i2c: I2cConfig,
+    pub mdns: MdnsConfig,
+    pub history: HistoryConfig,
+    pub mqtt: MqttConfig,
+    /// Per-sensor configuration, keyed by the sensor's name.
+    pub sensors: BTreeMap<String, SensorConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WifiConfig {
+    /// The SSID of the configuration access point.
+    pub ap_ssid: String,
+    /// The WiFi channel used by the configuration access point.
+    pub ap_channel: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct I2cConfig {
+    /// The I2C buses that sensors may be connected to.
+    ///
+    /// Sensors that aren't assigned to a bus (see [`SensorConfig::bus`]) are
+    /// looked for on the first bus.
+    pub buses: Vec<BusConfig>,
+    /// How often to rescan the I2C bus for sensors that haven't been detected
+    /// yet, in seconds.
+    pub scan_interval_secs: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusConfig {
+    /// The bus's name, used to assign sensors to it and to label its metrics.
+    pub name: String,
+    /// Which of the chip's I2C peripherals (`0` for `I2C0`, `1` for `I2C1`)
+    /// drives this bus. The ESP32-C3 only has `I2C0`.
+    pub peripheral: u8,
+    /// The GPIO number of the I2C SDA pin.
+    pub sda: u8,
+    /// The GPIO number of the I2C SCL pin.
+    pub scl: u8,
+    /// The I2C bus baud rate, in kHz.
+    pub baudrate_khz: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MdnsConfig {
+    /// The mDNS hostname (without the `.local` suffix).
+    pub hostname: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// The interval covered by each entry in the reading history, in seconds.
+    pub resolution_secs: u32,
+    /// The number of entries to keep in the reading history.
+    ///
+    /// Each entry uses 4 bytes of memory for every gauge, so be careful when
+    /// increasing this.
+    pub depth: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    /// Whether sensor readings should be published over MQTT.
+    pub enabled: bool,
+    /// The URL of the MQTT broker, e.g. `mqtt://homeassistant.local:1883`.
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Readings are published under `{topic_prefix}/{hostname}/`.
+    pub topic_prefix: String,
+    /// The Home Assistant MQTT discovery prefix.
+    pub discovery_prefix: String,
+    /// How often to publish sensor readings, in seconds.
+    pub publish_interval_secs: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    /// Whether the sensor should be brought up and polled. A disabled sensor
+    /// can be resumed at runtime.
+    pub enabled: bool,
+    /// Overrides the sensor's default poll interval, in seconds.
+    ///
+    /// Not all sensors support changing their poll interval.
+    pub poll_interval_secs: Option<u16>,
+    /// The name of the I2C bus the sensor is connected to, or `None` for the
+    /// first bus.
+    pub bus: Option<String>,
+}
+
+/// Stores a [`Config`] in non-volatile storage.
+pub struct Store {
+    nvs: Nvs,
+}
+
+// === impl Config ===
+
+impl Config {
+    /// Deserializes a configuration from JSON, migrating it to the current
+    /// schema version if necessary.
+    ///
+    /// This does *not* validate the configuration; call [`Config::validate`]
+    /// on the returned configuration before using it.
+    pub fn from_json(json: &[u8]) -> anyhow::Result<Self> {
+        let mut value: serde_json::Value =
+            serde_json::from_slice(json).context("config is not valid JSON")?;
+        migrate(&mut value, MIGRATIONS)?;
+        serde_json::from_value(value).context("failed to deserialize config")
+    }
+
+    /// Returns the configuration for the sensor named `name`, or the default
+    /// sensor configuration if none has been set.
+    pub fn sensor(&self, name: &str) -> SensorConfig {
+        self.sensors.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Returns the I2C bus that the sensor named `name` is connected to.
+    ///
+    /// This returns `None` if the sensor is assigned to a bus that doesn't
+    /// exist, which [`Config::validate`] rejects.
+    pub fn bus_for(&self, name: &str) -> Option<&BusConfig> {
+        self.i2c.buses.get(self.bus_index(name)?)
+    }
+
+    /// Returns the index in `i2c.buses` of the bus that the sensor named
+    /// `name` is connected to (see [`Config::bus_for`]).
+    pub fn bus_index(&self, name: &str) -> Option<usize> {
+        match self
+            .sensors
+            .get(name)
+            .and_then(|sensor| sensor.bus.as_ref())
+        {
+            Some(bus) => self.i2c.buses.iter().position(|b| &b.name == bus),
+            None if self.i2c.buses.is_empty() => None,
+            None => Some(0),
+        }
+    }
+
+    /// Checks that this configuration is valid for the board this firmware
+    /// was built for.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.validate_for(&BOARD)
+    }
+
+    fn validate_for(&self, board: &Board) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.version == CURRENT_VERSION,
+            "config version must be {CURRENT_VERSION} (got {})",
+            self.version
+        );
+
+        let WifiConfig {
+            ref ap_ssid,
+            ap_channel,
+        } = self.wifi;
+        anyhow::ensure!(!ap_ssid.is_empty(), "access point SSID must not be empty");
+        anyhow::ensure!(
+            ap_ssid.len() <= 32,
+            "access point SSID must be at most 32 bytes (got {})",
+            ap_ssid.len()
+        );
+        anyhow::ensure!(
+            (1..=13).contains(&ap_channel),
+            "access point channel must be between 1 and 13 (got {ap_channel})"
+        );
+
+        let I2cConfig {
+            ref buses,
+            scan_interval_secs,
+        } = self.i2c;
+        anyhow::ensure!(!buses.is_empty(), "at least one I2C bus must be configured");
+        for (i, bus) in buses.iter().enumerate() {
+            let BusConfig {
+                ref name,
+                peripheral,
+                sda,
+                scl,
+                baudrate_khz,
+            } = *bus;
+            anyhow::ensure!(!name.is_empty(), "I2C bus names must not be empty");
+            // the ESP32-C3 only has one I2C peripheral, but the ESP32-S3 has
+            // two.
+            let chip = board.chip;
+            anyhow::ensure!(
+                peripheral < chip.i2c_peripherals(),
+                "I2C bus {name} peripheral must be less than {} on the {chip} (got {peripheral})",
+                chip.i2c_peripherals(),
+            );
+            anyhow::ensure!(
+                sda != scl,
+                "I2C bus {name} SDA and SCL must be different pins"
+            );
+            // the pins are handed to the I2C driver (and bit-banged to recover
+            // the bus) without any other checks, so make sure that they exist
+            // and aren't used by anything else.
+            for (line, pin) in [("SDA", sda), ("SCL", scl)] {
+                anyhow::ensure!(
+                    chip.is_usable_gpio(pin),
+                    "I2C bus {name} {line} pin GPIO{pin} can't be used on the {chip}"
+                );
+                anyhow::ensure!(
+                    !board.is_reserved(pin),
+                    "I2C bus {name} {line} pin GPIO{pin} is used by the {}",
+                    board.name
+                );
+            }
+            anyhow::ensure!(
+                (1..=1000).contains(&baudrate_khz),
+                "I2C bus {name} baud rate must be between 1 and 1000 kHz (got {baudrate_khz})"
+            );
+            for other in &buses[..i] {
+                anyhow::ensure!(&other.name != name, "duplicate I2C bus name {name:?}");
+                anyhow::ensure!(
+                    other.peripheral != peripheral,
+                    "I2C buses {} and {name} both use peripheral {peripheral}",
+                    other.name
+                );
+                anyhow::ensure!(
+                    ![other.sda, other.scl]
+                        .iter()
+                        .any(|pin| [sda, scl].contains(pin)),
+                    "I2C buses {} and {name} share pins",
+                    other.name
+                );
+            }
+        }
+        anyhow::ensure!(
+            scan_interval_secs > 0,
+            "I2C scan interval must be greater than 0"
+        );
+
+        let hostname = &self.mdns.hostname;
+        anyhow::ensure!(!hostname.is_empty(), "mDNS hostname must not be empty");
+        anyhow::ensure!(
+            hostname.len() <= 63,
+            "mDNS hostname must be at most 63 bytes (got {})",
+            hostname.len()
+        );
+        anyhow::ensure!(
+            hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-'),
+            "mDNS hostname {hostname:?} may only contain ASCII letters, digits, and '-'"
+        );
+
+        let HistoryConfig {
+            resolution_secs,
+            depth,
+        } = self.history;
+        anyhow::ensure!(
+            resolution_secs >= 10,
+            "history resolution must be at least 10 seconds (got {resolution_secs})"
+        );
+        anyhow::ensure!(
+            (1..=MAX_HISTORY_DEPTH).contains(&depth),
+            "history depth must be between 1 and {MAX_HISTORY_DEPTH} (got {depth})"
+        );
+
+        let mqtt = &self.mqtt;
+        if mqtt.enabled {
+            anyhow::ensure!(
+                mqtt.url.starts_with("mqtt://") || mqtt.url.starts_with("mqtts://"),
+                "MQTT broker URL {:?} must start with mqtt:// or mqtts://",
+                mqtt.url
+            );
+            for (name, prefix) in [
+                ("topic prefix", &mqtt.topic_prefix),
+                ("discovery prefix", &mqtt.discovery_prefix),
+            ] {
+                anyhow::ensure!(!prefix.is_empty(), "MQTT {name} must not be empty");
+                anyhow::ensure!(
+                    !prefix.contains(['#', '+']) && !prefix.ends_with('/'),
+                    "MQTT {name} {prefix:?} may not contain wildcards or a trailing '/'"
+                );
+            }
+            anyhow::ensure!(
+                mqtt.publish_interval_secs > 0,
+                "MQTT publish interval must be greater than 0"
+            );
+        }
+
+        for (name, sensor) in &self.sensors {
+            if let Some(secs) = sensor.poll_interval_secs {
+                anyhow::ensure!(secs > 0, "{name} poll interval must be greater than 0");
+            }
+            if let Some(ref bus) = sensor.bus {
+                anyhow::ensure!(
+                    buses.iter().any(|b| &b.name == bus),
+                    "{name} is assigned to I2C bus {bus:?}, which doesn't exist"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            wifi: WifiConfig::default(),
+            i2c: I2cConfig::default(),
+            mdns: MdnsConfig::default(),
+            history: HistoryConfig::default(),
+            mqtt: MqttConfig::default(),
+            sensors: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self {
+            ap_ssid: "eclss".to_string(),
+            ap_channel: 1,
+        }
+    }
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self {
+            buses: vec![BusConfig::default()],
+            scan_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            name: "i2c0".to_string(),
+            peripheral: 0,
+            // the board's I2C connector pins.
+            sda: BOARD.i2c_sda,
+            scl: BOARD.i2c_scl,
+            // Maximal I2C speed is 100 kHz and the master has to support clock
+            // stretching. Sensirion recommends to operate the SCD30
+            // at a baud rate of 50 kHz or smaller.
+            baudrate_khz: 50,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        // 24 hours at 5 minute resolution
+        Self {
+            resolution_secs: 5 * 60,
+            depth: 24 * 12,
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            username: None,
+            password: None,
+            topic_prefix: "eclss".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            publish_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_secs: None,
+            bus: None,
+        }
+    }
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self {
+            hostname: "eclss".to_string(),
+        }
+    }
+}
+
+fn migrate(value: &mut serde_json::Value, migrations: &[Migration]) -> anyhow::Result<()> {
+    let current = migrations.len() as u64 + 1;
+    let version = value
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .context("config does not have a version")?;
+    anyhow::ensure!(version > 0, "config version 0 is invalid");
+    anyhow::ensure!(
+        version <= current,
+        "config version {version} is newer than the current version ({current})"
+    );
+
+    for (from, migration) in (version..).zip(&migrations[(version - 1) as usize..]) {
+        log::info!(target: "eclss::config", "migrating config from version {from} to {}", from + 1);
+        migration(value)
+            .with_context(|| format!("failed to migrate config from version {from}"))?;
+        value["version"] = (from + 1).into();
+    }
+
+    Ok(())
+}
+
+/// Version 2 moved the I2C pins and baud rate into a list of buses, so that
+/// sensors can be spread across more than one bus.
+fn v1_to_v2(value: &mut serde_json::Value) -> anyhow::Result<()> {
+    let Some(i2c) = value
+        .get_mut("i2c")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        // the I2C config was defaulted, so it'll be defaulted again.
+        return Ok(());
+    };
+    let mut bus = serde_json::to_value(BusConfig::default())?;
+    for key in ["sda", "scl", "baudrate_khz"] {
+        if let Some(old) = i2c.remove(key) {
+            bus[key] = old;
+        }
+    }
+    i2c.insert("buses".to_string(), serde_json::Value::Array(vec![bus]));
+    Ok(())
+}
+
+// === impl Store ===
+
+impl Store {
+    const NAMESPACE: &'static str = "eclss";
+    const KEY: &'static str = "config";
+    const MAX_LEN: usize = 2048;
+
+    pub fn new(partition: Partition) -> anyhow::Result<Self> {
+        let nvs = Nvs::new(partition, Self::NAMESPACE, true)
+            .context("failed to open config NVS namespace")?;
+        Ok(Self { nvs })
+    }
+
+    /// Loads the stored configuration, if one exists.
+    ///
+    /// If the stored configuration was written with an older schema version,
+    /// the migrated configuration is written back to NVS.
+    pub fn load(&mut self) -> anyhow::Result<Option<Config>> {
+        let mut buf = vec![0; Self::MAX_LEN];
+        let Some(json) = self
+            .nvs
+            .get_raw(Self::KEY, &mut buf)
+            .context("failed to read config from NVS")?
+        else {
+            return Ok(None);
+        };
+
+        let needs_save = serde_json::from_slice::<serde_json::Value>(json)
+            .ok()
+            .and_then(|value| value.get("version")?.as_u64())
+            != Some(CURRENT_VERSION as u64);
+        let config = Config::from_json(json)?;
+        config.validate().context("stored config is invalid")?;
+
+        if needs_save {
+            self.save(&config)
+                .context("failed to save migrated config")?;
+        }
+
+        Ok(Some(config))
+    }
+
+    /// Loads the stored configuration, falling back to the default
+    /// configuration if none is stored or the stored config is invalid.
+    pub fn load_or_default(&mut self) -> Config {
+        match self.load() {
+            Ok(Some(config)) => {
+                log::info!(target: "eclss::config", "loaded config: {config:?}");
+                config
+            }
+            Ok(None) => {
+                log::info!(target: "eclss::config", "no config saved; using defaults (is this a fresh board?)");
+                Config::default()
+            }
+            Err(error) => {
+                log::warn!(target: "eclss::config", "failed to load config: {error:?}; using defaults");
+                Config::default()
+            }
+        }
+    }
+
+    /// Validates and saves `config` to NVS.
+    ///
+    /// Most configuration changes take effect after the next reboot.
+    pub fn save(&mut self, config: &Config) -> anyhow::Result<()> {
+        config.validate()?;
+        let json = serde_json::to_vec(config).context("failed to serialize config")?;
+        anyhow::ensure!(
+            json.len() <= Self::MAX_LEN,
+            "serialized config is too long ({} bytes, max {})",
+            json.len(),
+            Self::MAX_LEN
+        );
+        self.nvs
+            .set_raw(Self::KEY, &json)
+            .context("failed to write config to NVS")?;
+        log::info!(target: "eclss::config", "saved config: {config:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board;
+
+    #[test]
+    fn default_is_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut config = Config::default();
+        config.mdns.hostname = "eclss-bedroom".to_string();
+        config.sensors.insert(
+            "SCD30".to_string(),
+            SensorConfig {
+                enabled: false,
+                poll_interval_secs: Some(5),
+                bus: Some("i2c0".to_string()),
+            },
+        );
+        let json = serde_json::to_vec(&config).unwrap();
+        assert_eq!(Config::from_json(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn missing_fields_are_defaulted() {
+        let config =
+            Config::from_json(br#"{"version": 2, "i2c": {"scan_interval_secs": 5}}"#).unwrap();
+        assert_eq!(config.i2c.scan_interval_secs, 5);
+        assert_eq!(config.i2c.buses, I2cConfig::default().buses);
+        assert_eq!(config.wifi, WifiConfig::default());
+        assert_eq!(config.mdns, MdnsConfig::default());
+        config.validate().unwrap();
+
+        // sensors are enabled unless they're explicitly disabled
+        let config = Config::from_json(
+            br#"{"version": 1, "sensors": {"SCD30": {"poll_interval_secs": 5}}}"#,
+        )
+        .unwrap();
+        assert!(config.sensor("SCD30").enabled);
+        assert!(config.sensor("BME680").enabled);
+    }
+
+    #[test]
+    fn load_rejects_missing_version() {
+        assert!(Config::from_json(br#"{"i2c": {"baudrate_khz": 100}}"#).is_err());
+    }
+
+    #[test]
+    fn load_rejects_newer_version() {
+        let json = format!(r#"{{"version": {}}}"#, CURRENT_VERSION + 1);
+        assert!(Config::from_json(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn v1_i2c_pins_are_migrated_to_a_bus() {
+        let config = Config::from_json(
+            br#"{"version": 1, "i2c": {"sda": 8, "scl": 9, "baudrate_khz": 100, "scan_interval_secs": 10}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.i2c.scan_interval_secs, 10);
+        assert_eq!(
+            config.i2c.buses,
+            vec![BusConfig {
+                sda: 8,
+                scl: 9,
+                baudrate_khz: 100,
+                ..Default::default()
+            }]
+        );
+        config.validate().unwrap();
+
+        // fields that weren't set are defaulted
+        let config = Config::from_json(br#"{"version": 1, "i2c": {"baudrate_khz": 100}}"#).unwrap();
+        assert_eq!(config.i2c.buses[0].baudrate_khz, 100);
+        assert_eq!(config.i2c.buses[0].sda, BusConfig::default().sda);
+
+        let config = Config::from_json(br#"{"version": 1}"#).unwrap();
+        assert_eq!(config.i2c, I2cConfig::default());
+    }
+
+    #[test]
+    fn sensors_are_assigned_to_buses() {
+        let mut config = Config::default();
+        config.i2c.buses.push(BusConfig {
+            name: "fast".to_string(),
+            peripheral: 1,
+            sda: 8,
+            scl: 9,
+            baudrate_khz: 400,
+        });
+        config.sensors.insert(
+            "BME680".to_string(),
+            SensorConfig {
+                bus: Some("fast".to_string()),
+                ..Default::default()
+            },
+        );
+        config.validate_for(&board::S3_FEATHER).unwrap();
+        assert_eq!(config.bus_for("BME680").unwrap().name, "fast");
+        assert_eq!(config.bus_index("BME680"), Some(1));
+        // unassigned sensors are on the first bus
+        assert_eq!(config.bus_for("SCD30").unwrap().name, "i2c0");
+
+        config.sensors.get_mut("BME680").unwrap().bus = Some("slow".to_string());
+        assert!(config.validate_for(&board::S3_FEATHER).is_err());
+        assert_eq!(config.bus_for("BME680"), None);
+    }
+
+    #[test]
+    fn migrate_applies_migrations_in_order() {
+        fn v1_to_v2(value: &mut serde_json::Value) -> anyhow::Result<()> {
+            value["a"] = 2.into();
+            Ok(())
+        }
+        fn v2_to_v3(value: &mut serde_json::Value) -> anyhow::Result<()> {
+            let a = value["a"].as_u64().unwrap();
+            value["b"] = (a + 1).into();
+            Ok(())
+        }
+        const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3];
+
+        let mut v1 = serde_json::json!({ "version": 1 });
+        migrate(&mut v1, MIGRATIONS).unwrap();
+        assert_eq!(v1, serde_json::json!({ "version": 3, "a": 2, "b": 3 }));
+
+        // a config that's already partially migrated only gets the remaining
+        // migrations
+        let mut v2 = serde_json::json!({ "version": 2, "a": 5 });
+        migrate(&mut v2, MIGRATIONS).unwrap();
+        assert_eq!(v2, serde_json::json!({ "version": 3, "a": 5, "b": 6 }));
+
+        let mut v3 = serde_json::json!({ "version": 3 });
+        migrate(&mut v3, MIGRATIONS).unwrap();
+        assert_eq!(v3, serde_json::json!({ "version": 3 }));
+    }
+
+    #[test]
+    fn migrate_propagates_errors() {
+        fn broken(_: &mut serde_json::Value) -> anyhow::Result<()> {
+            anyhow::bail!("oh no")
+        }
+        let mut v1 = serde_json::json!({ "version": 1 });
+        assert!(migrate(&mut v1, &[broken]).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_bad_wifi() {
+        let mut config = Config::default();
+        config.wifi.ap_ssid = String::new();
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.wifi.ap_ssid = "a".repeat(33);
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.wifi.ap_channel = 14;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_bad_i2c() {
+        let mut config = Config::default();
+        config.i2c.buses[0].scl = config.i2c.buses[0].sda;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.i2c.buses[0].baudrate_khz = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.i2c.buses[0].peripheral = 2;
+        assert!(config.validate_for(&board::S3_FEATHER).is_err());
+
+        // pins must exist, and can't be used by the flash, USB, or the board
+        for pin in [
+            12, // SPI flash
+            18, // USB
+            22, // doesn't exist
+            2,  // the QT Py's NeoPixel
+        ] {
+            let mut config = Config::default();
+            config.i2c.buses[0].sda = pin;
+            assert!(config.validate_for(&board::QT_PY_C3).is_err());
+        }
+        let mut config = Config::default();
+        config.i2c.buses[0].scl = 21;
+        // NEOPIXEL_POWER on the Feather, but a normal GPIO on the QT Py
+        assert!(config.validate_for(&board::S3_FEATHER).is_err());
+        config.validate_for(&board::QT_PY_C3).unwrap();
+
+        // the ESP32-C3 only has I2C0
+        let mut config = Config::default();
+        config.i2c.buses[0].peripheral = 1;
+        assert!(config.validate_for(&board::QT_PY_C3).is_err());
+        config.validate_for(&board::S3_FEATHER).unwrap();
+
+        let mut config = Config::default();
+        config.i2c.buses.clear();
+        assert!(config.validate().is_err());
+
+        // two buses can't share a name, a peripheral, or pins
+        let second = BusConfig {
+            name: "i2c1".to_string(),
+            peripheral: 1,
+            sda: 8,
+            scl: 9,
+            baudrate_khz: 400,
+        };
+        let mut config = Config::default();
+        config.i2c.buses.push(second.clone());
+        config.validate_for(&board::S3_FEATHER).unwrap();
+        assert!(config.validate_for(&board::QT_PY_C3).is_err());
+        for bad in [
+            BusConfig {
+                name: "i2c0".to_string(),
+                ..second.clone()
+            },
+            BusConfig {
+                peripheral: 0,
+                ..second.clone()
+            },
+            BusConfig {
+                sda: BusConfig::default().sda,
+                ..second.clone()
+            },
+        ] {
+            let mut config = Config::default();
+            config.i2c.buses.push(bad);
+            assert!(config.validate_for(&board::S3_FEATHER).is_err());
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_hostname() {
+        let mut config = Config::default();
+        config.mdns.hostname = "eclss.local".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_bad_history() {
+        let mut config = Config::default();
+        config.history.depth = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.history.depth = MAX_HISTORY_DEPTH + 1;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.history.resolution_secs = 1;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_checks_mqtt_only_if_enabled() {
+        let mut config = Config::default();
+        config.mqtt.url = "not a url".to_string();
+        config.validate().unwrap();
+
+        config.mqtt.enabled = true;
+        assert!(config.validate().is_err());
+
+        config.mqtt.url = "mqtt://broker.local:1883".to_string();
+        config.validate().unwrap();
+
+        config.mqtt.topic_prefix = "eclss/#".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "espidf"))]
+    fn store_roundtrip() {
+        let partition = Partition::new();
+        let mut store = Store::new(partition.clone()).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+
+        let mut config = Config::default();
+        config.mdns.hostname = "eclss-kitchen".to_string();
+        store.save(&config).unwrap();
+
+        // a new store on the same partition sees the saved config
+        let mut store = Store::new(partition).unwrap();
+        assert_eq!(store.load().unwrap(), Some(config));
+    }
+
+    #[test]
+    fn validate_rejects_zero_poll_interval() {
+        let mut config = Config::default();
+        config.sensors.insert(
+            "BME680".to_string(),
+            SensorConfig {
+                poll_interval_secs: Some(0),
+                ..Default::default()
+            },
+        );
+        assert!(config.validate().is_err());
+    }
+}
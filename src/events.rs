@@ -0,0 +1,190 @@
+//! Broadcasts sensor events to any number of subscribers.
+//!
+//! Sensor managers publish an [`Event`] every time a sensor is successfully
+//! polled and every time a sensor's [`Status`] changes. Subscribers (such as
+//! the `/sensors/stream` HTTP endpoint) receive every event published after
+//! they subscribed. Events are kept in a small ring buffer, so a subscriber
+//! that falls too far behind skips the events it missed, rather than
+//! applying backpressure to the sensors.
+use crate::{metrics::SensorLabel, sensor::Status};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::Mutex,
+    task::{Poll, Waker},
+};
+
+/// Events published by the sensor managers.
+pub static EVENTS: Events = Events::new();
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A sensor was polled successfully, and its readings were updated.
+    Reading { sensor: SensorLabel },
+    /// A sensor's status changed.
+    Status { sensor: SensorLabel, status: Status },
+}
+
+pub struct Events {
+    inner: Mutex<Inner>,
+}
+
+/// A subscription to an [`Events`] broadcast.
+pub struct Subscription<'events> {
+    events: &'events Events,
+    /// The sequence number of the next event this subscriber will receive.
+    next_seq: u64,
+}
+
+struct Inner {
+    /// The sequence number of the oldest event in `buf`.
+    head_seq: u64,
+    buf: VecDeque<Event>,
+    wakers: Vec<Waker>,
+}
+
+/// The number of events buffered for subscribers that have fallen behind.
+const CAPACITY: usize = 16;
+
+// === impl Events ===
+
+impl Events {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                head_seq: 0,
+                buf: VecDeque::new(),
+                wakers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let wakers = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.buf.len() == CAPACITY {
+                inner.buf.pop_front();
+                inner.head_seq += 1;
+            }
+            inner.buf.push_back(event);
+            std::mem::take(&mut inner.wakers)
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Subscribes to events published after this call.
+    pub fn subscribe(&self) -> Subscription<'_> {
+        let inner = self.inner.lock().unwrap();
+        Subscription {
+            events: self,
+            next_seq: inner.head_seq + inner.buf.len() as u64,
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// === impl Subscription ===
+
+impl Subscription<'_> {
+    /// Waits for the next event.
+    ///
+    /// If this subscriber has fallen so far behind that events it hasn't seen
+    /// yet have been dropped from the buffer, it skips ahead to the oldest
+    /// event that's still buffered.
+    pub fn next(&mut self) -> impl Future<Output = Event> + '_ {
+        futures::future::poll_fn(|cx| {
+            let mut inner = self.events.inner.lock().unwrap();
+            if self.next_seq < inner.head_seq {
+                log::debug!(
+                    target: "eclss::events",
+                    "subscriber lagged by {} events",
+                    inner.head_seq - self.next_seq
+                );
+                self.next_seq = inner.head_seq;
+            }
+
+            let idx = (self.next_seq - inner.head_seq) as usize;
+            if let Some(&event) = inner.buf.get(idx) {
+                self.next_seq += 1;
+                return Poll::Ready(event);
+            }
+
+            if !inner.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                inner.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    const SCD30: SensorLabel = SensorLabel::new("SCD30", 0x61);
+
+    fn reading(address: u8) -> Event {
+        Event::Reading {
+            sensor: SensorLabel::new("BME680", address),
+        }
+    }
+
+    #[test]
+    fn subscribers_only_see_new_events() {
+        let events = Events::new();
+        events.publish(reading(0x76));
+
+        let mut sub = events.subscribe();
+        assert_eq!(sub.next().now_or_never(), None);
+
+        let status = Event::Status {
+            sensor: SCD30,
+            status: Status::Up,
+        };
+        events.publish(status);
+        events.publish(reading(0x77));
+        assert_eq!(sub.next().now_or_never(), Some(status));
+        assert_eq!(sub.next().now_or_never(), Some(reading(0x77)));
+        assert_eq!(sub.next().now_or_never(), None);
+    }
+
+    #[test]
+    fn lagging_subscribers_skip_ahead() {
+        let events = Events::new();
+        let mut sub = events.subscribe();
+        for addr in 0..(CAPACITY as u8 + 4) {
+            events.publish(reading(addr));
+        }
+
+        // the first 4 events were dropped from the buffer.
+        assert_eq!(sub.next().now_or_never(), Some(reading(4)));
+    }
+
+    #[test]
+    fn serializes_events() {
+        let json = serde_json::to_value(Event::Status {
+            sensor: SCD30,
+            status: Status::BusError,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "event": "status",
+                "sensor": "SCD30@0x61",
+                "status": "BusError",
+            })
+        );
+    }
+}
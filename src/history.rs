@@ -0,0 +1,296 @@
+//! On-device history of sensor readings.
+//!
+//! The [`History`] keeps a fixed-size ring buffer of timestamped samples for
+//! every gauge in [`SensorMetrics`], so that recent readings are available
+//! even if nothing was scraping `/metrics` at the time. Gauges are sampled
+//! frequently and the samples are averaged into buckets of the configured
+//! resolution, so the history covers `resolution * depth` of wall-clock time
+//! using a fixed amount of memory.
+use crate::{config::HistoryConfig, metrics::Gauge, SensorMetrics};
+use embassy_time::Timer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+pub struct History {
+    resolution: Duration,
+    depth: usize,
+    timestamps: Ring<u64>,
+    series: Vec<Series>,
+}
+
+/// Query parameters for `GET /sensors/history.json`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Query {
+    /// Only return samples taken at or after this UNIX timestamp (in
+    /// seconds).
+    pub since: Option<u64>,
+    /// The interval between returned samples, in seconds.
+    ///
+    /// If this is greater than the history's resolution, samples are averaged
+    /// together.
+    pub step: Option<u64>,
+}
+
+/// A snapshot of the history, returned by [`History::query`].
+#[derive(Debug, Serialize)]
+pub struct Snapshot<'history> {
+    pub step_secs: u64,
+    /// The UNIX timestamp (in seconds) at the start of each sample.
+    pub timestamps: Vec<u64>,
+    /// Samples for each gauge, keyed by metric name and label. A sample is
+    /// `null` if no readings were recorded in that interval.
+    pub series: BTreeMap<&'static str, BTreeMap<&'history str, Vec<Option<f32>>>>,
+}
+
+struct Series {
+    metric: &'static str,
+    label: String,
+    gauge: &'static Gauge,
+    values: Ring<f32>,
+    sum: f64,
+    count: u32,
+}
+
+/// A fixed-capacity ring buffer.
+#[derive(Debug)]
+struct Ring<T> {
+    buf: Box<[T]>,
+    /// The index of the next element to write.
+    head: usize,
+    len: usize,
+}
+
+/// How often gauges are sampled. Samples are averaged into buckets of the
+/// configured resolution.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+const TARGET: &str = "eclss::history";
+
+// === impl History ===
+
+impl History {
+    pub fn new(config: &HistoryConfig) -> Self {
+        let depth = config.depth as usize;
+        Self {
+            resolution: Duration::from_secs(config.resolution_secs.into()),
+            depth,
+            timestamps: Ring::new(depth, 0),
+            series: Vec::new(),
+        }
+    }
+
+    /// Samples the current value of every gauge in `metrics`.
+    ///
+    /// Gauges that have been registered since the last sample are added to
+    /// the history.
+    fn sample(&mut self, metrics: &'static SensorMetrics) {
+        metrics.for_each_gauge(|metric, label, gauge| {
+            let series = match self
+                .series
+                .iter_mut()
+                .find(|series| std::ptr::eq(series.gauge, gauge))
+            {
+                Some(series) => series,
+                None => {
+                    log::debug!(target: TARGET, "recording history for {metric}{{{label}}}");
+                    // pad the new series so that its samples line up with the
+                    // existing timestamps.
+                    let mut values = Ring::new(self.depth, f32::NAN);
+                    for _ in 0..self.timestamps.len() {
+                        values.push(f32::NAN);
+                    }
+                    self.series.push(Series {
+                        metric,
+                        label: label.to_string(),
+                        gauge,
+                        values,
+                        sum: 0.0,
+                        count: 0,
+                    });
+                    self.series.last_mut().unwrap()
+                }
+            };
+
+            let value = gauge.value();
+            if value.is_finite() {
+                series.sum += value;
+                series.count += 1;
+            }
+        });
+    }
+
+    /// Records the average of the samples taken since the last call to
+    /// `record` as a new entry in the history, starting at `timestamp`.
+    fn record(&mut self, timestamp: u64) {
+        self.timestamps.push(timestamp);
+        for series in &mut self.series {
+            let value = if series.count > 0 {
+                (series.sum / series.count as f64) as f32
+            } else {
+                f32::NAN
+            };
+            series.values.push(value);
+            series.sum = 0.0;
+            series.count = 0;
+        }
+    }
+
+    pub fn query(&self, query: &Query) -> Snapshot<'_> {
+        let resolution = self.resolution.as_secs();
+        let group = query
+            .step
+            .map(|step| (step / resolution).max(1) as usize)
+            .unwrap_or(1);
+        let since = query.since.unwrap_or(0);
+        // the index of the first sample at or after `since`.
+        let start = self
+            .timestamps
+            .iter()
+            .position(|&ts| ts >= since)
+            .unwrap_or(self.timestamps.len());
+
+        let timestamps = self
+            .timestamps
+            .iter()
+            .skip(start)
+            .step_by(group)
+            .copied()
+            .collect();
+        let mut series = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for s in &self.series {
+            let values = s.values.iter().skip(start).copied().collect::<Vec<_>>();
+            let values = downsample(&values, group);
+            series
+                .entry(s.metric)
+                .or_default()
+                .insert(s.label.as_str(), values);
+        }
+
+        Snapshot {
+            step_secs: resolution * group as u64,
+            timestamps,
+            series,
+        }
+    }
+
+    /// Periodically samples `metrics` and records them in `history`.
+    pub async fn run(
+        history: Arc<Mutex<Self>>,
+        metrics: &'static SensorMetrics,
+    ) -> anyhow::Result<()> {
+        let resolution = history.lock().unwrap().resolution;
+        let sample_interval = SAMPLE_INTERVAL.min(resolution);
+        let mut bucket_start = unix_timestamp();
+        loop {
+            Timer::after(embassy_time::Duration::from_secs(sample_interval.as_secs())).await;
+
+            let mut history = history.lock().unwrap();
+            history.sample(metrics);
+
+            let now = unix_timestamp();
+            if now.saturating_sub(bucket_start) >= resolution.as_secs() {
+                history.record(bucket_start);
+                bucket_start = now;
+            }
+        }
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        // if the clock hasn't been set by SNTP yet, it's probably 1970...
+        .unwrap_or(0)
+}
+
+/// Averages each group of `group` consecutive samples, ignoring missing
+/// samples. The last group may be partial.
+fn downsample(values: &[f32], group: usize) -> Vec<Option<f32>> {
+    values
+        .chunks(group)
+        .map(|chunk| {
+            let (sum, count) = chunk
+                .iter()
+                .filter(|value| value.is_finite())
+                .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+            (count > 0).then(|| sum / count as f32)
+        })
+        .collect()
+}
+
+// === impl Ring ===
+
+impl<T: Copy> Ring<T> {
+    fn new(capacity: usize, fill: T) -> Self {
+        Self {
+            buf: vec![fill; capacity].into_boxed_slice(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, value: T) {
+        if self.buf.is_empty() {
+            return;
+        }
+
+        self.buf[self.head] = value;
+        self.head = (self.head + 1) % self.buf.len();
+        self.len = (self.len + 1).min(self.buf.len());
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Iterates over the elements in the ring, oldest first.
+    fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let start = (self.head + self.buf.len() - self.len) % self.buf.len().max(1);
+        self.buf[start..]
+            .iter()
+            .chain(self.buf[..start].iter())
+            .take(self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_around() {
+        let mut ring = Ring::new(3, 0);
+        ring.push(1);
+        ring.push(2);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), vec![1, 2]);
+        ring.push(3);
+        ring.push(4);
+        ring.push(5);
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn downsample_averages_groups() {
+        let values = [1.0, 3.0, 5.0, 7.0, 9.0];
+        assert_eq!(
+            downsample(&values, 2),
+            vec![Some(2.0), Some(6.0), Some(9.0)]
+        );
+        assert_eq!(
+            downsample(&values, 1),
+            values.into_iter().map(Some).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn downsample_skips_missing() {
+        let values = [f32::NAN, f32::NAN, f32::NAN, 4.0, f32::NAN];
+        assert_eq!(downsample(&values, 2), vec![None, Some(4.0), None]);
+    }
+}
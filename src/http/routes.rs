@@ -0,0 +1,979 @@
+#[cfg(feature = "sensor-sgp30")]
+use crate::sgp30;
+use crate::{
+    actor, auth, config,
+    events::{self, Event},
+    history,
+    metrics::SensorLabel,
+    net, ota, scd30, sensor, SensorMetrics,
+};
+use anyhow::Context;
+use embassy_time::{Duration, Timer};
+use futures::{select, FutureExt};
+use serde::Serialize;
+use std::{
+    fmt,
+    net::{Ipv4Addr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use thingbuf::mpsc;
+
+use super::server::{self, Method, Request, Response};
+
+pub struct Server {
+    listener: TcpListener,
+    service: Service,
+}
+
+/// The state shared by HTTP request handlers.
+struct Service {
+    metrics: &'static SensorMetrics,
+    scd30_ctrl: actor::Client<scd30::ControlMessage, anyhow::Result<()>>,
+    config_store: Arc<Mutex<config::Store>>,
+    history: Arc<Mutex<history::History>>,
+    admin: Arc<auth::Admin>,
+    access_points: net::AccessPoints,
+    creds_tx: mpsc::Sender<net::Credentials>,
+    #[cfg(feature = "sensor-sgp30")]
+    sgp30_ctrl: Option<actor::Client<sgp30::ControlMessage, anyhow::Result<()>>>,
+}
+
+/// Holds one of the limited number of `/sensors/stream` slots.
+struct StreamSlot(());
+
+pub const HTTP_PORT: u16 = 80;
+pub const HTTPS_PORT: u16 = 443;
+
+/// The maximum number of concurrently open `/sensors/stream` responses.
+///
+/// Each open stream occupies one of the server's connection slots for as long
+/// as the client stays connected, so this must be less than the total number
+/// of connections the server will handle.
+const MAX_STREAMS: usize = 2;
+
+/// How often to send a comment on idle event streams, so that clients which
+/// have gone away are noticed.
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(15);
+
+static OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn start_server(
+    wifi: &net::EclssWifi,
+    metrics: &'static SensorMetrics,
+    scd30_ctrl: actor::Client<scd30::ControlMessage, anyhow::Result<()>>,
+    config_store: Arc<Mutex<config::Store>>,
+    history: Arc<Mutex<history::History>>,
+    admin: Arc<auth::Admin>,
+) -> anyhow::Result<Server> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, HTTP_PORT))
+        .context("failed to start HTTP server")?;
+    let service = Service {
+        metrics,
+        scd30_ctrl,
+        config_store,
+        history,
+        admin,
+        access_points: wifi.access_points.clone(),
+        creds_tx: wifi.credentials_tx(),
+        #[cfg(feature = "sensor-sgp30")]
+        sgp30_ctrl: None,
+    };
+
+    log::info!("Server is running on http://192.168.71.1/");
+
+    Ok(Server { listener, service })
+}
+
+// === impl Server ===
+
+impl Server {
+    /// Enables the SGP30 control routes.
+    #[cfg(feature = "sensor-sgp30")]
+    pub fn with_sgp30(
+        mut self,
+        sgp30_ctrl: actor::Client<sgp30::ControlMessage, anyhow::Result<()>>,
+    ) -> Self {
+        self.service.sgp30_ctrl = Some(sgp30_ctrl);
+        self
+    }
+
+    /// Serves HTTP requests until the end of time.
+    ///
+    /// This must be spawned on the executor for the server to handle any
+    /// requests.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let service = &self.service;
+        server::serve(self.listener, |req| service.handle(req)).await
+    }
+}
+
+// === impl Service ===
+
+impl Service {
+    async fn handle(&self, req: Request) -> Response {
+        match (req.method, req.path()) {
+            (Method::Get, "/") => {
+                static INDEX: &[u8] = include_bytes!("index.html");
+                Response::new(200, "OK").with_body(content_type::HTML, INDEX)
+            }
+            // TODO(eliza): also serve this on the normal prometheus metrics port?
+            (Method::Get, "/metrics") => {
+                log::debug!("handling GET /metrics request...");
+                let metrics = self.metrics.to_string();
+                log::debug!("metrics scrape OK!");
+                Response::new(200, "OK")
+                    .with_body("text/plain; version=0.0.4", metrics.into_bytes())
+            }
+            (Method::Get, "/sensors.json") => serve_json(self.metrics),
+            (Method::Get, "/sensors/status.json") => serve_json(&sensor::STATUSES),
+            (Method::Get, "/sensors/stream") => self.stream(),
+            #[cfg(feature = "sensor-sgp30")]
+            (Method::Get, "/sensors/sgp30/baseline.json") => {
+                // refresh the current baseline, if the sensor is up.
+                if let Err(error) = self.sgp30_request(sgp30::ControlMessage::GetBaseline).await {
+                    log::debug!("failed to read SGP30 baseline: {error:#}");
+                }
+                serve_json(&*sgp30::BASELINE.lock().unwrap())
+            }
+            (Method::Post, path)
+                if path.starts_with("/sensors/") && path.ends_with("/poll_interval") =>
+            {
+                self.set_poll_interval(req).await
+            }
+            (Method::Post, path)
+                if path.starts_with("/sensors/")
+                    && (path.ends_with("/pause")
+                        || path.ends_with("/resume")
+                        || path.ends_with("/restart")) =>
+            {
+                self.command_sensor(req).await
+            }
+            (Method::Post, path) if path.starts_with("/sensors/") && path.ends_with("/control") => {
+                self.control(req).await
+            }
+            #[cfg(feature = "sensor-sgp30")]
+            (Method::Post, path) if path.starts_with("/sensors/sgp30/") => {
+                self.control_sgp30(req).await
+            }
+            (Method::Get, "/sensors/history.json") => {
+                let query = match req.query().map(serde_urlencoded::from_str).transpose() {
+                    Ok(query) => query.unwrap_or_default(),
+                    Err(error) => return bad_request(error),
+                };
+                let history = self.history.lock().unwrap();
+                serve_json(&history.query(&query))
+            }
+            (Method::Post, "/sensors/co2/calibrate") => self.calibrate(req).await,
+            (Method::Get, "/sensors/scd30/config") => {
+                // refresh the current settings, if the sensor is up.
+                if let Err(error) = self.scd30_request(scd30::ControlMessage::GetSettings).await {
+                    log::debug!("failed to read SCD30 settings: {error:#}");
+                }
+                serve_scd30_settings()
+            }
+            (Method::Post, "/sensors/scd30/config") => self.configure_scd30(req).await,
+            (Method::Get, "/wifi/ssids.json") => {
+                let ssids = self.access_points.read().unwrap();
+                let ssids = ssids.iter().map(|ap| &ap.ssid).collect::<Vec<_>>();
+                serve_json(&ssids)
+            }
+            (Method::Post, "/wifi/select") => self.select_wifi(req).await,
+            (Method::Get, "/config.json") => {
+                // the config contains secrets (such as the MQTT password), so
+                // reading it also requires authentication.
+                if let Err(message) = self.check_auth(&req) {
+                    return unauthorized(message);
+                }
+
+                let config = self.config_store.lock().unwrap().load_or_default();
+                serve_json(&config)
+            }
+            (Method::Post, "/config.json") => self.save_config(req).await,
+            (Method::Post, "/admin/password") => self.set_admin_password(req).await,
+            (Method::Post, "/ota") => self.update_firmware(req).await,
+            (_, path) => json_rsp(JsonResponse {
+                code: 404,
+                status: "Not Found",
+                message: format!("no route for {:?} {path}", req.method),
+            }),
+        }
+    }
+
+    /// Streams sensor [`Event`]s as Server-Sent Events.
+    ///
+    /// The stream starts with a `snapshot` event containing the current
+    /// readings and statuses of every sensor. After that, a `reading` event
+    /// with the current readings is sent every time a sensor is polled, and a
+    /// `status` event is sent every time a sensor's status changes.
+    fn stream(&self) -> Response {
+        let Some(slot) = StreamSlot::acquire() else {
+            return json_rsp(JsonResponse {
+                code: 503,
+                status: "Service Unavailable",
+                message: "too many open sensor streams",
+            });
+        };
+
+        let metrics = self.metrics;
+        let snapshot = serde_json::json!({
+            "sensors": metrics,
+            "statuses": &sensor::STATUSES,
+        });
+        let first = sse_event("snapshot", &snapshot);
+        let events = futures::stream::unfold(
+            (events::EVENTS.subscribe(), slot),
+            move |(mut subscription, slot)| async move {
+                let chunk = select! {
+                    event = subscription.next().fuse() => stream_event(event, metrics),
+                    _ = Timer::after(STREAM_KEEPALIVE).fuse() => b": keepalive\n\n".to_vec(),
+                };
+                Some((chunk, (subscription, slot)))
+            },
+        );
+
+        Response::new(200, "OK")
+            .with_header(header::CACHE_CONTROL, "no-cache")
+            .with_stream(
+                content_type::EVENT_STREAM,
+                futures::stream::iter(first).chain(events),
+            )
+    }
+
+    async fn calibrate(&self, mut req: Request) -> Response {
+        #[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+        struct Calibrate {
+            ppm: u16,
+        }
+
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let ppm = match serde_urlencoded::from_bytes(&body) {
+            Ok(Calibrate { ppm }) => ppm,
+            Err(error) => return bad_request(error),
+        };
+
+        log::info!("received request to calibrate CO2 at {ppm} ppm");
+
+        match self
+            .scd30_request(scd30::ControlMessage::ForceCalibrate { ppm })
+            .await
+        {
+            Ok(()) => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message: "recalibrated SCD30",
+            }),
+            Err(error) => sensor_error(scd30_label(), &error),
+        }
+    }
+
+    /// Changes the poll interval of every instance of a sensor, and saves it
+    /// in the config so that it's used after a reboot.
+    async fn set_poll_interval(&self, mut req: Request) -> Response {
+        #[derive(Debug, serde::Deserialize)]
+        struct SetPollInterval {
+            secs: u16,
+        }
+
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let path = req.path();
+        let name = path
+            .strip_prefix("/sensors/")
+            .and_then(|path| path.strip_suffix("/poll_interval"))
+            .unwrap_or_default();
+        let name = match sensor_name(name) {
+            Ok(name) => name,
+            Err(rsp) => return rsp,
+        };
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let secs = match serde_urlencoded::from_bytes(&body) {
+            Ok(SetPollInterval { secs }) if secs > 0 => secs,
+            Ok(_) => return bad_request("poll interval must be greater than 0"),
+            Err(error) => return bad_request(error),
+        };
+
+        log::info!("received request to set {name} poll interval to {secs} seconds");
+
+        let cmd = sensor::Command::SetPollInterval(Duration::from_secs(secs.into()));
+        let instances = match sensor::COMMANDS.send(name, cmd).await {
+            Ok(instances) => instances,
+            Err(error) => return bad_request(format_args!("{error:#}")),
+        };
+
+        let mut store = self.config_store.lock().unwrap();
+        let mut config = match load_config(&mut store) {
+            Ok(config) => config,
+            Err(rsp) => return rsp,
+        };
+        config
+            .sensors
+            .entry(name.to_string())
+            .or_default()
+            .poll_interval_secs = Some(secs);
+        if let Err(error) = store.save(&config) {
+            return internal_error(format_args!("{error:#}"));
+        }
+
+        json_rsp(JsonResponse {
+            code: 200,
+            status: "OK",
+            message: format!(
+                "set {name} poll interval to {secs} seconds ({instances} instances updated)"
+            ),
+        })
+    }
+
+    /// Pauses, resumes, or restarts every instance of a sensor.
+    ///
+    /// Whether a sensor is paused is saved in the config, so that a paused
+    /// sensor stays paused after a reboot.
+    async fn command_sensor(&self, req: Request) -> Response {
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let path = req.path();
+        let (name, action) = path
+            .strip_prefix("/sensors/")
+            .and_then(|path| path.rsplit_once('/'))
+            .unwrap_or_default();
+        let (cmd, enabled, done) = match action {
+            "pause" => (sensor::Command::Pause, Some(false), "paused"),
+            "resume" => (sensor::Command::Resume, Some(true), "resumed"),
+            "restart" => (sensor::Command::Restart, None, "restarted"),
+            _ => {
+                return json_rsp(JsonResponse {
+                    code: 404,
+                    status: "Not Found",
+                    message: format!("no route for {:?} {path}", req.method),
+                })
+            }
+        };
+        let name = match sensor_name(name) {
+            Ok(name) => name,
+            Err(rsp) => return rsp,
+        };
+
+        log::info!("received request to {action} {name}");
+
+        let instances = match sensor::COMMANDS.send(name, cmd).await {
+            Ok(instances) => instances,
+            Err(error) => return internal_error(format_args!("{error:#}")),
+        };
+
+        if let Some(enabled) = enabled {
+            let mut store = self.config_store.lock().unwrap();
+            let mut config = match load_config(&mut store) {
+                Ok(config) => config,
+                Err(rsp) => return rsp,
+            };
+            config.sensors.entry(name.to_string()).or_default().enabled = enabled;
+            if let Err(error) = store.save(&config) {
+                return internal_error(format_args!("{error:#}"));
+            }
+        }
+
+        json_rsp(JsonResponse {
+            code: 200,
+            status: "OK",
+            message: format!("{done} {name} ({instances} instances)"),
+        })
+    }
+
+    /// Sends a JSON control message to every running instance of a sensor.
+    async fn control(&self, mut req: Request) -> Response {
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let path = req.path();
+        let name = path
+            .strip_prefix("/sensors/")
+            .and_then(|path| path.strip_suffix("/control"))
+            .unwrap_or_default()
+            .to_string();
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+
+        log::info!(
+            "received control message for {name}: {}",
+            String::from_utf8_lossy(&body)
+        );
+
+        match sensor::CONTROLS.send(&name, &body).await {
+            Ok(handled) => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message: format!("{handled} {name} instances handled the control message"),
+            }),
+            Err(sensor::ControlError::NotFound) => json_rsp(JsonResponse {
+                code: 404,
+                status: "Not Found",
+                message: format!("no sensor named {name:?}"),
+            }),
+            Err(error @ sensor::ControlError::NotRunning) => json_rsp(JsonResponse {
+                code: 503,
+                status: "Service Unavailable",
+                message: format!("{name}: {error}"),
+            }),
+            Err(error @ sensor::ControlError::InvalidMessage(_)) => bad_request(error),
+            Err(sensor::ControlError::Sensor { label, error }) => sensor_error(label, &error),
+            Err(error @ sensor::ControlError::Closed(_)) => internal_error(error),
+        }
+    }
+
+    async fn configure_scd30(&self, mut req: Request) -> Response {
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let update = match serde_json::from_slice::<scd30::SettingsUpdate>(&body) {
+            Ok(update) => update,
+            Err(error) => return bad_request(error),
+        };
+
+        log::info!("received request to update SCD30 settings: {update:?}");
+
+        for msg in update.messages() {
+            if let Err(error) = self.scd30_request(msg).await {
+                return internal_error(format_args!("{error:#}"));
+            }
+        }
+
+        serve_scd30_settings()
+    }
+
+    /// Sends a control message to the SCD30 and waits for it to be handled.
+    async fn scd30_request(&self, msg: scd30::ControlMessage) -> anyhow::Result<()> {
+        match self.scd30_ctrl.try_request(msg).await {
+            Ok(()) => Ok(()),
+            Err(actor::TryReqError::Error(error)) => Err(error),
+            Err(_) => Err(anyhow::anyhow!("SCD30 control channel error")),
+        }
+    }
+
+    #[cfg(feature = "sensor-sgp30")]
+    async fn control_sgp30(&self, mut req: Request) -> Response {
+        #[derive(Debug, serde::Deserialize)]
+        struct SetHumidity {
+            #[serde(default, deserialize_with = "sgp30::deserialize_abs_humidity")]
+            abs_humidity: Option<f32>,
+        }
+
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let (msg, message) = match req.path() {
+            "/sensors/sgp30/baseline" => match serde_urlencoded::from_bytes(&body) {
+                Ok(baseline) => (
+                    sgp30::ControlMessage::SetBaseline(baseline),
+                    "set SGP30 baseline",
+                ),
+                Err(error) => return bad_request(error),
+            },
+            "/sensors/sgp30/baseline/reset" => {
+                (sgp30::ControlMessage::ResetBaseline, "reset SGP30 baseline")
+            }
+            "/sensors/sgp30/humidity" => match serde_urlencoded::from_bytes(&body) {
+                Ok(SetHumidity { abs_humidity }) => (
+                    sgp30::ControlMessage::SetHumidity { abs_humidity },
+                    "set SGP30 absolute humidity",
+                ),
+                Err(error) => return bad_request(error),
+            },
+            "/sensors/sgp30/raw" => (
+                sgp30::ControlMessage::MeasureRawSignals,
+                "measured SGP30 raw signals",
+            ),
+            path => {
+                return json_rsp(JsonResponse {
+                    code: 404,
+                    status: "Not Found",
+                    message: format!("no route for {:?} {path}", req.method),
+                })
+            }
+        };
+
+        if let Err(error) = self.sgp30_request(msg).await {
+            return internal_error(format_args!("{error:#}"));
+        }
+
+        match *sgp30::RAW_SIGNALS.lock().unwrap() {
+            Some(raw) if req.path() == "/sensors/sgp30/raw" => serve_json(&raw),
+            _ => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message,
+            }),
+        }
+    }
+
+    /// Sends a control message to the SGP30 and waits for it to be handled.
+    #[cfg(feature = "sensor-sgp30")]
+    async fn sgp30_request(&self, msg: sgp30::ControlMessage) -> anyhow::Result<()> {
+        let ctrl = self
+            .sgp30_ctrl
+            .as_ref()
+            .context("SGP30 control is not enabled")?;
+        match ctrl.try_request(msg).await {
+            Ok(()) => Ok(()),
+            Err(actor::TryReqError::Error(error)) => Err(error),
+            Err(_) => Err(anyhow::anyhow!("SGP30 control channel error")),
+        }
+    }
+
+    async fn select_wifi(&self, mut req: Request) -> Response {
+        #[derive(Debug, serde::Deserialize)]
+        struct Provision {
+            admin_password: Option<String>,
+        }
+
+        let authorization = self.admin.check(req.header("authorization"));
+        if authorization == auth::Authorization::Unauthorized {
+            return unauthorized("invalid admin credentials");
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let credentials = match serde_urlencoded::from_bytes(&body) {
+            Ok(credentials) => credentials,
+            Err(error) => return bad_request(error),
+        };
+
+        // if no admin password has been set, this is first-run
+        // provisioning, and an admin password must be set along with the
+        // WiFi credentials.
+        if authorization == auth::Authorization::Unprovisioned {
+            let password = match serde_urlencoded::from_bytes(&body) {
+                Ok(Provision {
+                    admin_password: Some(password),
+                }) => password,
+                Ok(_) => {
+                    return bad_request(
+                        "an admin password must be set when selecting a WiFi network for the first time",
+                    )
+                }
+                Err(error) => return bad_request(error),
+            };
+
+            if let Err(error) = self.admin.set_password(&password) {
+                return bad_request(format_args!("{error:#}"));
+            }
+        }
+
+        match self
+            .creds_tx
+            .send(credentials)
+            .await
+            .map_err(|_| anyhow::anyhow!("wifi control channel error"))
+        {
+            Ok(_) => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message: "Connected",
+            }),
+            Err(error) => internal_error(error),
+        }
+    }
+
+    async fn save_config(&self, mut req: Request) -> Response {
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let config = match config::Config::from_json(&body) {
+            Ok(config) => config,
+            Err(error) => return bad_request(format_args!("{error:#}")),
+        };
+
+        if let Err(error) = config.validate() {
+            return bad_request(format_args!("{error:#}"));
+        }
+
+        match self.config_store.lock().unwrap().save(&config) {
+            Ok(()) => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message: "saved config; changes will take effect after a reboot",
+            }),
+            Err(error) => internal_error(format_args!("{error:#}")),
+        }
+    }
+
+    async fn set_admin_password(&self, mut req: Request) -> Response {
+        #[derive(Debug, serde::Deserialize)]
+        struct SetPassword {
+            password: String,
+        }
+
+        // the admin password may be set without authentication if none
+        // has been set yet.
+        if self.admin.check(req.header("authorization")) == auth::Authorization::Unauthorized {
+            return unauthorized("invalid admin credentials");
+        }
+
+        let body = match read_body(&mut req).await {
+            Ok(body) => body,
+            Err(rsp) => return rsp,
+        };
+        let password = match serde_urlencoded::from_bytes(&body) {
+            Ok(SetPassword { password }) => password,
+            Err(error) => return bad_request(error),
+        };
+
+        match self.admin.set_password(&password) {
+            Ok(()) => json_rsp(JsonResponse {
+                code: 200,
+                status: "OK",
+                message: "admin password changed",
+            }),
+            Err(error) => bad_request(format_args!("{error:#}")),
+        }
+    }
+
+    /// Writes a firmware image uploaded in the request body to the next OTA
+    /// partition, and reboots into it.
+    async fn update_firmware(&self, mut req: Request) -> Response {
+        if let Err(message) = self.check_auth(&req) {
+            return unauthorized(message);
+        }
+
+        let len = req.content_length();
+        if len < ota::HEADER_LEN {
+            return bad_request("request body must be a firmware image");
+        }
+
+        let mut update = match ota::Update::start() {
+            Ok(update) => update,
+            Err(error) => {
+                return json_rsp(JsonResponse {
+                    code: 409,
+                    status: "Conflict",
+                    message: format!("{error:#}"),
+                })
+            }
+        };
+
+        // read enough of the image to validate it before anything is erased.
+        let mut buf = vec![0; 4096];
+        let mut read = 0;
+        while read < ota::HEADER_LEN {
+            match req.read_body_chunk(&mut buf[read..]).await {
+                Ok(n) => read += n,
+                Err(error) => return bad_request(format_args!("{error:#}")),
+            }
+        }
+        let (app, mut writer) = match update.begin(&buf[..read]) {
+            Ok(begun) => begun,
+            Err(error) => return bad_request(format_args!("{error:#}")),
+        };
+
+        while read < len {
+            let res = match req.read_body_chunk(&mut buf).await {
+                Ok(n) => {
+                    read += n;
+                    writer.write(&buf[..n])
+                }
+                Err(error) => Err(error),
+            };
+            if let Err(error) = res {
+                writer.abort();
+                return bad_request(format_args!("{error:#}"));
+            }
+        }
+
+        if let Err(error) = writer.finish() {
+            return bad_request(format_args!("{error:#}"));
+        }
+
+        // give the response a chance to make it to the client before
+        // rebooting.
+        ota::schedule_reboot(std::time::Duration::from_secs(2));
+        json_rsp(JsonResponse {
+            code: 200,
+            status: "OK",
+            message: format!(
+                "updated firmware to {} {}; rebooting",
+                app.project_name, app.version
+            ),
+        })
+    }
+
+    /// Checks that `req` carries the admin credentials.
+    fn check_auth(&self, req: &Request) -> Result<(), &'static str> {
+        match self.admin.check(req.header("authorization")) {
+            auth::Authorization::Authorized => Ok(()),
+            auth::Authorization::Unauthorized => Err("invalid admin credentials"),
+            auth::Authorization::Unprovisioned => {
+                Err("no admin password has been set; set one with POST /admin/password")
+            }
+        }
+    }
+}
+
+/// Returns the name of the sensor called `name`, or a 404 response if there is
+/// no such sensor.
+///
+/// Sensor names are matched case-insensitively, so that the routes can be
+/// lowercase like the rest of them.
+fn sensor_name(name: &str) -> Result<&'static str, Response> {
+    sensor::STATUSES
+        .iter()
+        .map(|(label, _)| label.sensor)
+        .find(|sensor| sensor.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            json_rsp(JsonResponse {
+                code: 404,
+                status: "Not Found",
+                message: format!("no sensor named {name:?}"),
+            })
+        })
+}
+
+/// Reads the whole body of `req`, or returns an error response if it couldn't
+/// be read.
+async fn read_body(req: &mut Request) -> Result<Vec<u8>, Response> {
+    req.read_body()
+        .await
+        .map_err(|error| bad_request(format_args!("{error:#}")))
+}
+
+/// Loads the stored config so that it can be changed and saved again, or
+/// returns a 500 response if it couldn't be read.
+///
+/// Unlike [`config::Store::load_or_default`], this doesn't fall back to the
+/// default config, since saving that would wipe out the stored config.
+fn load_config(store: &mut config::Store) -> Result<config::Config, Response> {
+    store
+        .load()
+        .map(Option::unwrap_or_default)
+        .map_err(|error| internal_error(format_args!("failed to load the saved config: {error:#}")))
+}
+
+/// Returns the label of the SCD30 that `scd30_ctrl` controls.
+fn scd30_label() -> SensorLabel {
+    use sensor::Sensor;
+    SensorLabel::new(scd30::Scd30::NAME, scd30::Scd30::ADDRESSES[0])
+}
+
+/// Serves the SCD30's settings, as of the last time they were read.
+fn serve_scd30_settings() -> Response {
+    match *scd30::SETTINGS.lock().unwrap() {
+        Some(settings) => serve_json(&settings),
+        None => json_rsp(JsonResponse {
+            code: 503,
+            status: "Service Unavailable",
+            message: "SCD30 has not been initialized",
+        }),
+    }
+}
+
+fn serve_json(json: &impl Serialize) -> Response {
+    // XXX(eliza): this is technically more correct but i wanna be able to open
+    // it in the browser...
+    /*
+    if let Some(accept) = req.header("accept") {
+        if !accept.contains(JSON) {
+            return Response::new(406, "Not Acceptable") // not acceptable
+                .with_body(content_type::JSON, JSON.as_bytes());
+        }
+    }
+    */
+
+    match serde_json::to_string_pretty(&json) {
+        Ok(json) => {
+            log::debug!("responding with JSON: {json}");
+            Response::new(200, "OK").with_body(content_type::JSON, json.into_bytes())
+        }
+        Err(error) => {
+            log::error!("JSON serialization error: {error}");
+            internal_error(format_args!("JSON serialization error: {error}"))
+        }
+    }
+}
+
+/// Formats a sensor [`Event`] as a Server-Sent Event.
+fn stream_event(event: Event, metrics: &'static SensorMetrics) -> Vec<u8> {
+    let name = match event {
+        Event::Reading { .. } => "reading",
+        Event::Status { .. } => "status",
+    };
+    let mut data = match serde_json::to_value(event) {
+        Ok(data) => data,
+        Err(error) => {
+            log::error!("JSON serialization error: {error}");
+            return Vec::new();
+        }
+    };
+    if let Event::Reading { .. } = event {
+        data["sensors"] = serde_json::json!(metrics);
+    }
+    sse_event(name, &data).unwrap_or_default()
+}
+
+fn sse_event(name: &str, data: &impl Serialize) -> Option<Vec<u8>> {
+    match serde_json::to_string(data) {
+        // JSON serialized by `serde_json::to_string` never contains newlines,
+        // so it fits in a single `data:` field.
+        Ok(data) => Some(format!("event: {name}\ndata: {data}\n\n").into_bytes()),
+        Err(error) => {
+            log::error!("JSON serialization error: {error}");
+            None
+        }
+    }
+}
+
+fn json_rsp<T: Serialize + fmt::Display>(json: JsonResponse<T>) -> Response {
+    log::info!(
+        "responding with {} {}: {}",
+        json.code,
+        json.status,
+        json.message
+    );
+    let rsp = Response::new(json.code, json.status);
+    match serde_json::to_string_pretty(&json) {
+        Ok(body) => rsp.with_body(content_type::JSON, body.into_bytes()),
+        Err(error) => {
+            log::error!("JSON serialization error: {error}");
+            rsp
+        }
+    }
+}
+
+fn bad_request(error: impl fmt::Display) -> Response {
+    // TODO(eliza): don't ToString these...
+    json_rsp(JsonResponse {
+        code: 400,
+        status: "Bad Request",
+        message: error.to_string(),
+    })
+}
+
+fn unauthorized(message: &'static str) -> Response {
+    json_rsp(JsonResponse {
+        code: 401,
+        status: "Unauthorized",
+        message,
+    })
+    .with_header(header::WWW_AUTHENTICATE, "Basic realm=\"eclss\"")
+}
+
+/// Returns a 500 response describing an error returned by `sensor`.
+fn sensor_error(sensor: SensorLabel, error: &anyhow::Error) -> Response {
+    json_rsp(JsonResponse {
+        code: 500,
+        status: "Internal Server Error",
+        message: SensorError::new(sensor, error),
+    })
+}
+
+fn internal_error(error: impl fmt::Display) -> Response {
+    // TODO(eliza): don't ToString these...
+    json_rsp(JsonResponse {
+        code: 500,
+        status: "Internal Server Error",
+        message: error.to_string(),
+    })
+}
+
+#[derive(serde::Serialize)]
+struct JsonResponse<T: Serialize> {
+    code: u16,
+    status: &'static str,
+    message: T,
+}
+
+/// An error returned by a sensor, with its chain of causes.
+#[derive(serde::Serialize)]
+struct SensorError {
+    sensor: SensorLabel,
+    error: String,
+    causes: Vec<String>,
+}
+
+// === impl SensorError ===
+
+impl SensorError {
+    fn new(sensor: SensorLabel, error: &anyhow::Error) -> Self {
+        Self {
+            sensor,
+            error: error.to_string(),
+            causes: error.chain().skip(1).map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.sensor, self.error)?;
+        for cause in &self.causes {
+            write!(f, ": {cause}")?;
+        }
+        Ok(())
+    }
+}
+
+// === impl StreamSlot ===
+
+impl StreamSlot {
+    fn acquire() -> Option<Self> {
+        OPEN_STREAMS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < MAX_STREAMS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Self(()))
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        OPEN_STREAMS.fetch_sub(1, Ordering::Release);
+    }
+}
+
+mod header {
+    pub(super) const CACHE_CONTROL: &str = "cache-control";
+    pub(super) const WWW_AUTHENTICATE: &str = "www-authenticate";
+}
+
+mod content_type {
+
+    pub(super) const JSON: &str = "application/json";
+    pub(super) const HTML: &str = "text/html";
+    pub(super) const EVENT_STREAM: &str = "text/event-stream";
+}
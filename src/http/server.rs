@@ -0,0 +1,523 @@
+//! A small asynchronous HTTP/1.1 server.
+//!
+//! ESP-IDF's `httpd` calls handlers on its own worker threads, so handlers
+//! that need to talk to tasks running on our local executor (such as sensor
+//! control requests) have to block the worker thread until the executor gets
+//! around to responding. Instead, this server runs as a task on the same
+//! executor as everything else, and handlers are just `async` functions.
+//!
+//! lwIP doesn't give us a reactor to register interest in socket readiness
+//! with, so sockets are put in non-blocking mode and re-polled on a short timer
+//! whenever they would block.
+//!
+//! This only implements the subset of HTTP/1.1 that the ECLSS HTTP API needs:
+//! one request per connection, and request bodies delimited by
+//! `Content-Length`. Request bodies are read from the connection on demand, so
+//! that large uploads (such as firmware images) can be streamed rather than
+//! buffered in memory. Response bodies may either be sent all at once, or
+//! streamed until the stream ends or the client goes away (for
+//! [Server-Sent Events][sse]).
+//!
+//! [sse]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+use anyhow::Context;
+use embassy_time::{Duration, Instant, Timer};
+use futures::{
+    select,
+    stream::{FuturesUnordered, LocalBoxStream, Stream},
+    FutureExt, StreamExt,
+};
+use std::{
+    borrow::Cow,
+    future::Future,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    rc::Rc,
+};
+
+/// An HTTP request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: Method,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: RequestBody,
+}
+
+/// The body of a request, which is read from the connection on demand.
+#[derive(Debug, Default)]
+struct RequestBody {
+    conn: Option<Rc<TcpStream>>,
+    /// The total length of the body.
+    len: usize,
+    /// Body bytes that were received along with the request head.
+    buffered: Vec<u8>,
+    /// The number of body bytes that have yet to be read from the connection.
+    remaining: usize,
+}
+
+/// An HTTP response.
+pub struct Response {
+    pub code: u16,
+    pub status: &'static str,
+    headers: Vec<(&'static str, Cow<'static, str>)>,
+    body: Body,
+}
+
+enum Body {
+    Full(Cow<'static, [u8]>),
+    Stream(LocalBoxStream<'static, Vec<u8>>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Other,
+}
+
+/// The maximum number of connections that are handled concurrently.
+///
+/// Additional connections will wait in the listen backlog.
+const MAX_CONNECTIONS: usize = 4;
+
+/// The maximum size of a request's request line and headers.
+const MAX_HEAD_LEN: usize = 2048;
+
+/// The maximum size of a request body that is read all at once by
+/// [`Request::read_body`].
+const MAX_BODY_LEN: usize = 4096;
+
+/// How long to wait before re-polling a socket that would have blocked.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a client has to send a complete request head, or the next chunk
+/// of a request body.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TARGET: &str = "eclss::http";
+
+/// Accepts connections on `listener` and handles requests with `handler`
+/// until the end of time.
+pub async fn serve<F, Fut>(listener: TcpListener, handler: F) -> anyhow::Result<()>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    listener
+        .set_nonblocking(true)
+        .context("failed to set listener to non-blocking mode")?;
+    let mut connections = FuturesUnordered::new();
+
+    loop {
+        if connections.len() >= MAX_CONNECTIONS {
+            // wait for a connection to complete before accepting more.
+            connections.next().await;
+            continue;
+        }
+
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                log::debug!(target: TARGET, "accepted connection from {peer}");
+                connections.push(handle_connection(stream, peer, &handler));
+                continue;
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+            Err(error) => log::warn!(target: TARGET, "failed to accept connection: {error}"),
+        }
+
+        // nothing to accept right now; drive in-flight connections until it's
+        // time to check the listener again.
+        let mut poll_wait = Timer::after(POLL_INTERVAL).fuse();
+        loop {
+            select! {
+                _ = poll_wait => break,
+                _ = connections.select_next_some() => {},
+            }
+        }
+    }
+}
+
+async fn handle_connection<F, Fut>(stream: TcpStream, peer: SocketAddr, handler: &F)
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let res = async {
+        stream
+            .set_nonblocking(true)
+            .context("failed to set connection to non-blocking mode")?;
+        let stream = Rc::new(stream);
+        let rsp = match read_request(&stream).await? {
+            Ok(req) => {
+                log::debug!(target: TARGET, "{peer}: {:?} {}", req.method, req.uri);
+                handler(req).await
+            }
+            Err(rsp) => rsp,
+        };
+        log::debug!(target: TARGET, "{peer}: {} {}", rsp.code, rsp.status);
+        write_response(&stream, rsp).await
+    }
+    .await;
+
+    if let Err(error) = res {
+        log::debug!(target: TARGET, "{peer}: connection error: {error:#}");
+    }
+}
+
+/// Reads a request from `stream`.
+///
+/// Returns an error if reading from the socket failed, or `Ok(Err(response))`
+/// if the client sent an invalid request, where `response` is the error
+/// response to send back.
+/// Only the request head is read here; the body is left on the connection to
+/// be read by the handler.
+async fn read_request(stream: &Rc<TcpStream>) -> anyhow::Result<Result<Request, Response>> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut buf = Vec::with_capacity(512);
+    let mut chunk = [0; 256];
+
+    let head_len = loop {
+        if let Some(pos) = find_head_end(&buf) {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            return Ok(Err(Response::new(431, "Request Header Fields Too Large")));
+        }
+        let n = read_some(stream, &mut chunk, deadline).await?;
+        anyhow::ensure!(n > 0, "connection closed before request was complete");
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let mut req = match parse_head(&buf[..head_len]) {
+        Ok(req) => req,
+        Err(message) => {
+            return Ok(Err(
+                Response::new(400, "Bad Request").with_body("text/plain", message.as_bytes())
+            ))
+        }
+    };
+
+    let content_len = match req.header("content-length").map(str::parse::<usize>) {
+        None => 0,
+        Some(Ok(len)) => len,
+        Some(Err(_)) => {
+            return Ok(Err(Response::new(400, "Bad Request")
+                .with_body("text/plain", &b"invalid content-length"[..])))
+        }
+    };
+
+    let mut buffered = buf.split_off(head_len);
+    buffered.truncate(content_len);
+    req.body = RequestBody {
+        conn: Some(stream.clone()),
+        len: content_len,
+        remaining: content_len - buffered.len(),
+        buffered,
+    };
+
+    Ok(Ok(req))
+}
+
+async fn read_some(
+    mut stream: &TcpStream,
+    buf: &mut [u8],
+    deadline: Instant,
+) -> anyhow::Result<usize> {
+    loop {
+        match stream.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                anyhow::ensure!(Instant::now() < deadline, "timed out reading request");
+                Timer::after(POLL_INTERVAL).await;
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error).context("failed to read from connection"),
+        }
+    }
+}
+
+async fn write_response(stream: &TcpStream, rsp: Response) -> anyhow::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", rsp.code, rsp.status);
+    for (name, value) in &rsp.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if let Body::Full(ref body) = rsp.body {
+        head.push_str(&format!("content-length: {}\r\n", body.len()));
+    }
+    head.push_str("connection: close\r\n\r\n");
+    write_all(stream, head.as_bytes()).await?;
+
+    match rsp.body {
+        Body::Full(body) => write_all(stream, &body).await?,
+        // without a content-length, the end of the body is indicated by
+        // closing the connection.
+        Body::Stream(mut body) => {
+            while let Some(chunk) = body.next().await {
+                write_all(stream, &chunk).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_all(mut stream: &TcpStream, mut buf: &[u8]) -> anyhow::Result<()> {
+    while !buf.is_empty() {
+        match stream.write(buf) {
+            Ok(0) => anyhow::bail!("connection closed while writing response"),
+            Ok(n) => buf = &buf[n..],
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                Timer::after(POLL_INTERVAL).await
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error).context("failed to write to connection"),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the length of the request head (including the terminating blank
+/// line), if the whole head has been received.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Parses a request line and headers.
+fn parse_head(head: &[u8]) -> Result<Request, &'static str> {
+    let head = std::str::from_utf8(head).map_err(|_| "request head is not UTF-8")?;
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().ok_or("missing request line")?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(uri), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err("malformed request line");
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err("unsupported HTTP version");
+    }
+    if !uri.starts_with('/') {
+        return Err("request URI must be an absolute path");
+    }
+
+    let headers = lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (name, value) = line.split_once(':').ok_or("malformed header")?;
+            Ok((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Request {
+        method: Method::parse(method),
+        uri: uri.to_string(),
+        headers,
+        body: RequestBody::default(),
+    })
+}
+
+// === impl Request ===
+
+impl Request {
+    /// Returns the request's path, without the query string.
+    pub fn path(&self) -> &str {
+        self.uri.split_once('?').map_or(&self.uri, |(path, _)| path)
+    }
+
+    /// Returns the request's query string, if it has one.
+    pub fn query(&self) -> Option<&str> {
+        self.uri.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Returns the value of the header `name`. Header names are
+    /// case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the length of the request body, as given by its
+    /// `Content-Length` header.
+    pub fn content_length(&self) -> usize {
+        self.body.len
+    }
+
+    /// Reads the whole request body.
+    ///
+    /// Bodies longer than [`MAX_BODY_LEN`] are rejected; use
+    /// [`Request::read_body_chunk`] to stream larger bodies.
+    pub async fn read_body(&mut self) -> anyhow::Result<Vec<u8>> {
+        let len = self.content_length();
+        anyhow::ensure!(
+            len <= MAX_BODY_LEN,
+            "request body too long ({len} bytes, max {MAX_BODY_LEN} bytes)"
+        );
+
+        let mut body = Vec::with_capacity(len);
+        let mut chunk = [0; 256];
+        loop {
+            match self.read_body_chunk(&mut chunk).await? {
+                0 => return Ok(body),
+                n => body.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    /// Reads the next chunk of the request body into `buf`, returning the
+    /// number of bytes read, or 0 once the whole body has been read.
+    pub async fn read_body_chunk(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let body = &mut self.body;
+        if !body.buffered.is_empty() {
+            let n = buf.len().min(body.buffered.len());
+            buf[..n].copy_from_slice(&body.buffered[..n]);
+            body.buffered.drain(..n);
+            return Ok(n);
+        }
+
+        if body.remaining == 0 {
+            return Ok(0);
+        }
+
+        let conn = body
+            .conn
+            .as_deref()
+            .context("request is not attached to a connection")?;
+        let max = buf.len().min(body.remaining);
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let n = read_some(conn, &mut buf[..max], deadline).await?;
+        anyhow::ensure!(n > 0, "connection closed before request body was complete");
+        body.remaining -= n;
+        Ok(n)
+    }
+}
+
+// === impl Response ===
+
+impl Response {
+    pub fn new(code: u16, status: &'static str) -> Self {
+        Self {
+            code,
+            status,
+            headers: Vec::new(),
+            body: Body::Full(Cow::Borrowed(&[])),
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<Cow<'static, str>>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn with_body(
+        self,
+        content_type: &'static str,
+        body: impl Into<Cow<'static, [u8]>>,
+    ) -> Self {
+        let mut this = self.with_header("content-type", content_type);
+        this.body = Body::Full(body.into());
+        this
+    }
+
+    /// Streams the response body until `body` ends or the client
+    /// disconnects.
+    pub fn with_stream(
+        self,
+        content_type: &'static str,
+        body: impl Stream<Item = Vec<u8>> + 'static,
+    ) -> Self {
+        let mut this = self.with_header("content-type", content_type);
+        this.body = Body::Stream(body.boxed_local());
+        this
+    }
+}
+
+// === impl Method ===
+
+impl Method {
+    fn parse(s: &str) -> Self {
+        match s {
+            "GET" => Self::Get,
+            "HEAD" => Self::Head,
+            "POST" => Self::Post,
+            "PUT" => Self::Put,
+            "DELETE" => Self::Delete,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_head() {
+        let head = b"POST /sensors/co2/calibrate?foo=bar HTTP/1.1\r\nHost: eclss.local\r\nContent-Length: 7\r\n\r\n";
+        let len = find_head_end(head).unwrap();
+        assert_eq!(len, head.len());
+
+        let req = parse_head(&head[..len]).unwrap();
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.path(), "/sensors/co2/calibrate");
+        assert_eq!(req.query(), Some("foo=bar"));
+        assert_eq!(req.header("content-length"), Some("7"));
+        assert_eq!(req.header("HOST"), Some("eclss.local"));
+        assert_eq!(req.header("authorization"), None);
+    }
+
+    #[test]
+    fn incomplete_head_is_not_found() {
+        assert_eq!(
+            find_head_end(b"GET / HTTP/1.1\r\nHost: eclss.local\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        assert!(parse_head(b"GET /\r\n\r\n").is_err());
+        assert!(parse_head(b"GET / HTTP/2\r\n\r\n").is_err());
+        assert!(parse_head(b"GET http://eclss.local/ HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_head(b"GET / HTTP/1.1\r\nno colon here\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn reads_buffered_body_in_chunks() {
+        let mut req = parse_head(b"POST /ota HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap();
+        req.body = RequestBody {
+            conn: None,
+            len: 5,
+            buffered: b"hello".to_vec(),
+            remaining: 0,
+        };
+        assert_eq!(req.content_length(), 5);
+
+        let mut buf = [0; 3];
+        let n = req
+            .read_body_chunk(&mut buf)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"hel");
+        let n = req
+            .read_body_chunk(&mut buf)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"lo");
+        let n = req
+            .read_body_chunk(&mut buf)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+}
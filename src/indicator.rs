@@ -0,0 +1,90 @@
+//! Shows the device's status on the board's NeoPixel.
+//!
+//! Until the WiFi is connected, the NeoPixel indicates the WiFi state. Once
+//! it's connected, the NeoPixel indicates the status of the sensors instead,
+//! showing the least healthy sensor's status.
+use crate::{
+    net::WifiState,
+    sensor::{Status, STATUSES},
+    ws2812::NeoPixel,
+};
+use embassy_time::{Duration, Timer};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Color(u8, u8, u8);
+
+/// How often to check whether the NeoPixel's color should change.
+const INTERVAL: Duration = Duration::from_millis(250);
+
+const TARGET: &str = "eclss::indicator";
+
+const RED: Color = Color(255, 0, 0);
+const ORANGE: Color = Color(255, 165, 0);
+const YELLOW: Color = Color(255, 255, 0);
+const GREEN: Color = Color(0, 255, 0);
+const CYAN: Color = Color(0, 255, 255);
+const BLUE: Color = Color(0, 0, 255);
+const PURPLE: Color = Color(128, 0, 255);
+const MAGENTA: Color = Color(255, 0, 255);
+
+pub async fn run(mut npx: NeoPixel<'static>) -> anyhow::Result<()> {
+    let mut current = None;
+    loop {
+        let color = color(WifiState::current(), sensor_status());
+        if current != Some(color) {
+            let Color(r, g, b) = color;
+            match npx.set_color(r, g, b) {
+                Ok(_) => current = Some(color),
+                Err(error) => log::warn!(target: TARGET, "failed to set neopixel color: {error}"),
+            }
+        }
+
+        Timer::after(INTERVAL).await;
+    }
+}
+
+fn color(wifi: WifiState, sensors: Option<Status>) -> Color {
+    match wifi {
+        // no wifi configured --- orange
+        WifiState::Unconfigured => ORANGE,
+        // failed to connect --- red
+        WifiState::Error => RED,
+        // disconnected --- orange
+        WifiState::Disconnected => ORANGE,
+        // connecting --- yellow
+        WifiState::Connecting => YELLOW,
+        WifiState::Connected => match sensors {
+            // a sensor stopped responding on the bus --- magenta
+            Some(Status::BusError) => MAGENTA,
+            // a sensor is responding, but talking to it failed --- purple
+            Some(Status::ProtocolError) => PURPLE,
+            // a sensor is still coming up --- blue
+            Some(Status::Initializing | Status::WarmingUp) => BLUE,
+            // a sensor is calibrating itself --- cyan
+            Some(Status::Calibrating) => CYAN,
+            // successfully connected and all sensors are up (or there aren't
+            // any) --- all green across the board!
+            Some(Status::Up | Status::Missing | Status::Disabled) | None => GREEN,
+        },
+    }
+}
+
+/// Returns the status of the least healthy sensor that has been detected.
+fn sensor_status() -> Option<Status> {
+    /// Statuses in order of how urgently they should be indicated.
+    const PRIORITY: [Status; 5] = [
+        Status::BusError,
+        Status::ProtocolError,
+        Status::Initializing,
+        Status::WarmingUp,
+        Status::Calibrating,
+    ];
+    let statuses = STATUSES
+        .iter()
+        .map(|(_, status)| status.status())
+        .collect::<Vec<_>>();
+    PRIORITY
+        .into_iter()
+        .find(|status| statuses.contains(status))
+        .or_else(|| statuses.contains(&Status::Up).then_some(Status::Up))
+}
@@ -1,15 +1,34 @@
 #![feature(type_alias_impl_trait)]
+#![feature(async_fn_in_trait)]
+// `async_fn_in_trait` is still marked as incomplete on our toolchain.
+#![allow(incomplete_features)]
 #![doc = include_str!("../docs/README.md")]
 pub mod actor;
+pub mod auth;
+pub mod board;
+pub mod bus;
+pub mod compensation;
+pub mod config;
+pub mod events;
+pub mod history;
 pub mod http;
+#[cfg(target_os = "espidf")]
+pub mod indicator;
 pub mod metrics;
+pub mod mqtt;
+#[cfg(target_os = "espidf")]
 pub mod net;
+pub mod nvs;
+pub mod ota;
 
 pub mod retry;
 
 pub mod sensor;
 
+#[cfg(not(target_os = "espidf"))]
+pub mod sim;
 pub mod units;
+#[cfg(target_os = "espidf")]
 pub mod ws2812;
 
 // === sensors ===
@@ -25,6 +44,25 @@ pub mod sgp30;
 
 pub type I2cRef<'bus> = shared_bus::I2cProxy<'bus, SharedI2c>;
 pub type I2cBus = shared_bus::BusManager<SharedI2c>;
-pub type SharedI2c = std::sync::Mutex<esp_idf_hal::i2c::I2cDriver<'static>>;
+
+// sensor drivers are generic over the `embedded-hal` I2C and delay traits. on
+// the ESP32, they use the ESP-IDF I2C driver and busy-wait delays; on the host,
+// they run against a simulated bus.
+//
+// note that the bus is chosen by the build target, rather than by a type
+// parameter: the `Sensor` trait, the `Manager`, and the `Scanner` all use these
+// aliases directly. so, a host build can only run against the simulated bus,
+// and a firmware build can't use the simulated bus (e.g. to run sensor tests
+// on the device). making all of those generic over the bus would lift this
+// restriction, but it isn't needed yet.
+#[cfg(target_os = "espidf")]
+pub type SharedI2c = std::sync::Mutex<bus::I2c>;
+#[cfg(target_os = "espidf")]
+pub use esp_idf_hal::{delay::Ets as Delay, i2c::I2cError};
+
+#[cfg(not(target_os = "espidf"))]
+pub type SharedI2c = std::sync::Mutex<sim::SimBus>;
+#[cfg(not(target_os = "espidf"))]
+pub use sim::{Delay, I2cError};
 
 pub use metrics::SensorMetrics;
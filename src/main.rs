@@ -1,25 +1,40 @@
 // If using the `binstart` feature of `esp-idf-sys`, always keep this module
 // imported
+#[cfg(target_os = "espidf")]
 use anyhow::Context;
-use eclss::{actor, http, net, sensor, ws2812};
-use embassy_time::Duration;
-use esp_idf_hal::{
-    i2c::{I2cConfig, I2cDriver},
-    peripherals::Peripherals,
-    prelude::*,
-    reset::WakeupReason,
-    task,
+#[cfg(all(target_os = "espidf", feature = "sensor-bme680"))]
+use eclss::bme680;
+#[cfg(all(target_os = "espidf", feature = "sensor-pmsa003i"))]
+use eclss::pmsa003i;
+#[cfg(all(target_os = "espidf", feature = "sensor-scd30"))]
+use eclss::scd30;
+#[cfg(all(target_os = "espidf", feature = "sensor-sgp30"))]
+use eclss::sgp30;
+#[cfg(target_os = "espidf")]
+use eclss::{
+    auth, board, bus, config, history, http, indicator, mqtt, net, ota,
+    sensor::{self, Sensor},
 };
+#[cfg(target_os = "espidf")]
+use embassy_time::Duration;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::{peripherals::Peripherals, reset::WakeupReason, task};
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop, log::EspLogger, mdns::EspMdns, nvs::EspDefaultNvsPartition,
     sntp::EspSntp,
 };
+#[cfg(target_os = "espidf")]
 use esp_idf_sys as _;
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
 
+#[cfg(target_os = "espidf")]
 static METRICS: eclss::SensorMetrics = eclss::SensorMetrics::new();
 
 // Make sure that the firmware will contain
 // up-to-date build time and package info coming from the binary crate
+#[cfg(target_os = "espidf")]
 esp_idf_sys::esp_app_desc!();
 
 #[cfg(not(any(
@@ -32,6 +47,15 @@ compile_error!(
     "compiling without any of the 'sensor-*' features enabled is probably not very useful!"
 );
 
+#[cfg(not(target_os = "espidf"))]
+fn main() {
+    eprintln!(
+        "the ECLSS firmware only runs on an ESP32; on the host, use `cargo test` to run the tests"
+    );
+    std::process::exit(1);
+}
+
+#[cfg(target_os = "espidf")]
 fn main() -> anyhow::Result<()> {
     // It is necessary to call this function once. Otherwise, some patches to the
     // runtime implemented by esp-idf-sys might not link properly. See
@@ -51,13 +75,15 @@ fn main() -> anyhow::Result<()> {
     log::info!("Wakeup reason: {wakeup:?}");
     log::info!("ECLSS is go!");
 
+    let board = &board::BOARD;
+    log::info!("Board: {board}");
+
     let peripherals = Peripherals::take().unwrap();
-    let i2c = peripherals.i2c0;
-    let sda = peripherals.pins.gpio5;
-    let scl = peripherals.pins.gpio6;
-    // QT Py C3 neopixel is on GPIO 2
-    let mut neopixel = ws2812::NeoPixel::new(peripherals.pins.gpio2, peripherals.rmt.channel0)?;
-    neopixel.set_color(255, 0, 0).context("set neopixel red")?;
+    board.power_on()?;
+    let mut status_led = board.status_led(peripherals.rmt.channel0)?;
+    if let Some(ref mut neopixel) = status_led {
+        neopixel.set_color(255, 0, 0).context("set neopixel red")?;
+    }
 
     let _sntp = EspSntp::new_default().context("failed to initialize SNTP")?;
     let mut sysloop =
@@ -66,62 +92,194 @@ fn main() -> anyhow::Result<()> {
         EspDefaultNvsPartition::take().context("failed to initialize non-volatile storage")?;
     let mut mdns = EspMdns::take().context("failed to initialize mDNS")?;
 
-    let wifi = net::EclssWifi::new(peripherals.modem, &mut sysloop, nvs)?;
-    net::init_mdns(&mut mdns)?;
+    let mut config_store = config::Store::new(nvs.clone())?;
+    let config: &'static config::Config = Box::leak(Box::new(config_store.load_or_default()));
+    let config_store = Arc::new(Mutex::new(config_store));
+    let admin = Arc::new(auth::Admin::new(nvs.clone())?);
+    #[cfg(feature = "sensor-sgp30")]
+    sgp30::init_baseline_store(nvs.clone())?;
 
-    let (scd30_ctrl, scd30_rx) = actor::channel(10);
+    let wifi = net::EclssWifi::new(peripherals.modem, &mut sysloop, nvs, &config.wifi)?;
+    net::init_mdns(&mut mdns, &config.mdns)?;
+
+    // control channels for every sensor instance are kept in the sensor
+    // control registry, so that any sensor can be controlled over HTTP.
+    let (scd30_ctrl, scd30_rx) =
+        sensor::CONTROLS.channel::<scd30::Scd30>(scd30::Scd30::ADDRESSES[0]);
+    #[cfg(feature = "sensor-sgp30")]
+    let (sgp30_ctrl, sgp30_rx) =
+        sensor::CONTROLS.channel::<sgp30::Sgp30>(sgp30::Sgp30::ADDRESSES[0]);
+
+    let history = Arc::new(Mutex::new(history::History::new(&config.history)));
+
+    let server = http::start_server(
+        &wifi,
+        &METRICS,
+        scd30_ctrl.clone(),
+        config_store,
+        history.clone(),
+        admin,
+    )?;
+    #[cfg(feature = "sensor-sgp30")]
+    let server = server.with_sgp30(sgp30_ctrl);
 
-    let _server = http::start_server(&wifi, &METRICS, scd30_ctrl)?;
+    // bring up the I2C buses
+    #[cfg(any(esp32c2, esp32c3))]
+    let mut i2c_peripherals = bus::Peripherals::new(peripherals.i2c0);
+    #[cfg(not(any(esp32c2, esp32c3)))]
+    let mut i2c_peripherals = bus::Peripherals::new(peripherals.i2c0, peripherals.i2c1);
+    // if the configured buses can't be brought up, fall back to the default
+    // I2C config, rather than boot-looping until the config is fixed (which
+    // can't be done without the HTTP server).
+    let (config, i2cs) = match start_buses(&mut i2c_peripherals, config) {
+        Ok(i2cs) => (config, i2cs),
+        Err(error) => {
+            log::error!(
+                "failed to bring up I2C buses: {error:#}; falling back to the default I2C config"
+            );
+            let mut fallback = config.clone();
+            fallback.i2c = config::I2cConfig::default();
+            for sensor in fallback.sensors.values_mut() {
+                sensor.bus = None;
+            }
+            let fallback: &'static config::Config = Box::leak(Box::new(fallback));
+            (fallback, start_buses(&mut i2c_peripherals, fallback)?)
+        }
+    };
+    let scan_interval = Duration::from_secs(config.i2c.scan_interval_secs.into());
+    let mut busmans = Vec::with_capacity(i2cs.len());
+    let mut scanners = Vec::with_capacity(i2cs.len());
+    let mut bus_supervisors = Vec::with_capacity(i2cs.len());
+    for (bus_config, i2c) in config.i2c.buses.iter().zip(i2cs) {
+        bus_supervisors.push(bus::Supervisor::new(
+            i2c.clone(),
+            &bus_config.name,
+            config,
+            &METRICS,
+        )?);
+        let busman: &'static eclss::I2cBus = Box::leak(Box::new(shared_bus::BusManager::new(i2c)));
+        busmans.push(busman);
+        scanners.push(sensor::Scanner::new(busman, scan_interval));
+    }
+    let bus_index = |name: &str| {
+        config
+            .bus_index(name)
+            .with_context(|| format!("{name} is assigned to an I2C bus that doesn't exist"))
+    };
 
-    // Maximal I2C speed is 100 kHz and the master has to support clock
-    // stretching. Sensirion recommends to operate the SCD30
-    // at a baud rate of 50 kHz or smaller.
-    let config = I2cConfig::new().baudrate(50u32.kHz().into());
-    let i2c = I2cDriver::new(i2c, sda, scl, &config)?;
-    let bus = shared_bus::new_std!(I2cDriver = i2c).unwrap();
+    // scan each bus for the sensors assigned to it
+    #[cfg(feature = "sensor-scd30")]
+    scanners[bus_index(scd30::Scd30::NAME)?].register::<scd30::Scd30>()?;
+    #[cfg(feature = "sensor-pmsa003i")]
+    scanners[bus_index(pmsa003i::Pmsa003i::NAME)?].register::<pmsa003i::Pmsa003i>()?;
+    #[cfg(feature = "sensor-bme680")]
+    scanners[bus_index(bme680::Bme680::NAME)?].register::<bme680::Bme680>()?;
+    #[cfg(feature = "sensor-sgp30")]
+    scanners[bus_index(sgp30::Sgp30::NAME)?].register::<sgp30::Sgp30>()?;
+    for scanner in &scanners {
+        scanner.scan();
+    }
 
-    // bring up sensors
-    // TODO(eliza): use the sensors to calibrate each other...
-    let sensor_mangler = sensor::Manager {
-        metrics: &METRICS,
-        busman: bus,
-        retry_backoff: Duration::from_secs(1),
+    // bring up sensors, each on the bus it's assigned to
+    let sensor_mangler = |name: &str| {
+        anyhow::Ok(sensor::Manager {
+            metrics: &METRICS,
+            busman: busmans[bus_index(name)?],
+            config,
+            retry_backoff: Duration::from_secs(1),
+        })
     };
 
-    let exec: task::executor::EspExecutor<8, edge_executor::Local> =
+    let exec: task::executor::EspExecutor<16, edge_executor::Local> =
         task::executor::EspExecutor::new();
     let mut tasks = heapless::Vec::new();
-    exec.spawn_local_collect(wifi.run(sysloop.clone(), neopixel), &mut tasks)
+    exec.spawn_local_collect(wifi.run(sysloop.clone()), &mut tasks)
         .context("failed to spawn wifi bg task")?;
+    if let Some(neopixel) = status_led {
+        exec.spawn_local_collect(indicator::run(neopixel), &mut tasks)
+            .context("failed to spawn neopixel task")?;
+    }
+    exec.spawn_local_collect(server.run(), &mut tasks)
+        .context("failed to spawn HTTP server task")?;
+    for (scanner, supervisor) in scanners.into_iter().zip(bus_supervisors) {
+        exec.spawn_local_collect(scanner.run(), &mut tasks)
+            .context("failed to spawn I2C scanner task")?;
+        exec.spawn_local_collect(supervisor.run(), &mut tasks)
+            .context("failed to spawn I2C bus supervisor task")?;
+    }
+    exec.spawn_local_collect(history::History::run(history, &METRICS), &mut tasks)
+        .context("failed to spawn history task")?;
+    // if this firmware was just installed by an OTA update, roll back to the
+    // previous firmware unless the WiFi and sensors come up.
+    exec.spawn_local_collect(
+        ota::confirm_or_rollback(Duration::from_secs(5 * 60)),
+        &mut tasks,
+    )
+    .context("failed to spawn OTA health check task")?;
+    if config.mqtt.enabled {
+        exec.spawn_local_collect(
+            mqtt::run(&config.mqtt, &config.mdns.hostname, &METRICS, scd30_ctrl),
+            &mut tasks,
+        )
+        .context("failed to spawn MQTT task")?;
+    }
 
     #[cfg(feature = "sensor-scd30")]
-    exec.spawn_local_collect(sensor_mangler.run::<scd30::Scd30>(scd30_rx), &mut tasks)
-        .context("failed to spawn SCD30 task")?;
+    exec.spawn_local_collect(
+        sensor_mangler(scd30::Scd30::NAME)?
+            .run::<scd30::Scd30>(scd30::Scd30::ADDRESSES[0], scd30_rx),
+        &mut tasks,
+    )
+    .context("failed to spawn SCD30 task")?;
 
     #[cfg(feature = "sensor-pmsa003i")]
-    let _pmsa003i_control = {
-        let (tx, rx) = actor::channel(10);
-        exec.spawn_local_collect(sensor_mangler.run::<pmsa003i::Pmsa003i>(rx), &mut tasks)
-            .context("failed to spawn PMSA003I task")?;
-        tx
-    };
+    {
+        let addr = pmsa003i::Pmsa003i::ADDRESSES[0];
+        let (_, rx) = sensor::CONTROLS.channel::<pmsa003i::Pmsa003i>(addr);
+        exec.spawn_local_collect(
+            sensor_mangler(pmsa003i::Pmsa003i::NAME)?.run::<pmsa003i::Pmsa003i>(addr, rx),
+            &mut tasks,
+        )
+        .context("failed to spawn PMSA003I task")?;
+    }
 
+    // there may be up to two BME680s on the bus, so run a sensor manager for
+    // each address.
     #[cfg(feature = "sensor-bme680")]
-    let _bme680_control = {
-        let (tx, rx) = actor::channel(10);
-        exec.spawn_local_collect(sensor_mangler.run::<bme680::Bme680>(rx), &mut tasks)
-            .context("failed to spawn BME680 task")?;
-        tx
-    };
+    {
+        let bme680_mangler = sensor_mangler(bme680::Bme680::NAME)?;
+        for &addr in bme680::Bme680::ADDRESSES {
+            let (_, rx) = sensor::CONTROLS.channel::<bme680::Bme680>(addr);
+            exec.spawn_local_collect(bme680_mangler.run::<bme680::Bme680>(addr, rx), &mut tasks)
+                .with_context(|| format!("failed to spawn BME680 {addr:#04x} task"))?;
+        }
+    }
 
     #[cfg(feature = "sensor-sgp30")]
-    let _sgp30_control = {
-        let (tx, rx) = actor::channel(10);
-        exec.spawn_local_collect(sensor_mangler.run::<sgp30::Sgp30>(rx), &mut tasks)
-            .context("failed to spawn SGP30 task")?;
-        tx
-    };
+    exec.spawn_local_collect(
+        sensor_mangler(sgp30::Sgp30::NAME)?
+            .run::<sgp30::Sgp30>(sgp30::Sgp30::ADDRESSES[0], sgp30_rx),
+        &mut tasks,
+    )
+    .context("failed to spawn SGP30 task")?;
 
     exec.run_tasks(|| true, &mut tasks);
     Ok(())
 }
+
+/// Installs the I2C driver for every bus in `config`.
+///
+/// If any bus can't be brought up, the buses that were already brought up are
+/// dropped, releasing their peripherals.
+#[cfg(target_os = "espidf")]
+fn start_buses(
+    peripherals: &mut bus::Peripherals,
+    config: &config::Config,
+) -> anyhow::Result<Vec<bus::I2c>> {
+    config
+        .i2c
+        .buses
+        .iter()
+        .map(|bus_config| bus::I2c::new(peripherals, bus_config))
+        .collect()
+}
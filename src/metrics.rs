@@ -1,107 +1,249 @@
 pub use tinymetrics::{Counter, Gauge};
 
+use crate::sensor::{Status, STATUSES};
 use serde::{Serialize, Serializer};
 use std::fmt;
 use tinymetrics::{CounterFamily, FmtLabels, GaugeFamily, MetricBuilder, MetricFamily};
 
 const MAX_METRICS: usize = 4;
+const MAX_SENSORS: usize = 8;
+const MAX_BUSES: usize = 2;
 
 #[derive(Debug, serde::Serialize)]
 pub struct SensorMetrics {
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub temp: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub co2: GaugeFamily<'static, 2, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub eco2: GaugeFamily<'static, 2, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub rel_humidity: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub abs_humidity: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub pressure: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub gas_resistance: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub tvoc: GaugeFamily<'static, MAX_METRICS, SensorLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
+    pub h2_raw: GaugeFamily<'static, 2, SensorLabel>,
+    #[serde(serialize_with = "serialize_live_gauges")]
+    pub ethanol_raw: GaugeFamily<'static, 2, SensorLabel>,
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub pm_conc: GaugeFamily<'static, 3, DiameterLabel>,
-    #[serde(serialize_with = "serialize_metric")]
+    #[serde(serialize_with = "serialize_live_gauges")]
     pub pm_count: GaugeFamily<'static, 6, DiameterLabel>,
+    #[serde(skip)]
+    pub sensor_up: GaugeFamily<'static, MAX_SENSORS, SensorLabel>,
+    #[serde(serialize_with = "serialize_metric")]
+    pub sensor_errors: CounterFamily<'static, MAX_SENSORS, SensorLabel>,
     #[serde(serialize_with = "serialize_metric")]
-    pub sensor_errors: CounterFamily<'static, MAX_METRICS, SensorLabel>,
+    pub bus_recoveries: CounterFamily<'static, MAX_BUSES, BusLabel>,
 }
 
-#[derive(Debug, Eq, PartialEq, serde::Serialize)]
-#[serde(transparent)]
-pub struct SensorLabel(pub &'static str);
+/// Identifies a particular sensor instance on the I2C bus.
+///
+/// In the Prometheus exposition format, this is formatted as separate
+/// `sensor` and `address` labels. When serialized (e.g. as JSON), it's
+/// represented as a string like `"BME680@0x77"`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SensorLabel {
+    pub sensor: &'static str,
+    pub address: u8,
+    /// The name of the sensor whose status determines whether this sensor's
+    /// readings are reported. This is the same as `sensor`, unless this
+    /// sensor is part of another one (such as the SCD30's onboard SHT31).
+    pub owner: &'static str,
+}
+
+/// Identifies a particulate matter reading by particle diameter (in µm), and
+/// the sensor instance it comes from.
+///
+/// In the Prometheus exposition format, this is formatted as a `diameter`
+/// label along with the sensor's labels. Otherwise, it's displayed and
+/// serialized as just the diameter, like `"2.5"`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DiameterLabel {
+    pub diameter: &'static str,
+    pub sensor: SensorLabel,
+}
 
-#[derive(Debug, Eq, PartialEq, serde::Serialize)]
+/// Identifies an I2C bus.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize)]
 #[serde(transparent)]
-pub struct DiameterLabel(pub &'static str);
+pub struct BusLabel(pub &'static str);
+
+/// Labels which identify the sensor that a gauge's readings come from.
+pub trait SensorLabels {
+    fn sensor_label(&self) -> SensorLabel;
+}
+
+/// The name, help text, and unit of a family of sensor readings.
+struct Desc {
+    name: &'static str,
+    help: &'static str,
+    unit: &'static str,
+}
+
+const TEMP: Desc = Desc {
+    name: "temperature_degrees_celcius",
+    help: "Temperature in degrees Celcius.",
+    unit: "celcius",
+};
+const CO2: Desc = Desc {
+    name: "co2_ppm",
+    help: "CO2 in parts per million (ppm).",
+    unit: "ppm",
+};
+const ECO2: Desc = Desc {
+    name: "eco2_ppm",
+    help: "VOC equivalent CO2 (eCO2) calculated by a tVOC sensor, in parts per million (ppm).",
+    unit: "ppm",
+};
+const REL_HUMIDITY: Desc = Desc {
+    name: "humidity_percent",
+    help: "Relative humidity (RH) percentage.",
+    unit: "percent",
+};
+const ABS_HUMIDITY: Desc = Desc {
+    name: "absolute_humidity_grams_m3",
+    help: "Absolute humidity in grams per cubic meter.",
+    unit: "g/m^3",
+};
+const PRESSURE: Desc = Desc {
+    name: "pressure_hpa",
+    help: "Barometric pressure, in hectopascals (hPa).",
+    unit: "hPa",
+};
+const GAS_RESISTANCE: Desc = Desc {
+    name: "gas_resistance_ohms",
+    help: "BME680 VOC sensor resistance, in Ohms.",
+    unit: "Ohms",
+};
+const TVOC: Desc = Desc {
+    name: "tvoc_ppb",
+    help: "Total Volatile Organic Compounds (VOC) in parts per billion (ppb)",
+    unit: "ppb",
+};
+const H2_RAW: Desc = Desc {
+    name: "sgp30_h2_raw",
+    help: "SGP30 raw H2 signal. Only updated when the raw signals are measured on request.",
+    unit: "ticks",
+};
+const ETHANOL_RAW: Desc = Desc {
+    name: "sgp30_ethanol_raw",
+    help: "SGP30 raw ethanol signal. Only updated when the raw signals are measured on request.",
+    unit: "ticks",
+};
+const PM_CONC: Desc = Desc {
+    name: "pm_concentration_ug_m3",
+    help: "Particulate matter concentration in ug/m^3",
+    unit: "ug/m^3",
+};
+const PM_COUNT: Desc = Desc {
+    name: "pm_count",
+    help: "Particulate matter count per 0.1L of air.",
+    unit: "particulates per 0.1L",
+};
+
+/// Builds a labeled gauge family from a [`Desc`].
+macro_rules! gauge_family {
+    ($desc:ident, $label:ty, $len:literal) => {
+        MetricBuilder::new($desc.name)
+            .with_help($desc.help)
+            .with_unit($desc.unit)
+            .build_labeled::<_, $label, $len>()
+    };
+}
 
 impl SensorMetrics {
     pub const fn new() -> Self {
         Self {
-            temp: MetricBuilder::new("temperature_degrees_celcius")
-                .with_help("Temperature in degrees Celcius.")
-                .with_unit("celcius")
-                .build_labeled::<_, SensorLabel, 4>(),
-            co2: MetricBuilder::new("co2_ppm")
-                .with_help("CO2 in parts per million (ppm).")
-                .with_unit("ppm")
-                .build_labeled::<_, SensorLabel, 2>(),
-            eco2: MetricBuilder::new("eco2_ppm")
-                .with_help("VOC equivalent CO2 (eCO2) calculated by a tVOC sensor, in parts per million (ppm).")
-                .with_unit("ppm")
-                .build_labeled::<_, SensorLabel, 2>(),
-            rel_humidity: MetricBuilder::new("humidity_percent")
-                .with_help("Relative humidity (RH) percentage.")
-                .with_unit("percent")
-                .build_labeled::<_, SensorLabel, 4>(),
-            abs_humidity: MetricBuilder::new("absolute_humidity_grams_m3")
-                .with_help("Absolute humidity in grams per cubic meter.")
-                .with_unit("g/m^3")
-                .build_labeled::<_, SensorLabel, 4>(),
-            pressure: MetricBuilder::new("pressure_hpa")
-                .with_help("Barometric pressure, in hectopascals (hPa).")
-                .with_unit("hPa")
-                .build_labeled::<_, SensorLabel, 4>(),
-            gas_resistance: MetricBuilder::new("gas_resistance_ohms")
-                .with_help("BME680 VOC sensor resistance, in Ohms.")
-                .with_unit("Ohms")
-                .build_labeled::<_, SensorLabel, 4>(),
-            tvoc: MetricBuilder::new("tvoc_ppb")
-                .with_help("Total Volatile Organic Compounds (VOC) in parts per billion (ppb)")
-                .with_unit("ppb")
-                .build_labeled::<_, SensorLabel, 4>(),
-            pm_conc: MetricBuilder::new("pm_concentration_ug_m3")
-                .with_help("Particulate matter concentration in ug/m^3")
-                .with_unit("ug/m^3")
-                .build_labeled::<_, DiameterLabel, 3>(),
-            pm_count: MetricBuilder::new("pm_count")
-                .with_help("Particulate matter count per 0.1L of air.")
-                .with_unit("particulates per 0.1L")
-                .build_labeled::<_, DiameterLabel, 6>(),
+            temp: gauge_family!(TEMP, SensorLabel, 4),
+            co2: gauge_family!(CO2, SensorLabel, 2),
+            eco2: gauge_family!(ECO2, SensorLabel, 2),
+            rel_humidity: gauge_family!(REL_HUMIDITY, SensorLabel, 4),
+            abs_humidity: gauge_family!(ABS_HUMIDITY, SensorLabel, 4),
+            pressure: gauge_family!(PRESSURE, SensorLabel, 4),
+            gas_resistance: gauge_family!(GAS_RESISTANCE, SensorLabel, 4),
+            tvoc: gauge_family!(TVOC, SensorLabel, 4),
+            h2_raw: gauge_family!(H2_RAW, SensorLabel, 2),
+            ethanol_raw: gauge_family!(ETHANOL_RAW, SensorLabel, 2),
+            pm_conc: gauge_family!(PM_CONC, DiameterLabel, 3),
+            pm_count: gauge_family!(PM_COUNT, DiameterLabel, 6),
+            sensor_up: MetricBuilder::new("sensor_up")
+                .with_help("Whether a sensor is up (1) or down or missing (0).")
+                .build_labeled::<_, SensorLabel, MAX_SENSORS>(),
             sensor_errors: MetricBuilder::new("sensor_error_count")
                 .with_help("Count of I2C errors that occurred while talking to a sensor")
-                .build_labeled::<_, SensorLabel, 4>(),
+                .build_labeled::<_, SensorLabel, MAX_SENSORS>(),
+            bus_recoveries: MetricBuilder::new("i2c_bus_recovery_count")
+                .with_help("Count of times an I2C bus was recovered after it got stuck")
+                .build_labeled::<_, BusLabel, MAX_BUSES>(),
         }
     }
 
+    /// Formats the metrics in the Prometheus text format.
+    ///
+    /// Readings from sensors that aren't currently up are omitted, so that a
+    /// sensor that has gone down doesn't keep reporting its last reading
+    /// forever.
     pub fn fmt_metrics(&self, f: &mut impl fmt::Write) -> fmt::Result {
-        self.temp.fmt_metric(f)?;
-        self.co2.fmt_metric(f)?;
-        self.rel_humidity.fmt_metric(f)?;
-        self.abs_humidity.fmt_metric(f)?;
-        self.pressure.fmt_metric(f)?;
-        self.gas_resistance.fmt_metric(f)?;
-        self.tvoc.fmt_metric(f)?;
-        self.pm_conc.fmt_metric(f)?;
-        self.pm_count.fmt_metric(f)?;
+        fmt_gauges(f, &TEMP, &self.temp)?;
+        fmt_gauges(f, &CO2, &self.co2)?;
+        fmt_gauges(f, &ECO2, &self.eco2)?;
+        fmt_gauges(f, &REL_HUMIDITY, &self.rel_humidity)?;
+        fmt_gauges(f, &ABS_HUMIDITY, &self.abs_humidity)?;
+        fmt_gauges(f, &PRESSURE, &self.pressure)?;
+        fmt_gauges(f, &GAS_RESISTANCE, &self.gas_resistance)?;
+        fmt_gauges(f, &TVOC, &self.tvoc)?;
+        fmt_gauges(f, &H2_RAW, &self.h2_raw)?;
+        fmt_gauges(f, &ETHANOL_RAW, &self.ethanol_raw)?;
+        fmt_gauges(f, &PM_CONC, &self.pm_conc)?;
+        fmt_gauges(f, &PM_COUNT, &self.pm_count)?;
+        self.sensor_up.fmt_metric(f)?;
         self.sensor_errors.fmt_metric(f)?;
+        self.bus_recoveries.fmt_metric(f)?;
         Ok(())
     }
+
+    /// Calls `f` with the name, label, and gauge of every registered gauge
+    /// whose sensor is currently up.
+    ///
+    /// The names passed to `f` are the same as the field names used when
+    /// serializing the metrics as JSON.
+    pub fn for_each_gauge(
+        &'static self,
+        mut f: impl FnMut(&'static str, &dyn fmt::Display, &'static Gauge),
+    ) {
+        macro_rules! for_each {
+            ($($family:ident),+) => {
+                $(
+                    for (label, gauge) in self.$family.metrics().iter() {
+                        if is_up(label) {
+                            f(stringify!($family), label, gauge);
+                        }
+                    }
+                )+
+            }
+        }
+        for_each!(
+            temp,
+            co2,
+            eco2,
+            rel_humidity,
+            abs_humidity,
+            pressure,
+            gas_resistance,
+            tvoc,
+            h2_raw,
+            ethanol_raw,
+            pm_conc,
+            pm_count
+        );
+    }
 }
 
 impl fmt::Display for SensorMetrics {
@@ -112,15 +254,94 @@ impl fmt::Display for SensorMetrics {
 
 // === impl Label ===
 
+impl SensorLabel {
+    pub const fn new(sensor: &'static str, address: u8) -> Self {
+        Self {
+            sensor,
+            address,
+            owner: sensor,
+        }
+    }
+
+    /// Returns a label for a sensor that's built into the `owner` sensor at
+    /// `address`, and is only up while `owner` is.
+    pub const fn onboard(sensor: &'static str, owner: &'static str, address: u8) -> Self {
+        Self {
+            sensor,
+            address,
+            owner,
+        }
+    }
+}
+
 impl FmtLabels for SensorLabel {
     fn fmt_labels(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
-        write!(writer, "sensor=\"{}\"", self.0)
+        write!(
+            writer,
+            "sensor=\"{}\",address=\"{:#04x}\"",
+            self.sensor, self.address
+        )
+    }
+}
+
+impl fmt::Display for SensorLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{:#04x}", self.sensor, self.address)
+    }
+}
+
+impl Serialize for SensorLabel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl DiameterLabel {
+    pub const fn new(diameter: &'static str, sensor: SensorLabel) -> Self {
+        Self { diameter, sensor }
+    }
+}
+
+impl fmt::Display for DiameterLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.diameter)
+    }
+}
+
+impl Serialize for DiameterLabel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.diameter)
+    }
+}
+
+impl SensorLabels for SensorLabel {
+    fn sensor_label(&self) -> SensorLabel {
+        SensorLabel::new(self.owner, self.address)
+    }
+}
+
+impl SensorLabels for DiameterLabel {
+    fn sensor_label(&self) -> SensorLabel {
+        self.sensor.sensor_label()
+    }
+}
+
+impl fmt::Display for BusLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl FmtLabels for BusLabel {
+    fn fmt_labels(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
+        write!(writer, "bus=\"{}\"", self.0)
     }
 }
 
 impl FmtLabels for DiameterLabel {
     fn fmt_labels(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
-        write!(writer, "diameter=\"{}\",sensor=\"PMSA003I\"", self.0)
+        write!(writer, "diameter=\"{}\",", self.diameter)?;
+        self.sensor.fmt_labels(writer)
     }
 }
 
@@ -135,3 +356,125 @@ where
 {
     metric.metrics().serialize(serializer)
 }
+
+fn serialize_live_gauges<S, L, const METRICS: usize>(
+    metric: &GaugeFamily<'_, METRICS, L>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    L: Serialize + SensorLabels,
+{
+    serializer.collect_map(metric.metrics().iter().filter(|(label, _)| is_up(label)))
+}
+
+/// Formats the gauges in `family` whose sensors are up.
+fn fmt_gauges<L, const METRICS: usize>(
+    f: &mut impl fmt::Write,
+    desc: &Desc,
+    family: &GaugeFamily<'_, METRICS, L>,
+) -> fmt::Result
+where
+    L: FmtLabels + SensorLabels,
+{
+    let Desc { name, help, .. } = desc;
+    writeln!(f, "# HELP {name} {help}")?;
+    writeln!(f, "# TYPE {name} gauge")?;
+    for (label, gauge) in family.metrics().iter() {
+        if is_up(label) {
+            write!(f, "{name}{{")?;
+            label.fmt_labels(f)?;
+            writeln!(f, "}} {}", gauge.value())?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if the sensor that `label` belongs to is up.
+fn is_up(label: &impl SensorLabels) -> bool {
+    let sensor = label.sensor_label();
+    STATUSES
+        .iter()
+        .any(|(status_label, status)| *status_label == sensor && status.status().is_up())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn omits_gauges_of_sensors_that_are_not_up() {
+        let metrics = SensorMetrics::new();
+        let up = SensorLabel::new("BME680", 0x76);
+        let down = SensorLabel::new("BME680", 0x77);
+        STATUSES
+            .get_or_register_default(up)
+            .unwrap()
+            .set_status(Status::Up);
+        STATUSES
+            .get_or_register_default(down)
+            .unwrap()
+            .set_status(Status::BusError);
+        metrics.pressure.register(up).unwrap().set_value(1013.0);
+        metrics.pressure.register(down).unwrap().set_value(990.0);
+
+        let text = metrics.to_string();
+        assert!(text.contains("pressure_hpa{sensor=\"BME680\",address=\"0x76\"} 1013"));
+        assert!(!text.contains("address=\"0x77\""));
+
+        let json = serde_json::to_value(&metrics).unwrap();
+        let pressure = json["pressure"].as_object().unwrap();
+        assert!(pressure.contains_key("BME680@0x76"));
+        assert!(!pressure.contains_key("BME680@0x77"));
+    }
+
+    #[test]
+    fn onboard_sensors_are_up_while_their_owner_is() {
+        let metrics = SensorMetrics::new();
+        // use an address that no other test registers a status for.
+        let scd30 = SensorLabel::new("SCD30", 0x62);
+        let sht31 = SensorLabel::onboard("SHT31", "SCD30", 0x62);
+        let status = STATUSES.get_or_register_default(scd30).unwrap();
+        metrics.temp.register(sht31).unwrap().set_value(21.5);
+        metrics
+            .rel_humidity
+            .register(sht31)
+            .unwrap()
+            .set_value(40.0);
+
+        status.set_status(Status::Up);
+        let text = metrics.to_string();
+        assert!(
+            text.contains("temperature_degrees_celcius{sensor=\"SHT31\",address=\"0x62\"} 21.5")
+        );
+        assert!(text.contains("humidity_percent{sensor=\"SHT31\",address=\"0x62\"} 40"));
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert!(json["temp"].as_object().unwrap().contains_key("SHT31@0x62"));
+
+        status.set_status(Status::BusError);
+        assert!(!metrics.to_string().contains("SHT31"));
+    }
+
+    #[test]
+    fn diameter_labels_include_the_sensor() {
+        let metrics = SensorMetrics::new();
+        // use an address that no other test registers a status for.
+        let pmsa003i = SensorLabel::new("PMSA003I", 0x13);
+        STATUSES
+            .get_or_register_default(pmsa003i)
+            .unwrap()
+            .set_status(Status::Up);
+        metrics
+            .pm_conc
+            .register(DiameterLabel::new("2.5", pmsa003i))
+            .unwrap()
+            .set_value(3.0);
+
+        let text = metrics.to_string();
+        assert!(text.contains(
+            "pm_concentration_ug_m3{diameter=\"2.5\",sensor=\"PMSA003I\",address=\"0x13\"} 3"
+        ));
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["pm_conc"]["2.5"], 3.0);
+    }
+}
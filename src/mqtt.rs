@@ -0,0 +1,375 @@
+//! Publishes sensor readings over MQTT, with Home Assistant discovery.
+//!
+//! Every gauge in [`SensorMetrics`] is published to
+//! `{topic_prefix}/{node_id}/{metric}/{label}`, and every sensor's
+//! [`Status`](crate::sensor::Status) is published to
+//! `{topic_prefix}/{node_id}/status/{label}`. For each gauge and sensor, a
+//! [Home Assistant MQTT discovery][discovery] config message is published, so
+//! that the node's sensors show up in Home Assistant automatically.
+//!
+//! Sensor control messages can be sent by publishing to
+//! `{topic_prefix}/{node_id}/command/{sensor}/{command}`. Any sensor can be
+//! sent a JSON control message by publishing it to
+//! `{topic_prefix}/{node_id}/command/{sensor}/control`, the same as the HTTP
+//! `/sensors/{sensor}/control` route.
+//!
+//! [discovery]: https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+use crate::{scd30, sensor, SensorMetrics};
+use anyhow::Context;
+
+// the MQTT client itself requires ESP-IDF, but publishing to it and parsing
+// commands from it don't.
+#[cfg(target_os = "espidf")]
+mod esp;
+#[cfg(target_os = "espidf")]
+pub use self::esp::run;
+
+/// Something that MQTT messages can be published to.
+///
+/// This is implemented by ESP-IDF's `EspMqttClient`, and may be implemented by an
+/// in-memory stand-in for a broker in tests.
+pub trait Sink {
+    fn send(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Publishes sensor readings and statuses to an MQTT [`Sink`].
+pub struct Publisher<S> {
+    sink: S,
+    metrics: &'static SensorMetrics,
+    /// `{topic_prefix}/{node_id}`
+    base_topic: String,
+    discovery_prefix: String,
+    node_id: String,
+    /// Gauges for which a discovery config has been published.
+    announced_gauges: Vec<*const crate::metrics::Gauge>,
+    /// Sensors for which a discovery config has been published.
+    announced_statuses: Vec<String>,
+}
+
+/// A control command received over MQTT.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Scd30(scd30::ControlMessage),
+    /// A JSON control message for every instance of `sensor`, to be sent by
+    /// [`CONTROLS.send`](sensor::Controls::send).
+    Control {
+        sensor: String,
+        json: Vec<u8>,
+    },
+}
+
+const TARGET: &str = "eclss::mqtt";
+
+/// Parses a command received on `topic`.
+///
+/// Returns `Ok(None)` if the topic is not a command topic.
+pub fn parse_command(
+    command_prefix: &str,
+    topic: &str,
+    payload: &[u8],
+) -> anyhow::Result<Option<Command>> {
+    let Some(command) = topic.strip_prefix(command_prefix) else {
+        return Ok(None);
+    };
+    if let Some(sensor) = command.strip_suffix("/control") {
+        return Ok(Some(Command::Control {
+            sensor: sensor.to_string(),
+            json: payload.to_vec(),
+        }));
+    }
+    let payload = std::str::from_utf8(payload)
+        .context("payload is not UTF-8")?
+        .trim();
+    let parse_u16 = || {
+        payload
+            .parse::<u16>()
+            .with_context(|| format!("invalid payload {payload:?}, expected a number"))
+    };
+
+    let cmd = match command {
+        "scd30/calibrate" => scd30::ControlMessage::ForceCalibrate { ppm: parse_u16()? },
+        "scd30/altitude" => scd30::ControlMessage::SetAltOffset(parse_u16()?),
+        "scd30/measurement_interval" => {
+            scd30::ControlMessage::SetMeasurementInterval { secs: parse_u16()? }
+        }
+        "scd30/reset" => scd30::ControlMessage::SoftReset,
+        _ => anyhow::bail!("unknown command {command:?}"),
+    };
+    Ok(Some(Command::Scd30(cmd)))
+}
+
+// === impl Publisher ===
+
+impl<S: Sink> Publisher<S> {
+    pub fn new(
+        sink: S,
+        metrics: &'static SensorMetrics,
+        topic_prefix: &str,
+        discovery_prefix: &str,
+        node_id: &str,
+    ) -> Self {
+        Self {
+            sink,
+            metrics,
+            base_topic: format!("{topic_prefix}/{node_id}"),
+            discovery_prefix: discovery_prefix.to_string(),
+            node_id: node_id.to_string(),
+            announced_gauges: Vec::new(),
+            announced_statuses: Vec::new(),
+        }
+    }
+
+    /// Forget which discovery configs have been published, so that they are
+    /// published again.
+    pub fn reset_discovery(&mut self) {
+        self.announced_gauges.clear();
+        self.announced_statuses.clear();
+    }
+
+    /// Publishes the current value of every gauge and the status of every
+    /// sensor, along with discovery configs for any gauges and sensors that
+    /// haven't been announced yet.
+    pub fn publish(&mut self) -> anyhow::Result<()> {
+        let mut gauges = Vec::new();
+        self.metrics.for_each_gauge(|metric, label, gauge| {
+            gauges.push((metric, topic_segment(&label.to_string()), gauge))
+        });
+
+        for (metric, label, gauge) in gauges {
+            let state_topic = format!("{}/{metric}/{label}", self.base_topic);
+            let ptr = gauge as *const _;
+            if !self.announced_gauges.contains(&ptr) {
+                self.announce(metric, &label, &state_topic)?;
+                self.announced_gauges.push(ptr);
+            }
+
+            self.sink
+                .send(&state_topic, false, gauge.value().to_string().as_bytes())?;
+        }
+
+        for (label, status) in sensor::STATUSES.iter() {
+            let label = topic_segment(&label.to_string());
+            let state_topic = format!("{}/status/{label}", self.base_topic);
+            if !self.announced_statuses.contains(&label) {
+                self.announce("status", &label, &state_topic)?;
+                self.announced_statuses.push(label);
+            }
+
+            let status = format!("{:?}", status.status());
+            self.sink.send(&state_topic, true, status.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn announce(&mut self, metric: &str, label: &str, state_topic: &str) -> anyhow::Result<()> {
+        let object_id = format!("{metric}_{label}");
+        let unique_id = format!("{}_{object_id}", self.node_id);
+        let (unit, device_class) = metric_info(metric, label);
+        let mut config = serde_json::json!({
+            "name": format!("{} {metric} ({label})", self.node_id),
+            "unique_id": unique_id,
+            "object_id": unique_id,
+            "state_topic": state_topic,
+            "availability_topic": format!("{}/availability", self.base_topic),
+            "device": {
+                "identifiers": [self.node_id],
+                "name": self.node_id,
+                "manufacturer": "ECLSS",
+                "model": "ECLSS",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        if let Some(unit) = unit {
+            config["unit_of_measurement"] = unit.into();
+            config["state_class"] = "measurement".into();
+        }
+        if let Some(device_class) = device_class {
+            config["device_class"] = device_class.into();
+        }
+
+        let topic = format!(
+            "{}/sensor/{}/{object_id}/config",
+            self.discovery_prefix, self.node_id
+        );
+        log::debug!(target: TARGET, "announcing {object_id} on {topic}");
+        self.sink
+            .send(&topic, true, serde_json::to_string(&config)?.as_bytes())
+    }
+}
+
+/// Returns the unit of measurement and Home Assistant device class for a
+/// metric.
+fn metric_info(metric: &str, label: &str) -> (Option<&'static str>, Option<&'static str>) {
+    match metric {
+        "temp" => (Some("°C"), Some("temperature")),
+        "co2" => (Some("ppm"), Some("carbon_dioxide")),
+        "eco2" => (Some("ppm"), None),
+        "rel_humidity" => (Some("%"), Some("humidity")),
+        "abs_humidity" => (Some("g/m³"), None),
+        "pressure" => (Some("hPa"), Some("atmospheric_pressure")),
+        "gas_resistance" => (Some("Ω"), None),
+        "tvoc" => (Some("ppb"), None),
+        "pm_conc" => {
+            let class = match label {
+                "1_0" => Some("pm1"),
+                "2_5" => Some("pm25"),
+                "10_0" => Some("pm10"),
+                _ => None,
+            };
+            (Some("µg/m³"), class)
+        }
+        "pm_count" => (Some("particles/0.1L"), None),
+        _ => (None, None),
+    }
+}
+
+/// Converts a label into something that's safe to use in an MQTT topic and
+/// a Home Assistant object ID.
+fn topic_segment(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::{DiameterLabel, SensorLabel};
+
+    /// An in-memory stand-in for an MQTT broker, which records every message
+    /// published to it.
+    #[derive(Default)]
+    struct Broker {
+        messages: Vec<(String, bool, String)>,
+    }
+
+    impl Sink for &mut Broker {
+        fn send(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<()> {
+            self.messages.push((
+                topic.to_string(),
+                retain,
+                String::from_utf8(payload.to_vec())?,
+            ));
+            Ok(())
+        }
+    }
+
+    impl Broker {
+        fn retained(&self, topic: &str) -> Option<&str> {
+            self.messages
+                .iter()
+                .rev()
+                .find(|(t, retain, _)| t == topic && *retain)
+                .map(|(_, _, payload)| payload.as_str())
+        }
+
+        fn last(&self, topic: &str) -> Option<&str> {
+            self.messages
+                .iter()
+                .rev()
+                .find(|(t, _, _)| t == topic)
+                .map(|(_, _, payload)| payload.as_str())
+        }
+    }
+
+    #[test]
+    fn publishes_gauges_and_discovery() {
+        static METRICS: SensorMetrics = SensorMetrics::new();
+        // only gauges for sensors that are up are published. use addresses
+        // that no other test registers a status for.
+        let pmsa003i = SensorLabel::new("PMSA003I", 0x7d);
+        for label in [SensorLabel::new("SCD30", 0x7e), pmsa003i] {
+            sensor::STATUSES
+                .get_or_register_default(label)
+                .unwrap()
+                .set_status(sensor::Status::Up);
+        }
+        METRICS
+            .co2
+            .register(SensorLabel::new("SCD30", 0x7e))
+            .unwrap()
+            .set_value(420.0);
+        METRICS
+            .pm_conc
+            .register(DiameterLabel::new("2.5", pmsa003i))
+            .unwrap()
+            .set_value(3.0);
+
+        let mut broker = Broker::default();
+        let mut publisher = Publisher::new(&mut broker, &METRICS, "eclss", "homeassistant", "node");
+        publisher.publish().unwrap();
+        // publishing again shouldn't re-announce anything
+        publisher.publish().unwrap();
+        drop(publisher);
+
+        assert_eq!(broker.last("eclss/node/co2/scd30_0x7e"), Some("420"));
+        assert_eq!(broker.last("eclss/node/pm_conc/2_5"), Some("3"));
+
+        let discovery = broker
+            .retained("homeassistant/sensor/node/co2_scd30_0x7e/config")
+            .expect("CO2 gauge must be announced");
+        let discovery: serde_json::Value = serde_json::from_str(discovery).unwrap();
+        assert_eq!(discovery["state_topic"], "eclss/node/co2/scd30_0x7e");
+        assert_eq!(discovery["device_class"], "carbon_dioxide");
+        assert_eq!(discovery["unit_of_measurement"], "ppm");
+
+        let discovery = broker
+            .retained("homeassistant/sensor/node/pm_conc_2_5/config")
+            .expect("PM2.5 gauge must be announced");
+        let discovery: serde_json::Value = serde_json::from_str(discovery).unwrap();
+        assert_eq!(discovery["device_class"], "pm25");
+
+        let announcements = broker
+            .messages
+            .iter()
+            .filter(|(topic, _, _)| topic.ends_with("/config") && !topic.contains("/status_"))
+            .count();
+        assert_eq!(announcements, 2);
+    }
+
+    #[test]
+    fn parses_commands() {
+        let prefix = "eclss/node/command/";
+        assert!(matches!(
+            parse_command(prefix, "eclss/node/command/scd30/calibrate", b"420\n"),
+            Ok(Some(Command::Scd30(
+                scd30::ControlMessage::ForceCalibrate { ppm: 420 }
+            )))
+        ));
+        assert!(matches!(
+            parse_command(prefix, "eclss/node/command/scd30/reset", b""),
+            Ok(Some(Command::Scd30(scd30::ControlMessage::SoftReset)))
+        ));
+        assert!(matches!(
+            parse_command(prefix, "eclss/other/command/scd30/reset", b""),
+            Ok(None)
+        ));
+        match parse_command(
+            prefix,
+            "eclss/node/command/sgp30/control",
+            br#""get_baseline""#,
+        ) {
+            Ok(Some(Command::Control { sensor, json })) => {
+                assert_eq!(sensor, "sgp30");
+                assert_eq!(json, br#""get_baseline""#);
+            }
+            res => panic!("expected a control message, got {res:?}"),
+        }
+        assert!(parse_command(prefix, "eclss/node/command/scd30/calibrate", b"lots").is_err());
+        assert!(parse_command(prefix, "eclss/node/command/bogus", b"").is_err());
+    }
+
+    #[test]
+    fn topic_segments_are_sanitized() {
+        assert_eq!(topic_segment("BME680@0x77"), "bme680_0x77");
+        assert_eq!(topic_segment("10.0"), "10_0");
+    }
+}
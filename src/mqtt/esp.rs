@@ -0,0 +1,151 @@
+//! The ESP-IDF MQTT client.
+use super::{parse_command, Command, Publisher, Sink, TARGET};
+use crate::{actor, config::MqttConfig, scd30, sensor, SensorMetrics};
+use anyhow::Context;
+use embassy_time::{Duration, Timer};
+use embedded_svc::mqtt::client::{Event, Message, QoS};
+use esp_idf_svc::mqtt::client::{EspMqttClient, LwtConfiguration, MqttClientConfiguration};
+use futures::{channel::mpsc, select, FutureExt, StreamExt};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+impl Sink for EspMqttClient {
+    fn send(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<()> {
+        self.publish(topic, QoS::AtLeastOnce, retain, payload)
+            .with_context(|| format!("failed to publish to {topic}"))?;
+        Ok(())
+    }
+}
+
+/// Connects to the MQTT broker in `config` and publishes sensor data until
+/// the end of time.
+pub async fn run(
+    config: &'static MqttConfig,
+    node_id: &'static str,
+    metrics: &'static SensorMetrics,
+    scd30_ctrl: actor::Client<scd30::ControlMessage, anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    let base_topic = format!("{}/{node_id}", config.topic_prefix);
+    let availability_topic = format!("{base_topic}/availability");
+    let command_topic = format!("{base_topic}/command/#");
+
+    // the MQTT client runs its own task and calls this callback from it, so
+    // forward everything we care about to this task.
+    let (mut cmd_tx, mut cmd_rx) = mpsc::channel(4);
+    let connected = Arc::new(AtomicBool::new(false));
+    let callback = {
+        let command_prefix = format!("{base_topic}/command/");
+        let connected = connected.clone();
+        move |event: &Result<Event<_>, _>| match event {
+            Ok(Event::Connected(_)) => {
+                log::info!(target: TARGET, "connected to MQTT broker");
+                connected.store(true, Ordering::Release);
+            }
+            Ok(Event::Disconnected) => {
+                log::info!(target: TARGET, "disconnected from MQTT broker");
+                connected.store(false, Ordering::Release);
+            }
+            Ok(Event::Received(msg)) => {
+                let Some(topic) = msg.topic() else { return };
+                match parse_command(&command_prefix, topic, msg.data()) {
+                    Ok(Some(cmd)) => {
+                        if let Err(error) = cmd_tx.try_send(cmd) {
+                            log::warn!(target: TARGET, "dropped MQTT command: {error}");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        log::warn!(target: TARGET, "invalid MQTT command on {topic}: {error:#}")
+                    }
+                }
+            }
+            Ok(event) => log::debug!(target: TARGET, "MQTT event: {event:?}"),
+            Err(error) => log::warn!(target: TARGET, "MQTT error: {error}"),
+        }
+    };
+
+    let mqtt_config = MqttClientConfiguration {
+        client_id: Some(node_id),
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        lwt: Some(LwtConfiguration {
+            topic: &availability_topic,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Default::default()
+    };
+    let client = EspMqttClient::new(&config.url, &mqtt_config, callback)
+        .with_context(|| format!("failed to create MQTT client for {}", config.url))?;
+    log::info!(target: TARGET, "connecting to MQTT broker at {}...", config.url);
+
+    let mut publisher = Publisher::new(
+        client,
+        metrics,
+        &config.topic_prefix,
+        &config.discovery_prefix,
+        node_id,
+    );
+    let interval = Duration::from_secs(config.publish_interval_secs.into());
+    let mut was_connected = false;
+    let mut publish_wait = Timer::after(interval);
+
+    loop {
+        select! {
+            _ = (&mut publish_wait).fuse() => {
+                publish_wait = Timer::after(interval);
+                let is_connected = connected.load(Ordering::Acquire);
+                if is_connected && !was_connected {
+                    // (re)announce ourselves; the broker may have restarted
+                    // and lost our retained messages.
+                    publisher.reset_discovery();
+                    let announced = publisher
+                        .sink
+                        .send(&availability_topic, true, b"online")
+                        .and_then(|_| {
+                            publisher
+                                .sink
+                                .subscribe(&command_topic, QoS::AtLeastOnce)
+                                .map(|_| ())
+                                .with_context(|| format!("failed to subscribe to {command_topic}"))
+                        });
+                    if let Err(error) = announced {
+                        log::warn!(target: TARGET, "{error:#}");
+                        continue;
+                    }
+                }
+                was_connected = is_connected;
+
+                if !is_connected {
+                    continue;
+                }
+
+                if let Err(error) = publisher.publish() {
+                    log::warn!(target: TARGET, "failed to publish sensor data: {error:#}");
+                }
+            },
+            cmd = cmd_rx.next() => {
+                let Some(cmd) = cmd else {
+                    anyhow::bail!("MQTT client callback was dropped");
+                };
+                log::info!(target: TARGET, "received MQTT command: {cmd:?}");
+                match cmd {
+                    Command::Scd30(msg) => match scd30_ctrl.try_request(msg).await {
+                        Ok(()) => log::info!(target: TARGET, "MQTT command succeeded"),
+                        Err(actor::TryReqError::Error(error)) => {
+                            log::warn!(target: TARGET, "MQTT command failed: {error:#}")
+                        }
+                        Err(_) => log::warn!(target: TARGET, "MQTT command failed: sensor control channel error"),
+                    },
+                    Command::Control { sensor, json } => match sensor::CONTROLS.send(&sensor, &json).await {
+                        Ok(handled) => log::info!(target: TARGET, "{handled} {sensor} instances handled the MQTT control message"),
+                        Err(error) => log::warn!(target: TARGET, "MQTT control message for {sensor} failed: {error}"),
+                    },
+                }
+            },
+        }
+    }
+}
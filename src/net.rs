@@ -16,14 +16,18 @@ use esp_idf_svc::{
 use futures::{future, FutureExt};
 use thingbuf::mpsc;
 
-use std::sync::{Arc, RwLock};
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc, RwLock,
+};
 
-use crate::{retry, ws2812};
+use crate::{config, retry};
 
 pub struct EclssWifi {
     wifi: Box<EspWifi<'static>>,
     pub access_points: AccessPoints,
     config: Configuration,
+    ap_config: AccessPointConfiguration,
     creds_rx: mpsc::Receiver<Credentials>,
     creds_tx: mpsc::Sender<Credentials>,
     state: WifiState,
@@ -37,8 +41,12 @@ pub struct Credentials {
 
 pub type AccessPoints = Arc<RwLock<Vec<AccessPointInfo>>>;
 
-#[derive(Debug, Eq, PartialEq)]
-enum WifiState {
+/// Set by [`EclssWifi::run`] whenever the WiFi state changes.
+static WIFI_STATE: AtomicU8 = AtomicU8::new(WifiState::Connecting as u8);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub(crate) enum WifiState {
     /// Waiting for an access point to be selected.
     Unconfigured,
     /// Waiting to successfully connect to an access point.
@@ -56,8 +64,10 @@ impl EclssWifi {
         modem: impl Peripheral<P = Modem> + 'static,
         sysloop: &mut EspSystemEventLoop,
         nvs: EspDefaultNvsPartition,
+        wifi_config: &config::WifiConfig,
     ) -> anyhow::Result<Self> {
         log::info!("bringing up WiFi...");
+        let ap_config = Self::access_point_config(wifi_config);
         let mut wifi = Box::new(EspWifi::new(modem, sysloop.clone(), Some(nvs))?);
 
         wifi.start()?;
@@ -70,23 +80,23 @@ impl EclssWifi {
             // can't connect to an AP without a SSID, just switch to softAP mode.
             Ok(Configuration::Client(client_config)) if client_config.ssid.is_empty() => {
                 log::info!("NVS client config has an empty SSID, starting in access point mode (is this a fresh board?)");
-                Configuration::AccessPoint(Self::access_point_config())
+                Configuration::AccessPoint(ap_config.clone())
             }
             // if a previous client configuration was saved in NVS, map it to a
             // mixed config so we can continue running an AP as well as connecting.
             Ok(Configuration::Client(client_config)) => {
-                Configuration::Mixed(client_config, Self::access_point_config())
+                Configuration::Mixed(client_config, ap_config.clone())
             }
             // if no previous configuration was saved, start in AP mode.
             Ok(Configuration::None) => {
                 log::info!("no WiFi configuration saved; starting in access point mode");
-                Configuration::AccessPoint(Self::access_point_config())
+                Configuration::AccessPoint(ap_config.clone())
             }
             // restore the previous access point or mixed configuration.
             Ok(config) => config,
             Err(error) => {
                 log::warn!("failed to load existing wifi configuration: {error}; starting in access point mode");
-                Configuration::AccessPoint(Self::access_point_config())
+                Configuration::AccessPoint(ap_config.clone())
             }
         };
 
@@ -101,6 +111,7 @@ impl EclssWifi {
             wifi,
             access_points: Arc::new(RwLock::new(access_points)),
             config,
+            ap_config,
             creds_rx,
             creds_tx,
             state,
@@ -122,11 +133,7 @@ impl EclssWifi {
         self.creds_tx.clone()
     }
 
-    pub async fn run(
-        mut self,
-        mut sysloop: EspSystemEventLoop,
-        mut npx: crate::ws2812::NeoPixel<'static>,
-    ) -> anyhow::Result<()> {
+    pub async fn run(mut self, mut sysloop: EspSystemEventLoop) -> anyhow::Result<()> {
         let mut wifi_events = pubsub::SvcReceiver::new(
             sysloop
                 .as_async()
@@ -147,17 +154,14 @@ impl EclssWifi {
         let mut has_ap_client = false;
 
         loop {
-            // set the board's neopixel to indicate the current wifi state.
-            if let Err(error) = self.state.set_neopixel_status(&mut npx) {
-                log::warn!("failed to set neopixel wifi status: {error}");
-            }
+            WIFI_STATE.store(self.state as u8, Ordering::Release);
 
             log::info!("WiFi: {:?}; polling for events...", self.state);
 
             match self.state {
                 WifiState::Error => {
                     log::info!("WiFi in error state; setting AP mode");
-                    self.configure(Configuration::AccessPoint(Self::access_point_config()))
+                    self.configure(Configuration::AccessPoint(self.ap_config.clone()))
                         .context("failed to set AP mode")?;
                     // clear reconnect backoff
                     current_backoff = future::Either::Left(future::pending());
@@ -301,7 +305,7 @@ impl EclssWifi {
                 channel,
                 ..Default::default()
             },
-            Self::access_point_config(),
+            self.ap_config.clone(),
         );
 
         self.configure(config).with_context(|| {
@@ -309,10 +313,10 @@ impl EclssWifi {
         })
     }
 
-    fn access_point_config() -> AccessPointConfiguration {
+    fn access_point_config(config: &config::WifiConfig) -> AccessPointConfiguration {
         AccessPointConfiguration {
-            ssid: "eclss".into(),
-            channel: 1,
+            ssid: config.ap_ssid.as_str().into(),
+            channel: config.ap_channel,
             ..Default::default()
         }
     }
@@ -321,28 +325,42 @@ impl EclssWifi {
 // === impl WifiState ===
 
 impl WifiState {
-    fn set_neopixel_status(&self, npx: &mut ws2812::NeoPixel) -> anyhow::Result<()> {
-        match self {
-            // no wifi configured --- orange
-            WifiState::Unconfigured => npx.set_color(255, 165, 0)?,
-            // failed to connect --- red
-            WifiState::Error => npx.set_color(255, 0, 0)?,
-            // disconnected --- orange
-            WifiState::Disconnected => npx.set_color(255, 165, 0)?,
-            // connecting --- yellow
-            WifiState::Connecting => npx.set_color(255, 255, 0)?,
-            // successfully connected --- all green across the board!
-            WifiState::Connected => npx.set_color(0, 255, 0)?,
-        };
+    /// Returns `true` if the device is reachable over WiFi: either it's
+    /// connected to an access point, or no access point has been configured
+    /// and it's serving its own softAP.
+    fn is_up(&self) -> bool {
+        matches!(self, WifiState::Connected | WifiState::Unconfigured)
+    }
 
-        Ok(())
+    /// Returns the current WiFi state, as last set by [`EclssWifi::run`].
+    pub(crate) fn current() -> Self {
+        match WIFI_STATE.load(Ordering::Acquire) {
+            s if s == WifiState::Unconfigured as u8 => WifiState::Unconfigured,
+            s if s == WifiState::Connected as u8 => WifiState::Connected,
+            s if s == WifiState::Disconnected as u8 => WifiState::Disconnected,
+            s if s == WifiState::Error as u8 => WifiState::Error,
+            _ => WifiState::Connecting,
+        }
     }
 }
 
-pub fn init_mdns(mdns: &mut EspMdns) -> anyhow::Result<()> {
-    let txt = &[("board", "esp32c3"), ("version", env!("CARGO_PKG_VERSION"))];
-    mdns.set_hostname("eclss").context("set mDNS hostname")?;
-    mdns.set_instance_name("eclss")
+/// Returns `true` if the WiFi is up, either because the device is connected to
+/// an access point, or because no access point has been configured yet and
+/// the device is serving its softAP.
+pub fn is_up() -> bool {
+    WifiState::current().is_up()
+}
+
+pub fn init_mdns(mdns: &mut EspMdns, config: &config::MdnsConfig) -> anyhow::Result<()> {
+    let board = &crate::board::BOARD;
+    let txt = &[
+        ("board", board.id),
+        ("chip", board.chip.id()),
+        ("version", env!("CARGO_PKG_VERSION")),
+    ];
+    mdns.set_hostname(&config.hostname)
+        .context("set mDNS hostname")?;
+    mdns.set_instance_name(&config.hostname)
         .context("set mDNS instance name")?;
     mdns.add_service(None, "_http", "_tcp", crate::http::HTTP_PORT, txt)
         .context("add HTTP mDNS service")?;
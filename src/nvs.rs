@@ -0,0 +1,14 @@
+//! Non-volatile storage.
+//!
+//! On the ESP32, this is ESP-IDF's NVS (non-volatile storage) library. On the
+//! host, it's an in-memory stand-in with the same interface (see
+//! [`sim::nvs`](crate::sim::nvs)), so that code which persists settings can be
+//! tested.
+
+#[cfg(target_os = "espidf")]
+pub type Nvs = esp_idf_svc::nvs::EspNvs<esp_idf_svc::nvs::NvsDefault>;
+#[cfg(target_os = "espidf")]
+pub type Partition = esp_idf_svc::nvs::EspDefaultNvsPartition;
+
+#[cfg(not(target_os = "espidf"))]
+pub use crate::sim::nvs::{MemNvs as Nvs, MemPartition as Partition};
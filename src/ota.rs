@@ -0,0 +1,202 @@
+//! Over-the-air firmware updates.
+//!
+//! Firmware images are written to whichever of the two OTA app partitions
+//! isn't currently running. Before anything is written, the image's header
+//! and application description (`esp_app_desc_t`) are checked, so that images
+//! for the wrong chip or the wrong project are rejected without erasing
+//! anything.
+//!
+//! The bootloader is built with rollback enabled, so a freshly updated image
+//! boots in the "pending verify" state. [`confirm_or_rollback`] marks it as
+//! valid once the WiFi is up and every detected sensor has been brought up
+//! (see [`sensors_settled`]); if that doesn't happen in time, it marks the
+//! image as invalid and reboots back into the previous firmware. If the new
+//! firmware crashes before it gets that far, the bootloader rolls back on the
+//! next boot.
+use crate::{board::BOARD, metrics::SensorLabel, sensor::Status};
+use serde::Serialize;
+
+// writing images to the OTA partitions requires ESP-IDF, but validating them
+// doesn't.
+#[cfg(target_os = "espidf")]
+mod esp;
+#[cfg(target_os = "espidf")]
+pub use self::esp::{confirm_or_rollback, schedule_reboot, Update, Writer};
+
+/// Information about a firmware image, read from its `esp_app_desc_t`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct AppDesc {
+    pub project_name: String,
+    pub version: String,
+    pub idf_version: String,
+    pub date: String,
+    pub time: String,
+}
+
+/// The number of bytes at the start of an image that must be received before
+/// it can be validated.
+pub const HEADER_LEN: usize = APP_DESC_OFFSET + APP_DESC_LEN;
+
+/// The first byte of every ESP-IDF app image.
+const IMAGE_MAGIC: u8 = 0xE9;
+
+/// The magic number at the start of an `esp_app_desc_t`.
+const APP_DESC_MAGIC: u32 = 0xABCD_5432;
+
+/// The app description immediately follows the image header (24 bytes) and
+/// the first segment's header (8 bytes).
+const APP_DESC_OFFSET: usize = 24 + 8;
+const APP_DESC_LEN: usize = 256;
+
+/// Parses and validates the header of a firmware image.
+///
+/// `image` must contain at least the first [`HEADER_LEN`] bytes of the image.
+/// The image is rejected if it isn't an app image for the board's chip, or if
+/// it was built from a different project than the running firmware.
+pub fn validate_image(image: &[u8]) -> anyhow::Result<AppDesc> {
+    anyhow::ensure!(
+        image.len() >= HEADER_LEN,
+        "firmware image is too short ({} bytes)",
+        image.len()
+    );
+    anyhow::ensure!(
+        image[0] == IMAGE_MAGIC,
+        "not an ESP-IDF app image (magic byte {:#04x}, expected {IMAGE_MAGIC:#04x})",
+        image[0]
+    );
+
+    let chip = BOARD.chip;
+    let chip_id = u16::from_le_bytes([image[12], image[13]]);
+    anyhow::ensure!(
+        chip_id == chip.image_chip_id(),
+        "image is for the wrong chip (chip ID {chip_id:#06x}, expected {chip})"
+    );
+
+    let desc = &image[APP_DESC_OFFSET..HEADER_LEN];
+    let magic = u32::from_le_bytes([desc[0], desc[1], desc[2], desc[3]]);
+    anyhow::ensure!(
+        magic == APP_DESC_MAGIC,
+        "image has no app description (magic {magic:#010x}, expected {APP_DESC_MAGIC:#010x})"
+    );
+
+    let app = AppDesc {
+        version: c_str(&desc[16..48]),
+        project_name: c_str(&desc[48..80]),
+        time: c_str(&desc[80..96]),
+        date: c_str(&desc[96..112]),
+        idf_version: c_str(&desc[112..144]),
+    };
+    anyhow::ensure!(
+        app.project_name == env!("CARGO_PKG_NAME"),
+        "image is for a different project ({:?}, expected {:?})",
+        app.project_name,
+        env!("CARGO_PKG_NAME"),
+    );
+
+    Ok(app)
+}
+
+/// Returns `true` if every sensor in `detected` has been brought up by its
+/// sensor task, according to `status`.
+///
+/// A sensor counts as brought up once it has left [`Status::Initializing`],
+/// even if it has failed since, or is still warming up. A faulty sensor would
+/// be just as faulty with the previous firmware, so it shouldn't cause an
+/// update to be rolled back; only a sensor whose task never gets it going
+/// should.
+pub fn sensors_settled(
+    detected: impl IntoIterator<Item = SensorLabel>,
+    status: impl Fn(&SensorLabel) -> Option<Status>,
+) -> bool {
+    detected.into_iter().all(|label| {
+        !matches!(
+            status(&label),
+            None | Some(Status::Missing | Status::Initializing)
+        )
+    })
+}
+
+/// Reads a NUL-padded C string from an `esp_app_desc_t` field.
+fn c_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHIP_ID: u16 = BOARD.chip.image_chip_id();
+
+    fn image(chip_id: u16, project: &str) -> Vec<u8> {
+        let mut image = vec![0; HEADER_LEN + 64];
+        image[0] = IMAGE_MAGIC;
+        image[12..14].copy_from_slice(&chip_id.to_le_bytes());
+        let desc = &mut image[APP_DESC_OFFSET..];
+        desc[..4].copy_from_slice(&APP_DESC_MAGIC.to_le_bytes());
+        desc[16..21].copy_from_slice(b"0.2.0");
+        desc[48..48 + project.len()].copy_from_slice(project.as_bytes());
+        desc[80..88].copy_from_slice(b"12:34:56");
+        desc[96..107].copy_from_slice(b"Oct 17 2026");
+        desc[112..118].copy_from_slice(b"v4.4.6");
+        image
+    }
+
+    #[test]
+    fn parses_app_desc() {
+        let app = validate_image(&image(CHIP_ID, "eclss")).unwrap();
+        assert_eq!(
+            app,
+            AppDesc {
+                project_name: "eclss".to_string(),
+                version: "0.2.0".to_string(),
+                idf_version: "v4.4.6".to_string(),
+                date: "Oct 17 2026".to_string(),
+                time: "12:34:56".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        // wrong chip (ESP32)
+        assert!(validate_image(&image(0x0000, "eclss")).is_err());
+        // wrong project
+        assert!(validate_image(&image(CHIP_ID, "blinky")).is_err());
+        // truncated
+        assert!(validate_image(&image(CHIP_ID, "eclss")[..64]).is_err());
+
+        let mut bad_magic = image(CHIP_ID, "eclss");
+        bad_magic[0] = 0;
+        assert!(validate_image(&bad_magic).is_err());
+
+        let mut no_desc = image(CHIP_ID, "eclss");
+        no_desc[APP_DESC_OFFSET] = 0;
+        assert!(validate_image(&no_desc).is_err());
+    }
+
+    #[test]
+    fn faulty_sensors_are_settled() {
+        let scd30 = SensorLabel::new("SCD30", 0x61);
+        let sgp30 = SensorLabel::new("SGP30", 0x58);
+        let settled = |scd30_status, sgp30_status| {
+            sensors_settled([scd30, sgp30], |label| {
+                if *label == scd30 {
+                    scd30_status
+                } else {
+                    sgp30_status
+                }
+            })
+        };
+
+        assert!(settled(Some(Status::Up), Some(Status::Calibrating)));
+        // sensor faults don't roll back an update...
+        assert!(settled(Some(Status::BusError), Some(Status::ProtocolError)));
+        assert!(settled(Some(Status::WarmingUp), Some(Status::Disabled)));
+        // ...but sensors that were never brought up do.
+        assert!(!settled(Some(Status::Up), Some(Status::Initializing)));
+        assert!(!settled(Some(Status::Missing), Some(Status::Up)));
+        assert!(!settled(None, Some(Status::Up)));
+        assert!(sensors_settled([], |_| None));
+    }
+}
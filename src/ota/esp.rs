@@ -0,0 +1,151 @@
+//! The ESP-IDF side of OTA updates: writing images to the OTA partitions,
+//! and confirming or rolling back updated firmware.
+use super::{sensors_settled, validate_image, AppDesc};
+use crate::sensor::{self, scan};
+use anyhow::Context;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_svc::ota::{Ota, OtaUpdate, SlotState};
+use esp_idf_svc::ota::{EspOta, EspOtaUpdate};
+use std::io::Write;
+
+/// A firmware update.
+///
+/// Only one update may be in progress at a time.
+pub struct Update {
+    ota: EspOta,
+}
+
+/// Writes a validated firmware image to the next OTA partition.
+pub struct Writer<'update> {
+    update: EspOtaUpdate<'update>,
+    written: usize,
+}
+
+/// How often to check whether a pending update is healthy.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const TARGET: &str = "eclss::ota";
+
+/// Waits for the running firmware to become healthy, and marks it as valid.
+///
+/// If the running firmware was just installed by an OTA update and doesn't
+/// become healthy within `timeout`, it's marked invalid and the device reboots
+/// into the previous firmware. Otherwise, this does nothing.
+pub async fn confirm_or_rollback(timeout: Duration) -> anyhow::Result<()> {
+    let mut ota = EspOta::new().context("failed to access OTA partitions")?;
+    let slot = ota
+        .get_running_slot()
+        .context("failed to get running OTA slot")?;
+    log::info!(target: TARGET, "running from {} ({:?})", slot.label, slot.state);
+    if slot.state != SlotState::Unverified {
+        return Ok(());
+    }
+
+    log::info!(target: TARGET, "firmware update pending verification; waiting up to {}s for WiFi and sensors", timeout.as_secs());
+    let deadline = Instant::now() + timeout;
+    while !is_healthy() {
+        if Instant::now() >= deadline {
+            log::error!(target: TARGET, "updated firmware did not become healthy; rolling back!");
+            let error = ota.mark_running_slot_invalid_and_reboot();
+            return Err(error).context("failed to roll back firmware update");
+        }
+        Timer::after(HEALTH_CHECK_INTERVAL).await;
+    }
+
+    ota.mark_running_slot_valid()
+        .context("failed to mark firmware as valid")?;
+    log::info!(target: TARGET, "firmware update verified");
+    Ok(())
+}
+
+/// Returns `true` if the WiFi is up and every sensor that has been detected
+/// has been brought up (see [`sensors_settled`]).
+fn is_healthy() -> bool {
+    let detected = scan::DETECTED
+        .iter()
+        .filter(|(_, detected)| detected.is_detected())
+        .map(|(label, _)| *label);
+    crate::net::is_up()
+        && sensors_settled(detected, |label| {
+            sensor::STATUSES
+                .iter()
+                .find(|(status_label, _)| *status_label == label)
+                .map(|(_, status)| status.status())
+        })
+}
+
+/// Reboots the device after a short delay, so that a response can be sent to
+/// whoever asked for the reboot.
+pub fn schedule_reboot(delay: std::time::Duration) {
+    log::warn!(target: TARGET, "rebooting in {}ms...", delay.as_millis());
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        esp_idf_hal::reset::restart();
+    });
+}
+
+// === impl Update ===
+
+impl Update {
+    /// Starts an update.
+    ///
+    /// This fails if another update is already in progress.
+    pub fn start() -> anyhow::Result<Self> {
+        let ota = EspOta::new()
+            .context("failed to access OTA partitions; is another update in progress?")?;
+        Ok(Self { ota })
+    }
+
+    /// Validates the header of a new firmware image, and begins writing it
+    /// to the next OTA partition.
+    ///
+    /// `header` must contain at least the first [`HEADER_LEN`](super::HEADER_LEN) bytes of the
+    /// image, and is written to the partition if it's valid. Nothing is
+    /// erased if the image is invalid.
+    pub fn begin(&mut self, header: &[u8]) -> anyhow::Result<(AppDesc, Writer<'_>)> {
+        let app = validate_image(header)?;
+        log::info!(target: TARGET, "updating firmware to {app:?}");
+
+        let update = self
+            .ota
+            .initiate_update()
+            .context("failed to start firmware update")?;
+        let mut writer = Writer { update, written: 0 };
+        writer.write(header)?;
+        Ok((app, writer))
+    }
+}
+
+// === impl Writer ===
+
+impl Writer<'_> {
+    /// Writes the next chunk of the image.
+    pub fn write(&mut self, chunk: &[u8]) -> anyhow::Result<()> {
+        self.update
+            .write_all(chunk)
+            .context("failed to write firmware image")?;
+        self.written += chunk.len();
+        Ok(())
+    }
+
+    /// Finishes writing the image, and sets it to boot on the next reboot.
+    ///
+    /// This fails if the image that was written is corrupt.
+    pub fn finish(self) -> anyhow::Result<()> {
+        let written = self.written;
+        self.update
+            .complete()
+            .context("failed to finish firmware update")?;
+        log::info!(target: TARGET, "firmware update written ({written} bytes); it will boot after a reboot");
+        Ok(())
+    }
+
+    /// Abandons the update, leaving the current firmware as the boot
+    /// partition.
+    pub fn abort(self) {
+        log::warn!(target: TARGET, "aborting firmware update after {} bytes", self.written);
+        if let Err(error) = self.update.abort() {
+            log::warn!(target: TARGET, "failed to abort firmware update: {error}");
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use crate::{
-    metrics::{DiameterLabel, Gauge, SensorMetrics},
-    sensor::Sensor,
+    config::SensorConfig,
+    metrics::{DiameterLabel, Gauge, SensorLabel, SensorMetrics},
+    sensor::{Identity, Sensor, Status},
     I2cBus, I2cRef,
 };
+use anyhow::anyhow;
+use embassy_time::{Duration, Instant};
+use embedded_hal::blocking::i2c::Read;
 
 pub struct Pmsa003i {
     sensor: pmsa003i::Pmsa003i<I2cRef<'static>>,
@@ -15,29 +19,77 @@ pub struct Pmsa003i {
     particles_2_5um: &'static Gauge,
     particles_5_0um: &'static Gauge,
     particles_10_0um: &'static Gauge,
+    poll_interval: Duration,
+    started_at: Instant,
+    sensor_version: u8,
 }
 
 const NAME: &'static str = "PMSA003I";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Per the datasheet, readings aren't stable until 30 seconds after the
+/// sensor starts, because the fan has to spin up.
+const WARMUP: Duration = Duration::from_secs(30);
 
 impl Sensor for Pmsa003i {
     type ControlMessage = ();
 
     const NAME: &'static str = NAME;
+    const ADDRESSES: &'static [u8] = &[0x12];
 
-    fn init(busman: &'static I2cBus, metrics: &'static SensorMetrics) -> anyhow::Result<Self> {
-        log::info!(target: NAME, "connecting to {}", Self::NAME);
-        let i2c = busman.acquire_i2c();
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+        // every PMSA003I packet starts with the magic word 0x424d.
+        let mut packet = [0; 32];
+        i2c.read(addr, &mut packet)
+            .map_err(|error| anyhow!("failed to read packet: {error:?}"))?;
+        Ok(packet[..2] == [0x42, 0x4d])
+    }
+
+    fn init(
+        busman: &'static I2cBus,
+        // the PMSA003I has a fixed I2C address
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self> {
+        log::info!(target: NAME, "connecting to {NAME}");
+        let mut sensor = pmsa003i::Pmsa003i::new(busman.acquire_i2c());
+        // the sensor doesn't have any registers to read its version from, so
+        // read a packet to find out what version it is.
+        let sensor_version = sensor
+            .read()
+            .map_err(|error| anyhow!("error reading from {NAME}: {error:?}"))?
+            .sensor_version;
+        log::info!(target: NAME, "connected to {NAME}; version: {sensor_version:#04x}");
+        let label = SensorLabel::new(NAME, addr);
+        let pm_conc = |diameter| {
+            metrics
+                .pm_conc
+                .register(DiameterLabel::new(diameter, label))
+                .unwrap()
+        };
+        let pm_count = |diameter| {
+            metrics
+                .pm_count
+                .register(DiameterLabel::new(diameter, label))
+                .unwrap()
+        };
         Ok(Self {
-            sensor: pmsa003i::Pmsa003i::new(i2c),
-            pm2_5: metrics.pm_conc.register(DiameterLabel("2.5")).unwrap(),
-            pm1_0: metrics.pm_conc.register(DiameterLabel("1.0")).unwrap(),
-            pm10_0: metrics.pm_conc.register(DiameterLabel("10.0")).unwrap(),
-            particles_0_3um: metrics.pm_count.register(DiameterLabel("0.3")).unwrap(),
-            particles_0_5um: metrics.pm_count.register(DiameterLabel("0.5")).unwrap(),
-            particles_1_0um: metrics.pm_count.register(DiameterLabel("1.0")).unwrap(),
-            particles_2_5um: metrics.pm_count.register(DiameterLabel("2.5")).unwrap(),
-            particles_5_0um: metrics.pm_count.register(DiameterLabel("5.0")).unwrap(),
-            particles_10_0um: metrics.pm_count.register(DiameterLabel("10.0")).unwrap(),
+            sensor,
+            pm2_5: pm_conc("2.5"),
+            pm1_0: pm_conc("1.0"),
+            pm10_0: pm_conc("10.0"),
+            particles_0_3um: pm_count("0.3"),
+            particles_0_5um: pm_count("0.5"),
+            particles_1_0um: pm_count("1.0"),
+            particles_2_5um: pm_count("2.5"),
+            particles_5_0um: pm_count("5.0"),
+            particles_10_0um: pm_count("10.0"),
+            poll_interval: config
+                .poll_interval_secs
+                .map(|secs| Duration::from_secs(secs.into()))
+                .unwrap_or(DEFAULT_POLL_INTERVAL),
+            started_at: Instant::now(),
+            sensor_version,
         })
     }
 
@@ -49,7 +101,7 @@ impl Sensor for Pmsa003i {
         } = self
             .sensor
             .read()
-            .map_err(|error| anyhow::anyhow!("error reading from {NAME}: {error:?}"))?;
+            .map_err(|error| anyhow!("error reading from {NAME}: {error:?}"))?;
 
         log::info!(target: NAME, "particulate concentrations:\n{concentrations:>#3}");
         log::info!(target: NAME, "particulates {counts:>#3}");
@@ -73,8 +125,29 @@ impl Sensor for Pmsa003i {
         Ok(())
     }
 
-    fn poll_interval(&self) -> embassy_time::Duration {
-        embassy_time::Duration::from_secs(2)
+    fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    fn set_poll_interval(&mut self, interval: Duration) -> anyhow::Result<()> {
+        self.poll_interval = interval;
+        Ok(())
+    }
+
+    fn check_poll_interval(_: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        if self.started_at.elapsed() < WARMUP {
+            Status::WarmingUp
+        } else {
+            Status::Up
+        }
+    }
+
+    fn identity(&self) -> Identity {
+        Identity::from([("version", format!("{:#04x}", self.sensor_version))])
     }
 
     fn handle_control_message(&mut self, _: &Self::ControlMessage) -> anyhow::Result<()> {
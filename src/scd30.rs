@@ -1,23 +1,35 @@
 use crate::{
-    metrics::{self, Gauge},
-    sensor::Sensor,
-    units, I2cBus, I2cRef, SensorMetrics,
+    compensation::COMPENSATION,
+    config::SensorConfig,
+    metrics::{Gauge, SensorLabel},
+    sensor::{scan, Identity, Sensor},
+    units, Delay, I2cBus, I2cError, I2cRef, SensorMetrics,
 };
-use anyhow::anyhow;
-use esp_idf_hal::{delay::Ets, i2c::I2cError};
-use std::num::Wrapping;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::{num::Wrapping, sync::Mutex};
 
 pub struct Scd30 {
-    sensor: sensor_scd30::Scd30<I2cRef<'static>, Ets, I2cError>,
+    sensor: sensor_scd30::Scd30<I2cRef<'static>, Delay, I2cError>,
+    /// A second handle on the bus, for reading back settings that the driver
+    /// can only write.
+    i2c: I2cRef<'static>,
+    addr: u8,
     measurement_interval_secs: u16,
+    /// The ambient pressure the sensor is currently compensating for, in
+    /// millibars, or 0 if pressure compensation is disabled.
+    ambient_pressure_mbar: u16,
+    sht31: SensorLabel,
     co2_gauge: &'static Gauge,
     temp_gauge: &'static Gauge,
     rel_humidity_gauge: &'static Gauge,
     abs_humidity_gauge: &'static Gauge,
     polls: Wrapping<usize>,
+    firmware: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ControlMessage {
     /// Force calibrate the sensor to the given CO2 parts per million.
     ForceCalibrate {
@@ -29,22 +41,112 @@ pub enum ControlMessage {
         secs: u16,
     },
     SoftReset,
+    /// Enables or disables automatic self-calibration (ASC).
+    SetAutoCalibration {
+        enabled: bool,
+    },
+    /// Sets the temperature offset (in degrees Celcius) subtracted from the
+    /// onboard SHT31's measurements, to account for heating by nearby parts.
+    SetTempOffset {
+        celcius: f32,
+    },
+    /// Reads the sensor's current settings back into [`SETTINGS`].
+    GetSettings,
+}
+
+/// The SCD30's current settings, as read back from the sensor.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Settings {
+    pub measurement_interval_secs: u16,
+    pub auto_calibration: bool,
+    /// The temperature offset, in degrees Celcius.
+    pub temp_offset_celcius: f32,
+    /// The altitude the sensor compensates for, in meters above sea level.
+    pub altitude_m: u16,
+    /// The CO2 concentration (in ppm) the sensor was last force calibrated to.
+    pub forced_calibration_ppm: u16,
+    /// The ambient pressure the sensor compensates for, in millibars, or 0 if
+    /// pressure compensation is disabled. This is measured by other sensors,
+    /// so it can't be set.
+    pub ambient_pressure_mbar: u16,
+}
+
+/// Changes to the SCD30's settings. Settings that are `None` are left as
+/// they are.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub measurement_interval_secs: Option<u16>,
+    pub auto_calibration: Option<bool>,
+    pub temp_offset_celcius: Option<f32>,
+    pub altitude_m: Option<u16>,
 }
 
+/// The SCD30's settings, as of the last time they were read, or `None` if the
+/// sensor hasn't been initialized.
+pub static SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+
 const NAME: &str = "SCD30";
 
+/// Valid SCD30 measurement intervals, in seconds (per the datasheet).
+const MEASUREMENT_INTERVAL_SECS: std::ops::RangeInclusive<u16> = 2..=1800;
+
+/// Valid SCD30 ambient pressure compensation values, in millibars (per the
+/// datasheet).
+const AMBIENT_PRESSURE_MBAR: std::ops::RangeInclusive<u16> = 700..=1400;
+
+/// How much the ambient pressure must change (in millibars) before the
+/// sensor's pressure compensation is updated.
+const AMBIENT_PRESSURE_THRESHOLD_MBAR: u16 = 2;
+
+/// Valid SCD30 temperature offsets, in degrees Celcius. The offset is stored
+/// in hundredths of a degree in a `u16`.
+const TEMP_OFFSET_CELCIUS: std::ops::RangeInclusive<f32> = 0.0..=655.35;
+
+// Commands for reading back settings (per the interface description).
+const CMD_MEASUREMENT_INTERVAL: u16 = 0x4600;
+const CMD_AUTO_CALIBRATION: u16 = 0x5306;
+const CMD_FORCED_CALIBRATION: u16 = 0x5204;
+const CMD_TEMP_OFFSET: u16 = 0x5403;
+const CMD_ALTITUDE: u16 = 0x5102;
+/// The SCD30 needs at least 3 ms between a command and reading its response.
+const READ_DELAY_MS: u8 = 3;
+
 impl Sensor for Scd30 {
     type ControlMessage = ControlMessage;
 
     const NAME: &'static str = NAME;
-    fn init(busman: &'static I2cBus, metrics: &'static SensorMetrics) -> anyhow::Result<Self> {
-        const INITIAL_INTERVAL_SECS: u16 = 2;
-        const SHT31: metrics::SensorLabel = metrics::SensorLabel("SHT31");
+    const ADDRESSES: &'static [u8] = &[0x61];
+
+    /// The SCD30 only has a new measurement once per measurement interval,
+    /// which may not line up exactly with our poll timer.
+    const DATA_READY_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_millis(250);
+
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+        // read the firmware version register. the SCD30 has a fixed address,
+        // so if a Sensirion part at that address responds with a valid CRC,
+        // it's probably a SCD30.
+        let firmware = scan::sensirion_read_word(i2c, addr, 0xD100, 3)?;
+        log::debug!(target: NAME, "probe: firmware version {firmware:#06x}");
+        Ok(true)
+    }
+
+    fn init(
+        busman: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self> {
+        const DEFAULT_INTERVAL_SECS: u16 = 2;
+        let label = SensorLabel::new(NAME, addr);
+        // the SCD30's temperature and humidity measurements come from an
+        // onboard SHT31.
+        let sht31 = SensorLabel::onboard("SHT31", NAME, addr);
 
         log::debug!("connecting to SCD30");
 
         let i2c = busman.acquire_i2c();
-        let mut sensor = sensor_scd30::Scd30::new(i2c, Ets)
+        let mut sensor = sensor_scd30::Scd30::new(i2c, Delay)
             .map_err(|error| anyhow!("failed to connect to SCD30: {error:?}"))?;
 
         let firmware = sensor
@@ -52,48 +154,71 @@ impl Sensor for Scd30 {
             .map_err(|error| anyhow!("failed to read SCD30 firmware version: {error:?}"))?;
         log::info!(target: NAME, "connected to SCD30; firmware: {firmware}");
 
+        let interval_secs = match config.poll_interval_secs {
+            Some(secs) if MEASUREMENT_INTERVAL_SECS.contains(&secs) => secs,
+            Some(secs) => {
+                log::warn!(target: NAME, "configured SCD30 measurement interval of {secs} seconds is out of range ({MEASUREMENT_INTERVAL_SECS:?}); using {DEFAULT_INTERVAL_SECS} seconds");
+                DEFAULT_INTERVAL_SECS
+            }
+            None => DEFAULT_INTERVAL_SECS,
+        };
         sensor
-            .set_measurement_interval(INITIAL_INTERVAL_SECS)
+            .set_measurement_interval(interval_secs)
             .map_err(|error| anyhow!("failed to set SCD30 measurement interval: {error:?}"))?;
-        log::info!(target: NAME, "set SCD30 measurement interval to {INITIAL_INTERVAL_SECS} seconds");
+        log::info!(target: NAME, "set SCD30 measurement interval to {interval_secs} seconds");
 
-        sensor.start_continuous(0).map_err(|error| {
-            anyhow!("failed to start SCD30 continuous sampling mode: {error:?}")
-        })?; // TODO(eliza): figure out pressure compensation.
+        let ambient_pressure_mbar = ambient_pressure_mbar();
+        sensor
+            .start_continuous(ambient_pressure_mbar)
+            .map_err(|error| {
+                anyhow!("failed to start SCD30 continuous sampling mode: {error:?}")
+            })?;
 
-        log::info!(target: NAME, "enabled SCD30 continuous sampling mode");
+        log::info!(target: NAME, "enabled SCD30 continuous sampling mode (ambient pressure: {ambient_pressure_mbar} mbar)");
 
-        Ok(Self {
+        let mut this = Self {
             sensor,
-            measurement_interval_secs: INITIAL_INTERVAL_SECS,
+            i2c: busman.acquire_i2c(),
+            addr,
+            measurement_interval_secs: interval_secs,
+            ambient_pressure_mbar,
+            sht31,
             co2_gauge: metrics
                 .co2
-                .register(Self::LABEL)
+                .register(label)
                 .expect("couldn't register gauge"),
             temp_gauge: metrics
                 .temp
-                .register(SHT31)
+                .register(sht31)
                 .expect("couldn't register gauge"),
             rel_humidity_gauge: metrics
                 .rel_humidity
-                .register(SHT31)
+                .register(sht31)
                 .expect("couldn't register gauge"),
             abs_humidity_gauge: metrics
                 .abs_humidity
-                .register(SHT31)
+                .register(sht31)
                 .expect("couldn't register gauge"),
             polls: Wrapping(0),
-        })
+            firmware: firmware.to_string(),
+        };
+
+        // the sensor remembers ASC, the temperature offset, and the altitude
+        // across power cycles, so read back what it's actually using. the
+        // sensor is usable even if this fails, so don't fail init.
+        if let Some(settings) = this.refresh_settings() {
+            log::info!(target: NAME, "SCD30 settings: {settings:?}");
+        }
+        Ok(this)
     }
 
-    fn poll(&mut self) -> anyhow::Result<()> {
-        // Keep looping until ready
-        while !self
-            .sensor
+    fn data_ready(&mut self) -> anyhow::Result<bool> {
+        self.sensor
             .data_ready()
-            .map_err(|err| anyhow!("error waiting for data: {err:?}"))?
-        {}
+            .map_err(|err| anyhow!("error waiting for data: {err:?}"))
+    }
 
+    fn poll(&mut self) -> anyhow::Result<()> {
         // Fetch data when available
         let sensor_scd30::Measurement { co2, temp, rh } = self
             .sensor
@@ -104,15 +229,23 @@ impl Sensor for Scd30 {
         self.co2_gauge.set_value(co2.into());
         self.rel_humidity_gauge.set_value(rh.into());
         self.temp_gauge.set_value(temp.into());
+        COMPENSATION.temperature.record(self.sht31, temp);
         log::info!(target: NAME, " CO2: {co2:>4.2} ppm");
         log::info!(target: NAME, " Temp: {temp:>3.2} \u{00B0}C, Humidity: {rh:>3.2}%");
 
         if self.polls.0 % units::ABS_HUMIDITY_INTERVAL == 0 {
             let abs_humidity = units::absolute_humidity(temp, rh);
             self.abs_humidity_gauge.set_value(abs_humidity.into());
+            COMPENSATION.abs_humidity.record(self.sht31, abs_humidity);
             log::info!(target: NAME, " Absolute Humidity: {abs_humidity:>3.2} g/𝑚³");
         }
 
+        // the measurement was successful even if updating the pressure
+        // compensation wasn't, so don't fail the poll.
+        if let Err(error) = self.update_pressure_compensation() {
+            log::warn!(target: NAME, "{error:#}");
+        }
+
         Ok(())
     }
 
@@ -120,6 +253,22 @@ impl Sensor for Scd30 {
         embassy_time::Duration::from_secs(self.measurement_interval_secs as u64)
     }
 
+    fn set_poll_interval(&mut self, interval: embassy_time::Duration) -> anyhow::Result<()> {
+        let secs = u16::try_from(interval.as_secs()).unwrap_or(u16::MAX);
+        self.set_measurement_interval(secs)?;
+        self.refresh_settings();
+        Ok(())
+    }
+
+    fn check_poll_interval(interval: embassy_time::Duration) -> anyhow::Result<()> {
+        let secs = u16::try_from(interval.as_secs()).unwrap_or(u16::MAX);
+        check_measurement_interval(secs)
+    }
+
+    fn identity(&self) -> Identity {
+        Identity::from([("firmware", self.firmware.clone())])
+    }
+
     fn handle_control_message(&mut self, msg: &Self::ControlMessage) -> anyhow::Result<()> {
         match msg {
             &ControlMessage::ForceCalibrate { ppm } => {
@@ -135,15 +284,7 @@ impl Sensor for Scd30 {
                 log::info!(target: NAME, "set altitude offset to {altitude}");
             }
             &ControlMessage::SetMeasurementInterval { secs } => {
-                anyhow::ensure!(secs > 0);
-                self.sensor
-                    .set_measurement_interval(secs)
-                    .map_err(|error| {
-                        anyhow!(
-                            "failed to set SCD30 measurement interval {secs} seconds: {error:?}"
-                        )
-                    })?;
-                log::info!(target: NAME, "set measurement interval to {secs} seconds");
+                self.set_measurement_interval(secs)?
             }
             ControlMessage::SoftReset => {
                 self.sensor
@@ -151,8 +292,224 @@ impl Sensor for Scd30 {
                     .map_err(|error| anyhow!("failed to trigger SCD30 soft reset {error:?}"))?;
                 log::info!(target: NAME, "soft reset!");
             }
+            &ControlMessage::SetAutoCalibration { enabled } => {
+                self.sensor.set_afc(enabled).map_err(|error| {
+                    anyhow!(
+                        "failed to set SCD30 automatic self-calibration to {enabled}: {error:?}"
+                    )
+                })?;
+                log::info!(target: NAME, "set automatic self-calibration to {enabled}");
+            }
+            &ControlMessage::SetTempOffset { celcius } => {
+                anyhow::ensure!(
+                    TEMP_OFFSET_CELCIUS.contains(&celcius),
+                    "SCD30 temperature offset must be in {TEMP_OFFSET_CELCIUS:?} \u{00B0}C"
+                );
+                self.sensor.set_temp_offset(celcius).map_err(|error| {
+                    anyhow!(
+                        "failed to set SCD30 temperature offset to {celcius} \u{00B0}C: {error:?}"
+                    )
+                })?;
+                log::info!(target: NAME, "set temperature offset to {celcius} \u{00B0}C");
+            }
+            ControlMessage::GetSettings => {
+                self.read_settings()?;
+                return Ok(());
+            }
+        }
+
+        // the change was applied even if reading the settings back fails, so
+        // don't report the message as failed.
+        self.refresh_settings();
+        Ok(())
+    }
+}
+
+impl Scd30 {
+    /// Sets the sensor's measurement interval, which is also its poll
+    /// interval.
+    fn set_measurement_interval(&mut self, secs: u16) -> anyhow::Result<()> {
+        check_measurement_interval(secs)?;
+        self.sensor
+            .set_measurement_interval(secs)
+            .map_err(|error| {
+                anyhow!("failed to set SCD30 measurement interval {secs} seconds: {error:?}")
+            })?;
+        self.measurement_interval_secs = secs;
+        log::info!(target: NAME, "set measurement interval to {secs} seconds");
+        Ok(())
+    }
+
+    /// Reads the sensor's current settings, and publishes them to
+    /// [`SETTINGS`].
+    fn read_settings(&mut self) -> anyhow::Result<Settings> {
+        let settings = Settings {
+            measurement_interval_secs: self
+                .read_word(CMD_MEASUREMENT_INTERVAL)
+                .context("failed to read SCD30 measurement interval")?,
+            auto_calibration: self
+                .read_word(CMD_AUTO_CALIBRATION)
+                .context("failed to read SCD30 automatic self-calibration")?
+                != 0,
+            temp_offset_celcius: self
+                .read_word(CMD_TEMP_OFFSET)
+                .context("failed to read SCD30 temperature offset")?
+                as f32
+                / 100.0,
+            altitude_m: self
+                .read_word(CMD_ALTITUDE)
+                .context("failed to read SCD30 altitude")?,
+            forced_calibration_ppm: self
+                .read_word(CMD_FORCED_CALIBRATION)
+                .context("failed to read SCD30 forced recalibration value")?,
+            ambient_pressure_mbar: self.ambient_pressure_mbar,
+        };
+        *SETTINGS.lock().unwrap() = Some(settings);
+        Ok(settings)
+    }
+
+    /// Reads the sensor's current settings, logging a warning rather than
+    /// failing if they can't be read.
+    fn refresh_settings(&mut self) -> Option<Settings> {
+        self.read_settings()
+            .map_err(|error| log::warn!(target: NAME, "{error:#}"))
+            .ok()
+    }
+
+    fn read_word(&mut self, cmd: u16) -> anyhow::Result<u16> {
+        scan::sensirion_read_word(&mut self.i2c, self.addr, cmd, READ_DELAY_MS)
+    }
+
+    /// Restarts continuous measurement with a new ambient pressure, if the
+    /// ambient pressure has changed significantly.
+    ///
+    /// If no other sensor has measured the pressure recently, the sensor keeps
+    /// compensating for the last known pressure.
+    fn update_pressure_compensation(&mut self) -> anyhow::Result<()> {
+        let pressure = ambient_pressure_mbar();
+        if pressure == 0
+            || pressure.abs_diff(self.ambient_pressure_mbar) < AMBIENT_PRESSURE_THRESHOLD_MBAR
+        {
+            return Ok(());
         }
 
+        self.sensor.start_continuous(pressure).map_err(|error| {
+            anyhow!("failed to set SCD30 ambient pressure to {pressure} mbar: {error:?}")
+        })?;
+        log::info!(target: NAME, "set ambient pressure compensation to {pressure} mbar");
+        self.ambient_pressure_mbar = pressure;
+        if let Some(settings) = SETTINGS.lock().unwrap().as_mut() {
+            settings.ambient_pressure_mbar = pressure;
+        }
         Ok(())
     }
 }
+
+// === impl SettingsUpdate ===
+
+impl SettingsUpdate {
+    /// Returns the control messages that apply this update.
+    pub fn messages(&self) -> Vec<ControlMessage> {
+        let mut msgs = Vec::new();
+        if let Some(secs) = self.measurement_interval_secs {
+            msgs.push(ControlMessage::SetMeasurementInterval { secs });
+        }
+        if let Some(enabled) = self.auto_calibration {
+            msgs.push(ControlMessage::SetAutoCalibration { enabled });
+        }
+        if let Some(celcius) = self.temp_offset_celcius {
+            msgs.push(ControlMessage::SetTempOffset { celcius });
+        }
+        if let Some(altitude) = self.altitude_m {
+            msgs.push(ControlMessage::SetAltOffset(altitude));
+        }
+        msgs
+    }
+}
+
+/// Returns the ambient pressure measured by other sensors, in millibars, or 0
+/// (which disables pressure compensation) if there is no recent measurement
+/// within the range the SCD30 can compensate for.
+fn ambient_pressure_mbar() -> u16 {
+    match COMPENSATION.pressure.get() {
+        // 1 hPa is 1 millibar.
+        Some(hpa) if AMBIENT_PRESSURE_MBAR.contains(&(hpa.round() as u16)) => hpa.round() as u16,
+        Some(hpa) => {
+            log::warn!(target: NAME, "ambient pressure {hpa:>4.2} hPa is out of range ({AMBIENT_PRESSURE_MBAR:?} mbar); not compensating");
+            0
+        }
+        None => 0,
+    }
+}
+
+/// Returns an error if the SCD30 can't measure every `secs` seconds.
+fn check_measurement_interval(secs: u16) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        MEASUREMENT_INTERVAL_SECS.contains(&secs),
+        "SCD30 measurement interval must be in {MEASUREMENT_INTERVAL_SECS:?} seconds"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_update_messages() {
+        let update: SettingsUpdate =
+            serde_json::from_str(r#"{"auto_calibration": false, "temp_offset_celcius": 1.5}"#)
+                .unwrap();
+        let msgs = update.messages();
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(
+            msgs[0],
+            ControlMessage::SetAutoCalibration { enabled: false }
+        ));
+        assert!(matches!(
+            msgs[1],
+            ControlMessage::SetTempOffset { celcius } if celcius == 1.5
+        ));
+
+        assert!(SettingsUpdate::default().messages().is_empty());
+        assert!(serde_json::from_str::<SettingsUpdate>(r#"{"asc": true}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "espidf"))]
+    fn settings_read_back_failures_are_not_errors() {
+        use crate::sim;
+
+        let bus = sim::SimBus::new();
+        let scd30 = sim::Scd30::new();
+        scd30.set_settings_readable(false);
+        bus.attach(sim::scd30::ADDR, scd30.clone());
+        let metrics: &'static SensorMetrics = Box::leak(Box::new(SensorMetrics::new()));
+
+        let mut sensor = Scd30::init(
+            bus.leak_bus_manager(),
+            sim::scd30::ADDR,
+            metrics,
+            &SensorConfig::default(),
+        )
+        .expect("init shouldn't fail if only reading the settings back fails");
+
+        // the setting is changed, even though it can't be read back...
+        sensor
+            .handle_control_message(&ControlMessage::SetMeasurementInterval { secs: 5 })
+            .unwrap();
+        assert_eq!(scd30.measurement_interval_secs(), 5);
+        // ...but explicitly reading the settings still reports the failure.
+        assert!(sensor
+            .handle_control_message(&ControlMessage::GetSettings)
+            .is_err());
+
+        scd30.set_settings_readable(true);
+        sensor
+            .handle_control_message(&ControlMessage::GetSettings)
+            .unwrap();
+        // other tests may update `SETTINGS` concurrently, so check the
+        // settings directly.
+        assert_eq!(sensor.read_settings().unwrap().measurement_interval_secs, 5);
+    }
+}
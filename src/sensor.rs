@@ -1,16 +1,23 @@
 use crate::{
-    actor::Actor,
-    metrics::{self, SensorMetrics},
+    actor::{self, Actor, Envelope},
+    config::{Config, SensorConfig},
+    events::{Event, EVENTS},
+    metrics::{Counter, Gauge, SensorLabel, SensorMetrics},
     retry::ExpBackoff,
-    I2cBus,
+    I2cBus, I2cRef,
 };
-use embassy_time::{Duration, Timer};
-use futures::{select, FutureExt};
-use std::fmt;
+use embassy_time::{Duration, Instant, Timer};
+use futures::{select, Future, FutureExt};
+use serde::de::DeserializeOwned;
+use std::{fmt, sync::Mutex};
 use tinymetrics::registry::RegistryMap;
 
+pub mod control;
+pub mod scan;
 mod status;
-pub use self::status::{Status, StatusCell};
+pub use self::control::{ControlError, Controls, CONTROLS};
+pub use self::scan::Scanner;
+pub use self::status::{Identity, Status, StatusCell};
 
 /// Represents a pollable I2C sensor.
 pub trait Sensor: Sized {
@@ -23,18 +30,105 @@ pub trait Sensor: Sized {
     ///
     /// If a sensor does not respond to control messages, its `ControlMessage`
     /// type may be `()`.
-    type ControlMessage: fmt::Debug;
+    ///
+    /// Control messages are deserialized from JSON when they're sent over
+    /// HTTP (see [`Controls::send`]).
+    type ControlMessage: fmt::Debug + DeserializeOwned;
 
     const NAME: &'static str;
-    const LABEL: metrics::SensorLabel = metrics::SensorLabel(Self::NAME);
 
-    fn init(i2c: &'static I2cBus, metrics: &'static SensorMetrics) -> anyhow::Result<Self>;
+    /// The I2C addresses at which this sensor may be found.
+    ///
+    /// If a sensor has more than one possible address, multiple instances of
+    /// that sensor may be connected to the bus at the same time.
+    const ADDRESSES: &'static [u8];
+
+    /// Returns `true` if a sensor of this type is present at `addr`.
+    ///
+    /// This is used by the bus [`Scanner`] to detect sensors. Rather than
+    /// just checking whether the address is ACKed, implementations should
+    /// identify the part, e.g. by reading a chip ID register. Returning an
+    /// error indicates that nothing responded at `addr`.
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool>;
+
+    fn init(
+        i2c: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self>;
+
+    /// How long to wait before checking again if [`data_ready`] returns
+    /// `false`.
+    const DATA_READY_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Returns `true` if the sensor has a new measurement ready to be
+    /// [`poll`]ed.
+    ///
+    /// If this returns `Ok(false)`, the [`Manager`] yields to other tasks and
+    /// checks again after [`DATA_READY_INTERVAL`], rather than blocking the
+    /// executor (and hogging the bus) while waiting for the sensor. By
+    /// default, sensors are always ready.
+    ///
+    /// [`DATA_READY_INTERVAL`]: Self::DATA_READY_INTERVAL
+    fn data_ready(&mut self) -> anyhow::Result<bool> {
+        Ok(true)
+    }
 
     fn poll(&mut self) -> anyhow::Result<()>;
 
     /// Returns the interval between calls to [`poll`].
+    ///
+    /// This is checked again after every control message, so sensors may
+    /// change their poll interval at runtime.
     fn poll_interval(&self) -> Duration;
 
+    /// Changes the interval between calls to [`poll`].
+    ///
+    /// By default, sensors don't support changing their poll interval, and
+    /// this returns an error.
+    fn set_poll_interval(&mut self, interval: Duration) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "{} does not support changing its poll interval (to {interval})",
+            Self::NAME
+        )
+    }
+
+    /// Returns an error if [`set_poll_interval`] would reject `interval`.
+    ///
+    /// This is checked before a new poll interval is accepted for a sensor
+    /// that isn't running, since it will be passed to [`set_poll_interval`]
+    /// when the sensor is brought up. Sensors which override
+    /// [`set_poll_interval`] must also override this.
+    ///
+    /// [`set_poll_interval`]: Self::set_poll_interval
+    fn check_poll_interval(interval: Duration) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "{} does not support changing its poll interval (to {interval})",
+            Self::NAME
+        )
+    }
+
+    /// Returns the sensor's status while it's working normally.
+    ///
+    /// This is checked after the sensor is brought up and after every
+    /// successful [`poll`]. Sensors whose readings aren't meaningful right
+    /// away, or which calibrate themselves over time, can override this to
+    /// return [`Status::WarmingUp`] or [`Status::Calibrating`]. By default,
+    /// sensors are [`Status::Up`] as soon as they're polled successfully.
+    fn status(&self) -> Status {
+        Status::Up
+    }
+
+    /// Returns identifying details reported by the sensor, such as its
+    /// firmware version or serial number.
+    ///
+    /// This is checked once, after the sensor is brought up, and reported in
+    /// the sensor's diagnostics. By default, sensors report nothing.
+    fn identity(&self) -> Identity {
+        Identity::new()
+    }
+
     /// Handle a [`ControlMessage`] sent to this sensor.
     ///
     /// This method's behavior will depend on the control messages defined by
@@ -49,80 +143,300 @@ pub trait Sensor: Sized {
     fn handle_control_message(&mut self, msg: &Self::ControlMessage) -> anyhow::Result<()>;
 }
 
-/// A sensor mangler for pollable I2C [`Sensor`]s.
+/// An I2C sensor whose operations are asynchronous.
+///
+/// This is the interface that a sensor [`Manager`] actually drives. Unlike a
+/// [`Sensor`], an `AsyncSensor` may `.await` while it's initializing, waiting
+/// for a measurement, or handling a control message, so a slow sensor (such
+/// as a BME680 heating its gas sensor) yields to the other tasks on the
+/// executor instead of stalling them. Delays should use [`embassy_time::Timer`]
+/// rather than busy-waiting with a blocking [`Delay`](crate::Delay).
+///
+/// Every [`Sensor`] is also an `AsyncSensor` whose operations complete
+/// immediately, so existing synchronous drivers can be migrated one at a
+/// time. All of the current drivers are still synchronous; in particular, the
+/// `bosch-bme680` driver busy-waits for the gas sensor heater inside a single
+/// call, so the BME680 can't await it until the driver exposes the steps of a
+/// measurement separately.
+///
+/// Note that I2C transactions themselves are still blocking, since
+/// `esp-idf-hal` doesn't currently provide an async I2C driver. Individual
+/// transactions are short, though; it's the waits between them that matter.
+pub trait AsyncSensor: Sized {
+    /// Messages sent to control the behavior of this sensor.
+    ///
+    /// See [`Sensor::ControlMessage`].
+    type ControlMessage: fmt::Debug + DeserializeOwned;
+
+    const NAME: &'static str;
+
+    /// The I2C addresses at which this sensor may be found.
+    const ADDRESSES: &'static [u8];
+
+    /// How long to wait before checking again if [`data_ready`] returns
+    /// `false`.
+    ///
+    /// [`data_ready`]: Self::data_ready
+    const DATA_READY_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Returns `true` if a sensor of this type is present at `addr`.
+    ///
+    /// Probing is synchronous, since the bus [`Scanner`] probes every sensor
+    /// at once and each probe should be a single short transaction. See
+    /// [`Sensor::probe`].
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool>;
+
+    async fn init(
+        i2c: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self>;
+
+    /// Returns `true` if the sensor has a new measurement ready to be
+    /// [`poll`](Self::poll)ed. See [`Sensor::data_ready`].
+    async fn data_ready(&mut self) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    async fn poll(&mut self) -> anyhow::Result<()>;
+
+    /// Returns the interval between calls to [`poll`](Self::poll).
+    fn poll_interval(&self) -> Duration;
+
+    /// Changes the interval between calls to [`poll`](Self::poll).
+    fn set_poll_interval(&mut self, interval: Duration) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "{} does not support changing its poll interval (to {interval})",
+            Self::NAME
+        )
+    }
+
+    /// Returns an error if [`set_poll_interval`](Self::set_poll_interval)
+    /// would reject `interval`. See [`Sensor::check_poll_interval`].
+    fn check_poll_interval(interval: Duration) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "{} does not support changing its poll interval (to {interval})",
+            Self::NAME
+        )
+    }
+
+    /// Returns the sensor's status while it's working normally. See
+    /// [`Sensor::status`].
+    fn status(&self) -> Status {
+        Status::Up
+    }
+
+    /// Returns identifying details reported by the sensor. See
+    /// [`Sensor::identity`].
+    fn identity(&self) -> Identity {
+        Identity::new()
+    }
+
+    /// Handle a [`ControlMessage`](Self::ControlMessage) sent to this sensor.
+    async fn handle_control_message(&mut self, msg: &Self::ControlMessage) -> anyhow::Result<()>;
+}
+
+/// A sensor mangler for pollable I2C [`AsyncSensor`]s (including every
+/// [`Sensor`]).
 ///
-/// A sensor manager handles sensor initialization, polls the sensor at the
+/// A sensor manager waits for its sensor to be detected by the bus
+/// [`Scanner`], handles sensor initialization, polls the sensor at the
 /// provided `poll_interval`, and backs off when the sensor is unavailable. This
 /// allows a  limited form of hot-plugability for I2C sensors: although the
 /// kinds of sensors that may be on the bus must be known in advance, they can
-/// be disconnected after the device starts without requiring a complete reset.
+/// be connected or disconnected after the device starts without requiring a
+/// complete reset.
 #[derive(Copy, Clone)]
 pub struct Manager {
     pub metrics: &'static SensorMetrics,
     pub busman: &'static I2cBus,
+    pub config: &'static Config,
     pub retry_backoff: Duration,
 }
 
-pub static STATUSES: RegistryMap<&'static str, StatusCell, 16> = RegistryMap::new();
+/// Commands handled by a sensor's [`Manager`], rather than by the sensor
+/// itself. Every sensor accepts these, regardless of its `ControlMessage`
+/// type.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Changes the sensor's poll interval (see [`Sensor::set_poll_interval`]).
+    SetPollInterval(Duration),
+    /// Stops polling the sensor, leaving it [`Status::Disabled`] until it's
+    /// resumed.
+    Pause,
+    /// Resumes a paused sensor, bringing it back up from scratch.
+    Resume,
+    /// Brings the sensor back up from scratch, as though it had just been
+    /// detected. A paused sensor is resumed.
+    Restart,
+}
+
+/// Sends [`Command`]s to every sensor instance.
+pub struct Commands {
+    clients: Mutex<Vec<(SensorLabel, actor::Client<Command, anyhow::Result<()>>)>>,
+}
+
+/// The state of a sensor instance run by a [`Manager`].
+struct Instance<S: AsyncSensor> {
+    label: SensorLabel,
+    addr: u8,
+    status: SensorStatus,
+    errors: &'static Counter,
+    detected: &'static scan::Detected,
+    ctrl_rx: Actor<S::ControlMessage, anyhow::Result<()>>,
+    cmd_rx: Actor<Command, anyhow::Result<()>>,
+    /// A poll interval set by a [`Command`], which overrides the configured
+    /// poll interval if the sensor is restarted.
+    poll_interval: Option<Duration>,
+}
+
+/// Why a sensor instance's [`Manager`] stopped running it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Stop {
+    /// The sensor was paused, and should stay disabled until it's resumed.
+    Pause,
+    /// The sensor should be brought back up immediately.
+    Restart,
+}
+
+/// The status of each sensor instance.
+pub static STATUSES: RegistryMap<SensorLabel, StatusCell, 16> = RegistryMap::new();
+
+/// Command clients for every sensor instance that has been brought up.
+pub static COMMANDS: Commands = Commands::new();
 
 impl Manager {
-    pub async fn run<S: Sensor>(
+    /// Runs the sensor instance at I2C address `addr`.
+    pub async fn run<S: AsyncSensor>(
         self,
+        addr: u8,
         ctrl_rx: Actor<S::ControlMessage, anyhow::Result<()>>,
     ) -> anyhow::Result<()> {
+        let label = SensorLabel::new(S::NAME, addr);
         let status = STATUSES
-            .get_or_register_default(S::NAME)
-            .ok_or_else(|| anyhow::anyhow!("insufficient space in status map for {}", S::NAME))?;
-        let errors = self
-            .metrics
-            .sensor_errors
-            .register(S::LABEL)
-            .ok_or_else(|| {
-                anyhow::anyhow!("insufficient space in error metrics map for {}", S::NAME)
-            })?;
+            .get_or_register_default(label)
+            .ok_or_else(|| anyhow::anyhow!("insufficient space in status map for {label}"))?;
+        let errors = self.metrics.sensor_errors.register(label).ok_or_else(|| {
+            anyhow::anyhow!("insufficient space in error metrics map for {label}")
+        })?;
+        let up = self.metrics.sensor_up.register(label).ok_or_else(|| {
+            anyhow::anyhow!("insufficient space in sensor_up metrics map for {label}")
+        })?;
+        let status = SensorStatus {
+            label,
+            cell: status,
+            up,
+        };
+
+        let detected = scan::DETECTED
+            .get_or_register_default(label)
+            .ok_or_else(|| anyhow::anyhow!("insufficient space in detected map for {label}"))?;
+
+        let (commands, cmd_rx) = actor::channel(4);
+        COMMANDS.register(label, commands);
+
+        let mut instance = Instance::<S> {
+            label,
+            addr,
+            status,
+            errors,
+            detected,
+            ctrl_rx,
+            cmd_rx,
+            poll_interval: None,
+        };
+        let mut enabled = self.config.sensor(S::NAME).enabled;
+        loop {
+            if !enabled {
+                instance.wait_until_resumed().await;
+            }
+
+            let stop = self.run_instance(&mut instance).await;
+            CONTROLS.set_running(label, false);
+            log::info!(target: S::NAME, "stopping {label} ({stop:?})");
+            enabled = stop == Stop::Restart;
+        }
+    }
+
+    /// Brings up a sensor instance and polls it until it's stopped by a
+    /// [`Command`].
+    async fn run_instance<S: AsyncSensor>(&self, inst: &mut Instance<S>) -> Stop {
+        let label = inst.label;
+
+        // don't bother trying to bring up the sensor until the bus scanner has
+        // found it.
+        log::debug!(target: S::NAME, "waiting for {label} to be detected...");
+        let detected = inst.detected;
+        if let Err(stop) = inst.wait_idle(detected.wait()).await {
+            return stop;
+        }
+        inst.status.set(Status::Initializing);
 
+        let config = self.config.sensor(S::NAME);
         let mut sensor = {
             loop {
                 let mut backoff = ExpBackoff::new(self.retry_backoff).with_target(S::NAME);
-                match S::init(self.busman, self.metrics) {
-                    Ok(sensor) => {
-                        log::info!(target: S::NAME, "successfully brought up {}!", S::NAME);
-                        status.set_status(Status::Up);
+                match S::init(self.busman, inst.addr, self.metrics, &config).await {
+                    Ok(mut sensor) => {
+                        log::info!(target: S::NAME, "successfully brought up {label}!");
+                        if let Some(interval) = inst.poll_interval {
+                            if let Err(error) = sensor.set_poll_interval(interval) {
+                                log::warn!(target: S::NAME, "failed to set {label} poll interval to {interval}: {error}");
+                            }
+                        }
+                        inst.status.cell.set_identity(sensor.identity());
+                        inst.status.set(sensor.status());
                         break sensor;
                     }
                     Err(error) => {
                         log::warn!(
                             target: S::NAME,
-                            "failed to bring up {}: {error:?}; retrying in {backoff:?}...",
-                            S::NAME
+                            "failed to bring up {label}: {error:?}; retrying in {backoff:?}...",
                         );
-                        errors.fetch_add(1);
+                        inst.status.set(self.error_status(inst.addr));
+                        inst.status.cell.record_error(&error, backoff.current());
+                        inst.errors.fetch_add(1);
                     }
                 }
 
-                backoff.wait().await;
+                if let Err(stop) = inst.wait_idle(backoff.wait()).await {
+                    return stop;
+                }
             }
         };
+        CONTROLS.set_running(label, true);
 
-        let mut backoff = ExpBackoff::new(sensor.poll_interval()).with_target(S::NAME);
+        let mut poll_interval = sensor.poll_interval();
+        let mut backoff = ExpBackoff::new(poll_interval).with_target(S::NAME);
 
         let mut poll_wait = Timer::after(Duration::from_secs(0));
-        futures::pin_mut!(ctrl_rx);
+        // when we started waiting for the sensor to have data ready, if it
+        // wasn't ready when we last polled it.
+        let mut waiting_since = None;
 
         loop {
+            // a control message may have changed the sensor's poll interval.
+            if sensor.poll_interval() != poll_interval {
+                poll_interval = sensor.poll_interval();
+                log::info!(target: S::NAME, "{label} poll interval is now {poll_interval}");
+                backoff = ExpBackoff::new(poll_interval).with_target(S::NAME);
+                poll_wait = Timer::after(poll_interval);
+            }
+
             // wait to be notified either by a control message coming in or the
             // poll timer...
             select! {
-                msg = ctrl_rx.next_request().fuse() => {
+                msg = inst.ctrl_rx.next_request().fuse() => {
                     match msg {
                         Some(msg) => {
                             let req = msg.request();
                             log::debug!(target: S::NAME, "received control message: {req:?}");
 
-                            let res = sensor.handle_control_message(req);
+                            let res = sensor.handle_control_message(req).await;
                             if let Err(ref error) = res {
                                 log::warn!(target: S::NAME, "failed to respond to control message {req:?}: {error}");
-                                errors.fetch_add(1);
+                                inst.errors.fetch_add(1);
                             }
 
                             if let Err(_) = msg.respond(res) {
@@ -134,22 +448,732 @@ impl Manager {
                     continue;
                 },
 
-                _ = (&mut poll_wait).fuse() => match sensor.poll() {
+                cmd = inst.cmd_rx.next_request().fuse() => {
+                    let Some(cmd) = cmd else {
+                        continue;
+                    };
+                    if let Some(stop) = handle_command(label, Some(&mut sensor), &mut inst.poll_interval, cmd) {
+                        return stop;
+                    }
+                    continue;
+                },
+
+                _ = (&mut poll_wait).fuse() => match poll(&mut sensor, &mut waiting_since, poll_interval).await {
+                    Ok(false) => {
+                        log::trace!(target: S::NAME, "{label} not ready; checking again in {}", S::DATA_READY_INTERVAL);
+                        poll_wait = Timer::after(S::DATA_READY_INTERVAL);
+                    }
                     Err(error) => {
-                        log::warn!(target: S::NAME, "error polling {}: {error:?}", S::NAME);
-                        status.set_status(Status::Down);
-                        errors.fetch_add(1);
+                        log::warn!(target: S::NAME, "error polling {label}: {error:?}");
+                        inst.status.set(self.error_status(inst.addr));
+                        inst.status.cell.record_error(&error, backoff.current());
+                        inst.errors.fetch_add(1);
                         poll_wait = backoff.wait();
                     }
-                    Ok(()) => {
+                    Ok(true) => {
                         // if we have previously backed off due to repeated errors,
                         // reset the backoff now that the sensor is alive again.
                         backoff.reset();
-                        poll_wait = Timer::after(sensor.poll_interval());
-                        status.set_status(Status::Up);
+                        poll_wait = Timer::after(poll_interval);
+                        inst.status.cell.record_success();
+                        inst.status.set(sensor.status());
+                        EVENTS.publish(Event::Reading { sensor: label });
                     }
                 }
             }
         }
     }
+
+    /// Determines whether an error talking to the sensor at `addr` was a bus
+    /// error or a protocol error, by checking whether its address is still
+    /// ACKed.
+    fn error_status(&self, addr: u8) -> Status {
+        if scan::is_acked(&mut self.busman.acquire_i2c(), addr) {
+            Status::ProtocolError
+        } else {
+            Status::BusError
+        }
+    }
+}
+
+/// Polls `sensor` if it has data ready, returning `Ok(false)` if it doesn't.
+///
+/// If the sensor hasn't had data ready for several poll intervals, it's
+/// probably stuck, so that's treated as an error.
+async fn poll<S: AsyncSensor>(
+    sensor: &mut S,
+    waiting_since: &mut Option<Instant>,
+    poll_interval: Duration,
+) -> anyhow::Result<bool> {
+    let ready = sensor.data_ready().await;
+    if let Ok(false) = ready {
+        let since = *waiting_since.get_or_insert_with(Instant::now);
+        let waited = since.elapsed();
+        if waited <= poll_interval * 3 {
+            return Ok(false);
+        }
+        *waiting_since = None;
+        anyhow::bail!("no data ready after waiting {waited}");
+    }
+
+    *waiting_since = None;
+    ready?;
+    sensor.poll().await?;
+    Ok(true)
+}
+
+/// Handles a [`Command`] sent to a sensor instance, returning `Some` if the
+/// sensor should be stopped.
+///
+/// `sensor` is `None` if the sensor isn't currently running.
+fn handle_command<S: AsyncSensor>(
+    label: SensorLabel,
+    sensor: Option<&mut S>,
+    poll_interval: &mut Option<Duration>,
+    cmd: Envelope<Command, anyhow::Result<()>>,
+) -> Option<Stop> {
+    let req = cmd.request();
+    log::debug!(target: S::NAME, "received command for {label}: {req:?}");
+
+    let (res, stop) = match *req {
+        Command::SetPollInterval(interval) => {
+            // if the sensor isn't running, make sure it will accept the
+            // interval when it's brought up.
+            let res = match sensor {
+                Some(sensor) => sensor.set_poll_interval(interval),
+                None => S::check_poll_interval(interval),
+            };
+            if res.is_ok() {
+                // remember the new interval, so that it's still used if the
+                // sensor is restarted.
+                *poll_interval = Some(interval);
+            }
+            (res, None)
+        }
+        Command::Pause => (Ok(()), Some(Stop::Pause)),
+        Command::Resume => (Ok(()), None),
+        Command::Restart => (Ok(()), Some(Stop::Restart)),
+    };
+    if let Err(ref error) = res {
+        log::warn!(target: S::NAME, "failed to handle command {req:?}: {error}");
+    }
+
+    if let Err(_) = cmd.respond(res) {
+        log::debug!(target: S::NAME, "command canceled");
+    }
+    stop
+}
+
+/// Fails a control message sent to a sensor instance that isn't running.
+fn reject_control_message<M>(label: SensorLabel, msg: Option<Envelope<M, anyhow::Result<()>>>) {
+    if let Some(msg) = msg {
+        let _ = msg.respond(Err(anyhow::anyhow!("{label} is not running")));
+    }
+}
+
+// === impl Instance ===
+
+impl<S: AsyncSensor> Instance<S> {
+    /// Waits for `future` to complete while the sensor isn't running, handling
+    /// any commands received in the meantime.
+    ///
+    /// If a command stops the sensor, this returns why.
+    async fn wait_idle<F: Future>(&mut self, future: F) -> Result<F::Output, Stop> {
+        let future = future.fuse();
+        futures::pin_mut!(future);
+        loop {
+            select! {
+                output = future.as_mut() => return Ok(output),
+                cmd = self.cmd_rx.next_request().fuse() => {
+                    let Some(cmd) = cmd else {
+                        continue;
+                    };
+                    if let Some(stop) = handle_command::<S>(self.label, None, &mut self.poll_interval, cmd) {
+                        return Err(stop);
+                    }
+                },
+                msg = self.ctrl_rx.next_request().fuse() => reject_control_message(self.label, msg),
+            }
+        }
+    }
+
+    /// Leaves the sensor disabled until it's resumed by a [`Command`].
+    async fn wait_until_resumed(&mut self) {
+        log::info!(target: S::NAME, "{} is disabled", self.label);
+        self.status.set(Status::Disabled);
+        loop {
+            select! {
+                cmd = self.cmd_rx.next_request().fuse() => {
+                    let Some(cmd) = cmd else {
+                        continue;
+                    };
+                    let resume = matches!(cmd.request(), Command::Resume | Command::Restart);
+                    handle_command::<S>(self.label, None, &mut self.poll_interval, cmd);
+                    if resume {
+                        log::info!(target: S::NAME, "resuming {}", self.label);
+                        return;
+                    }
+                },
+                msg = self.ctrl_rx.next_request().fuse() => reject_control_message(self.label, msg),
+            }
+        }
+    }
+}
+
+// === impl AsyncSensor ===
+
+/// Synchronous sensors are adapted to the async interface by simply
+/// completing every operation immediately.
+impl<S: Sensor> AsyncSensor for S {
+    type ControlMessage = <S as Sensor>::ControlMessage;
+
+    const NAME: &'static str = <S as Sensor>::NAME;
+    const ADDRESSES: &'static [u8] = <S as Sensor>::ADDRESSES;
+    const DATA_READY_INTERVAL: Duration = <S as Sensor>::DATA_READY_INTERVAL;
+
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+        <S as Sensor>::probe(i2c, addr)
+    }
+
+    async fn init(
+        i2c: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self> {
+        <S as Sensor>::init(i2c, addr, metrics, config)
+    }
+
+    async fn data_ready(&mut self) -> anyhow::Result<bool> {
+        <S as Sensor>::data_ready(self)
+    }
+
+    async fn poll(&mut self) -> anyhow::Result<()> {
+        <S as Sensor>::poll(self)
+    }
+
+    fn poll_interval(&self) -> Duration {
+        <S as Sensor>::poll_interval(self)
+    }
+
+    fn set_poll_interval(&mut self, interval: Duration) -> anyhow::Result<()> {
+        <S as Sensor>::set_poll_interval(self, interval)
+    }
+
+    fn check_poll_interval(interval: Duration) -> anyhow::Result<()> {
+        <S as Sensor>::check_poll_interval(interval)
+    }
+
+    fn status(&self) -> Status {
+        <S as Sensor>::status(self)
+    }
+
+    fn identity(&self) -> Identity {
+        <S as Sensor>::identity(self)
+    }
+
+    async fn handle_control_message(&mut self, msg: &Self::ControlMessage) -> anyhow::Result<()> {
+        <S as Sensor>::handle_control_message(self, msg)
+    }
+}
+
+// === impl Commands ===
+
+impl Commands {
+    const fn new() -> Self {
+        Self {
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn register(&self, label: SensorLabel, client: actor::Client<Command, anyhow::Result<()>>) {
+        self.clients.lock().unwrap().push((label, client));
+    }
+
+    /// Sends `cmd` to every instance of the sensor named `sensor`, returning
+    /// the number of instances that handled it.
+    ///
+    /// Instances that haven't been brought up yet (or are disabled) still
+    /// handle the command; e.g. a new poll interval is used once they're
+    /// brought up.
+    pub async fn send(&self, sensor: &str, cmd: Command) -> anyhow::Result<usize> {
+        let clients = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(label, _)| label.sensor == sensor)
+            .map(|(label, client)| (*label, client.clone()))
+            .collect::<Vec<_>>();
+
+        for (label, client) in &clients {
+            match client.try_request(cmd.clone()).await {
+                Ok(()) => {}
+                Err(actor::TryReqError::Error(error)) => return Err(error),
+                Err(_) => anyhow::bail!("{label} command channel error"),
+            }
+        }
+
+        Ok(clients.len())
+    }
+}
+
+/// The status of a sensor instance, as reported in [`STATUSES`], in the
+/// `sensor_up` metric, and to event subscribers.
+struct SensorStatus {
+    label: SensorLabel,
+    cell: &'static StatusCell,
+    up: &'static Gauge,
+}
+
+impl SensorStatus {
+    /// Sets the sensor's status, publishing an [`Event`] if it changed.
+    fn set(&self, status: Status) {
+        self.up.set_value(if status.is_up() { 1.0 } else { 0.0 });
+        if self.cell.set_status(status) != status {
+            EVENTS.publish(Event::Status {
+                sensor: self.label,
+                status,
+            });
+        }
+    }
+}
+
+#[cfg(all(test, not(target_os = "espidf")))]
+mod tests {
+    use super::*;
+    use crate::sim;
+    use futures::future::{self, Either};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Waits up to ten seconds for `condition` to become true.
+    async fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            Timer::after(Duration::from_millis(100)).await;
+        }
+        panic!("timed out waiting until {what}");
+    }
+
+    /// Detects sensors of type `S` on `bus`, and returns a [`Manager`] for them
+    /// which polls them every `poll_interval_secs`, if it's set.
+    fn manager<S: AsyncSensor>(bus: &sim::SimBus, poll_interval_secs: Option<u16>) -> Manager {
+        let busman = bus.leak_bus_manager();
+        let mut config = Config::default();
+        config.sensors.insert(
+            S::NAME.to_string(),
+            SensorConfig {
+                poll_interval_secs,
+                ..Default::default()
+            },
+        );
+
+        let mut scanner = Scanner::new(busman, Duration::from_secs(1));
+        scanner.register::<S>().unwrap();
+        scanner.scan();
+
+        Manager {
+            metrics: Box::leak(Box::new(SensorMetrics::new())),
+            busman,
+            config: Box::leak(Box::new(config)),
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+
+    /// Waits until polling the sensor whose status is `status` has failed at
+    /// least twice in a row, and checks that its manager is backing off
+    /// exponentially from `initial`.
+    async fn wait_for_backoff(status: &StatusCell, initial: Duration) {
+        let failures = || {
+            serde_json::to_value(status).unwrap()["consecutive_failures"]
+                .as_u64()
+                .unwrap()
+        };
+        wait_until("the manager backs off", || failures() >= 2).await;
+
+        let diagnostics = serde_json::to_value(status).unwrap();
+        let failures = diagnostics["consecutive_failures"].as_u64().unwrap();
+        assert_eq!(
+            diagnostics["backoff_ms"].as_u64(),
+            Some(initial.as_millis() << (failures - 1)),
+            "backoff after {failures} failures"
+        );
+    }
+
+    /// Runs `test` alongside a sensor manager's `run` future, panicking if the
+    /// manager exits before the test completes.
+    fn run_manager(run: impl Future<Output = anyhow::Result<()>>, test: impl Future<Output = ()>) {
+        futures::pin_mut!(run, test);
+        if let Either::Left((result, _)) = futures::executor::block_on(future::select(run, test)) {
+            panic!("sensor manager exited unexpectedly: {result:?}");
+        }
+    }
+
+    /// A native [`AsyncSensor`], which waits on timers rather than blocking.
+    struct SlowSensor {
+        ready: bool,
+    }
+
+    /// A device that ACKs everything, for [`SlowSensor`] to find on the bus.
+    struct Present;
+
+    const SLOW_ADDR: u8 = 0x42;
+    const SLOW_INIT: Duration = Duration::from_millis(500);
+    static SLOW_INIT_STARTED: AtomicBool = AtomicBool::new(false);
+    static SLOW_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+    impl AsyncSensor for SlowSensor {
+        type ControlMessage = ();
+
+        const NAME: &'static str = "SLOW";
+        const ADDRESSES: &'static [u8] = &[SLOW_ADDR];
+        const DATA_READY_INTERVAL: Duration = Duration::from_millis(10);
+
+        fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+            Ok(scan::is_acked(i2c, addr))
+        }
+
+        async fn init(
+            _: &'static I2cBus,
+            _: u8,
+            _: &'static SensorMetrics,
+            _: &SensorConfig,
+        ) -> anyhow::Result<Self> {
+            SLOW_INIT_STARTED.store(true, Ordering::Release);
+            Timer::after(SLOW_INIT).await;
+            Ok(Self { ready: false })
+        }
+
+        async fn data_ready(&mut self) -> anyhow::Result<bool> {
+            // only have data every other time the manager checks.
+            Timer::after(Duration::from_millis(10)).await;
+            self.ready = !self.ready;
+            Ok(self.ready)
+        }
+
+        async fn poll(&mut self) -> anyhow::Result<()> {
+            Timer::after(Duration::from_millis(50)).await;
+            SLOW_POLLS.fetch_add(1, Ordering::AcqRel);
+            Ok(())
+        }
+
+        fn poll_interval(&self) -> Duration {
+            Duration::from_millis(100)
+        }
+
+        async fn handle_control_message(&mut self, _: &()) -> anyhow::Result<()> {
+            Timer::after(Duration::from_millis(50)).await;
+            Ok(())
+        }
+    }
+
+    impl sim::Device for Present {
+        fn write(&mut self, _: &[u8]) -> Result<(), sim::I2cError> {
+            Ok(())
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<(), sim::I2cError> {
+            buf.fill(0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn manager_drives_async_sensors() {
+        let bus = sim::SimBus::new();
+        bus.attach(SLOW_ADDR, Present);
+
+        let manager = manager::<SlowSensor>(&bus, None);
+        let (ctrl, ctrl_rx) = actor::channel(1);
+        let run = manager.run::<SlowSensor>(SLOW_ADDR, ctrl_rx);
+
+        let label = SensorLabel::new(SlowSensor::NAME, SLOW_ADDR);
+        let status = STATUSES.get_or_register_default(label).unwrap();
+        let test = async {
+            wait_until("the sensor is initializing", || {
+                SLOW_INIT_STARTED.load(Ordering::Acquire)
+            })
+            .await;
+            // the manager yields to other tasks while the sensor initializes,
+            // rather than blocking the executor until it's done.
+            Timer::after(SLOW_INIT / 2).await;
+            assert_eq!(status.status(), Status::Initializing);
+            assert_eq!(SLOW_POLLS.load(Ordering::Acquire), 0);
+
+            wait_until("the sensor is polled", || {
+                status.status() == Status::Up && SLOW_POLLS.load(Ordering::Acquire) >= 2
+            })
+            .await;
+            assert!(ctrl.try_request(()).await.is_ok());
+        };
+
+        run_manager(run, test);
+    }
+
+    #[test]
+    #[cfg(feature = "sensor-pmsa003i")]
+    fn manager_tracks_sensor_errors() {
+        use crate::pmsa003i::Pmsa003i;
+
+        let bus = sim::SimBus::new();
+        let pmsa003i = sim::Pmsa003i::new();
+        pmsa003i.set_concentrations(1, 2, 3);
+        bus.attach(sim::pmsa003i::ADDR, pmsa003i.clone());
+
+        let manager = manager::<Pmsa003i>(&bus, Some(1));
+        let metrics = manager.metrics;
+        let (ctrl, ctrl_rx) = actor::channel(1);
+        let run = manager.run::<Pmsa003i>(sim::pmsa003i::ADDR, ctrl_rx);
+
+        let label = SensorLabel::new(Pmsa003i::NAME, sim::pmsa003i::ADDR);
+        let status = STATUSES.get_or_register_default(label).unwrap();
+        let pm2_5 = || {
+            metrics
+                .pm_conc
+                .metrics()
+                .iter()
+                .find(|(label, _)| label.diameter == "2.5")
+                .map(|(_, gauge)| gauge.value())
+        };
+        let test = async {
+            // the PMSA003I's fan takes a while to spin up.
+            wait_until("the sensor is polled", || {
+                status.status() == Status::WarmingUp && pm2_5() == Some(2.0)
+            })
+            .await;
+            let diagnostics = serde_json::to_value(status).unwrap();
+            assert_eq!(diagnostics["identity"]["version"], "0x80");
+            assert_eq!(diagnostics["last_error"], serde_json::Value::Null);
+
+            // the sensor still ACKs, but its readings are bad.
+            pmsa003i.set_error_code(1);
+            wait_until("the sensor has a protocol error", || {
+                status.status() == Status::ProtocolError
+            })
+            .await;
+            pmsa003i.set_error_code(0);
+
+            bus.detach(sim::pmsa003i::ADDR);
+            wait_until("the sensor has a bus error", || {
+                status.status() == Status::BusError
+            })
+            .await;
+            // readings from a sensor that is down are no longer reported.
+            assert!(!metrics.to_string().contains("pm_concentration_ug_m3{"));
+            let diagnostics = serde_json::to_value(status).unwrap();
+            assert!(diagnostics["last_error"].is_string());
+            assert!(diagnostics["consecutive_failures"].as_u64().unwrap() >= 1);
+
+            pmsa003i.set_concentrations(4, 5, 6);
+            bus.attach(sim::pmsa003i::ADDR, pmsa003i.clone());
+            wait_until("the sensor is back", || {
+                status.status() == Status::WarmingUp && pm2_5() == Some(5.0)
+            })
+            .await;
+
+            // pausing the sensor stops polling it.
+            assert_eq!(
+                COMMANDS.send(Pmsa003i::NAME, Command::Pause).await.unwrap(),
+                1
+            );
+            wait_until("the sensor is disabled", || {
+                status.status() == Status::Disabled
+            })
+            .await;
+            assert!(matches!(
+                ctrl.try_request(()).await,
+                Err(actor::TryReqError::Error(_))
+            ));
+            let polls = serde_json::to_value(status).unwrap()["polls"].clone();
+            Timer::after(Duration::from_secs(2)).await;
+            assert_eq!(serde_json::to_value(status).unwrap()["polls"], polls);
+
+            pmsa003i.set_concentrations(7, 8, 9);
+            assert_eq!(
+                COMMANDS
+                    .send(Pmsa003i::NAME, Command::Resume)
+                    .await
+                    .unwrap(),
+                1
+            );
+            wait_until("the sensor is resumed", || {
+                status.status() == Status::WarmingUp && pm2_5() == Some(8.0)
+            })
+            .await;
+        };
+
+        run_manager(run, test);
+    }
+
+    #[test]
+    #[cfg(feature = "sensor-scd30")]
+    fn manager_runs_scd30() {
+        use crate::scd30::Scd30;
+
+        let bus = sim::SimBus::new();
+        let scd30 = sim::Scd30::new();
+        scd30.set_measurement(800.0, 21.5, 40.0);
+        bus.attach(sim::scd30::ADDR, scd30.clone());
+
+        let manager = manager::<Scd30>(&bus, Some(2));
+        let metrics = manager.metrics;
+        let (_ctrl, ctrl_rx) = actor::channel(1);
+        let run = manager.run::<Scd30>(sim::scd30::ADDR, ctrl_rx);
+
+        let label = SensorLabel::new(Scd30::NAME, sim::scd30::ADDR);
+        let status = STATUSES.get_or_register_default(label).unwrap();
+        let test = async {
+            wait_until("the sensor is polled", || {
+                status.status() == Status::Up
+                    && metrics
+                        .to_string()
+                        .contains("co2_ppm{sensor=\"SCD30\",address=\"0x61\"} 800")
+            })
+            .await;
+            assert_eq!(scd30.measurement_interval_secs(), 2);
+            // the onboard SHT31's readings are reported while the SCD30 is up.
+            let text = metrics.to_string();
+            assert!(text
+                .contains("temperature_degrees_celcius{sensor=\"SHT31\",address=\"0x61\"} 21.5"));
+            assert!(text.contains("humidity_percent{sensor=\"SHT31\",address=\"0x61\"} 40"));
+
+            bus.detach(sim::scd30::ADDR);
+            wait_for_backoff(status, Duration::from_secs(2)).await;
+            assert_eq!(status.status(), Status::BusError);
+            assert!(!metrics.to_string().contains("SHT31"));
+
+            scd30.set_measurement(900.0, 22.0, 45.0);
+            bus.attach(sim::scd30::ADDR, scd30.clone());
+            wait_until("the sensor is back", || {
+                status.status() == Status::Up
+                    && metrics
+                        .to_string()
+                        .contains("co2_ppm{sensor=\"SCD30\",address=\"0x61\"} 900")
+            })
+            .await;
+            assert_eq!(
+                serde_json::to_value(status).unwrap()["backoff_ms"],
+                serde_json::Value::Null
+            );
+        };
+
+        run_manager(run, test);
+    }
+
+    #[test]
+    #[cfg(feature = "sensor-sgp30")]
+    fn manager_runs_sgp30() {
+        use crate::sgp30::{ControlMessage, Sgp30};
+
+        let bus = sim::SimBus::new();
+        let sgp30 = sim::Sgp30::new();
+        bus.attach(sim::sgp30::ADDR, sgp30.clone());
+
+        let manager = manager::<Sgp30>(&bus, None);
+        let (ctrl, ctrl_rx) = actor::channel(1);
+        let run = manager.run::<Sgp30>(sim::sgp30::ADDR, ctrl_rx);
+
+        let label = SensorLabel::new(Sgp30::NAME, sim::sgp30::ADDR);
+        let status = STATUSES.get_or_register_default(label).unwrap();
+        let test = async {
+            // the SGP30's readings are thrown out for its first 15 seconds.
+            wait_until("the sensor is polled", || {
+                status.status() == Status::WarmingUp
+                    && serde_json::to_value(status).unwrap()["polls"].as_u64() >= Some(1)
+            })
+            .await;
+            let diagnostics = serde_json::to_value(status).unwrap();
+            assert_eq!(diagnostics["identity"]["serial"], "000001234567");
+
+            assert!(ctrl
+                .try_request(ControlMessage::SetHumidity {
+                    abs_humidity: Some(8.5),
+                })
+                .await
+                .is_ok());
+            // 8.5 g/m^3 in 8.8 fixed-point.
+            assert_eq!(sgp30.abs_humidity(), 0x0880);
+            assert!(matches!(
+                ctrl.try_request(ControlMessage::SetHumidity {
+                    abs_humidity: Some(300.0),
+                })
+                .await,
+                Err(actor::TryReqError::Error(_))
+            ));
+            assert_eq!(sgp30.abs_humidity(), 0x0880);
+
+            bus.detach(sim::sgp30::ADDR);
+            // the SGP30 is always polled every 988 ms.
+            wait_for_backoff(status, Duration::from_millis(988)).await;
+            assert_eq!(status.status(), Status::BusError);
+
+            bus.attach(sim::sgp30::ADDR, sgp30.clone());
+            wait_until("the sensor is back", || {
+                status.status() == Status::WarmingUp
+            })
+            .await;
+
+            // the SGP30's poll interval can't be changed, even while it's
+            // paused and can't be asked.
+            assert_eq!(COMMANDS.send(Sgp30::NAME, Command::Pause).await.unwrap(), 1);
+            wait_until("the sensor is disabled", || {
+                status.status() == Status::Disabled
+            })
+            .await;
+            let interval = Command::SetPollInterval(Duration::from_secs(5));
+            assert!(COMMANDS.send(Sgp30::NAME, interval).await.is_err());
+        };
+
+        run_manager(run, test);
+    }
+
+    #[test]
+    #[cfg(feature = "sensor-bme680")]
+    fn manager_runs_bme680() {
+        use crate::bme680::Bme680;
+
+        let bus = sim::SimBus::new();
+        let bme680 = sim::Bme680::new();
+        let addr = sim::bme680::ADDRS[0];
+        bus.attach(addr, bme680.clone());
+
+        let manager = manager::<Bme680>(&bus, Some(1));
+        let metrics = manager.metrics;
+        let (_ctrl, ctrl_rx) = actor::channel(1);
+        let run = manager.run::<Bme680>(addr, ctrl_rx);
+
+        let label = SensorLabel::new(Bme680::NAME, addr);
+        let status = STATUSES.get_or_register_default(label).unwrap();
+        let temp = || {
+            metrics
+                .temp
+                .metrics()
+                .iter()
+                .find(|(label, _)| label.sensor == Bme680::NAME)
+                .map(|(_, gauge)| gauge.value())
+        };
+        let test = async {
+            wait_until("the sensor is polled", || {
+                status.status() == Status::Up && temp().is_some()
+            })
+            .await;
+            // the default ADC values work out to roughly 26 degrees.
+            let celcius = temp().unwrap();
+            assert!((20.0..30.0).contains(&celcius), "{celcius} \u{00B0}C");
+            assert!(metrics
+                .to_string()
+                .contains("pressure_hpa{sensor=\"BME680\",address=\"0x76\"}"));
+
+            bus.detach(addr);
+            wait_for_backoff(status, Duration::from_secs(1)).await;
+            assert_eq!(status.status(), Status::BusError);
+
+            bus.attach(addr, bme680.clone());
+            wait_until("the sensor is back", || status.status() == Status::Up).await;
+        };
+
+        run_manager(run, test);
+    }
 }
@@ -0,0 +1,260 @@
+//! Sends [`ControlMessage`](super::Sensor::ControlMessage)s to sensors by
+//! name, without knowing what type of sensor they are.
+//!
+//! Control messages are deserialized from JSON, so that every sensor can be
+//! controlled by the same HTTP route, rather than each sensor type needing its
+//! own hand-written routes.
+use super::AsyncSensor;
+use crate::{
+    actor::{self, Actor},
+    metrics::SensorLabel,
+};
+use futures::{future::LocalBoxFuture, FutureExt};
+use std::{fmt, sync::Mutex};
+
+/// Sends JSON control messages to every sensor instance.
+pub struct Controls {
+    clients: Mutex<Vec<Registration>>,
+}
+
+/// An error returned by [`Controls::send`].
+#[derive(Debug)]
+pub enum ControlError {
+    /// No sensor by that name has been registered.
+    NotFound,
+    /// No instance of the sensor has been brought up yet.
+    NotRunning,
+    /// The JSON was not a valid control message for the sensor.
+    InvalidMessage(serde_json::Error),
+    /// The sensor failed to handle the control message.
+    Sensor {
+        label: SensorLabel,
+        error: anyhow::Error,
+    },
+    /// The sensor's control channel was closed.
+    Closed(SensorLabel),
+}
+
+/// Control message clients for every sensor instance.
+pub static CONTROLS: Controls = Controls::new();
+
+struct Registration {
+    label: SensorLabel,
+    /// Set by the sensor's [`Manager`](super::Manager) while the sensor is
+    /// up. Otherwise, nothing is handling its control messages.
+    running: bool,
+    send: SendJson,
+}
+
+/// Deserializes a control message for one sensor instance, returning a future
+/// that sends it to the sensor and waits for it to be handled.
+type SendJson = Box<
+    dyn Fn(&[u8]) -> Result<LocalBoxFuture<'static, Result<(), ControlError>>, ControlError> + Send,
+>;
+
+/// The number of control messages that may be queued for each sensor.
+const CAPACITY: usize = 10;
+
+// === impl Controls ===
+
+impl Controls {
+    const fn new() -> Self {
+        Self {
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns a new control channel for the instance of `S` at `addr`.
+    ///
+    /// The [`Actor`] should be passed to the sensor's
+    /// [`Manager`](super::Manager). The returned [`actor::Client`] may be used
+    /// to send typed control messages to the sensor; a clone of it is kept in
+    /// the registry, so that JSON control messages can be sent by
+    /// [`Controls::send`].
+    pub fn channel<S>(
+        &self,
+        addr: u8,
+    ) -> (
+        actor::Client<S::ControlMessage, anyhow::Result<()>>,
+        Actor<S::ControlMessage, anyhow::Result<()>>,
+    )
+    where
+        S: AsyncSensor,
+        S::ControlMessage: Send + 'static,
+    {
+        let label = SensorLabel::new(S::NAME, addr);
+        let (client, actor) = actor::channel(CAPACITY);
+        let send = {
+            let client = client.clone();
+            Box::new(move |json: &[u8]| {
+                let msg = serde_json::from_slice::<S::ControlMessage>(json)
+                    .map_err(ControlError::InvalidMessage)?;
+                let client = client.clone();
+                Ok(async move {
+                    match client.try_request(msg).await {
+                        Ok(()) => Ok(()),
+                        Err(actor::TryReqError::Error(error)) => {
+                            Err(ControlError::Sensor { label, error })
+                        }
+                        Err(_) => Err(ControlError::Closed(label)),
+                    }
+                }
+                .boxed_local())
+            })
+        };
+        self.clients.lock().unwrap().push(Registration {
+            label,
+            running: false,
+            send,
+        });
+        (client, actor)
+    }
+
+    /// Sets whether the sensor instance `label` is running. Control messages
+    /// are only sent to running instances.
+    pub(super) fn set_running(&self, label: SensorLabel, running: bool) {
+        for registration in self.clients.lock().unwrap().iter_mut() {
+            if registration.label == label {
+                registration.running = running;
+            }
+        }
+    }
+
+    /// Sends the JSON control message `json` to every running instance of the
+    /// sensor named `sensor` (matched case-insensitively), returning the
+    /// number of instances that handled it.
+    pub async fn send(&self, sensor: &str, json: &[u8]) -> Result<usize, ControlError> {
+        let requests = {
+            let clients = self.clients.lock().unwrap();
+            let mut found = false;
+            let mut requests = Vec::new();
+            for registration in clients.iter() {
+                if !registration.label.sensor.eq_ignore_ascii_case(sensor) {
+                    continue;
+                }
+                found = true;
+                if registration.running {
+                    requests.push((registration.send)(json)?);
+                }
+            }
+
+            if !found {
+                return Err(ControlError::NotFound);
+            }
+            requests
+        };
+
+        if requests.is_empty() {
+            return Err(ControlError::NotRunning);
+        }
+
+        let handled = requests.len();
+        for request in requests {
+            request.await?;
+        }
+        Ok(handled)
+    }
+}
+
+impl fmt::Debug for Controls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let clients = self.clients.lock().unwrap();
+        f.debug_struct("Controls")
+            .field(
+                "sensors",
+                &clients
+                    .iter()
+                    .map(|registration| registration.label)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+// === impl ControlError ===
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("no sensor by that name"),
+            Self::NotRunning => f.write_str("sensor has not been brought up"),
+            Self::InvalidMessage(error) => write!(f, "invalid control message: {error}"),
+            Self::Sensor { label, error } => write!(f, "{label}: {error:#}"),
+            Self::Closed(label) => write!(f, "{label} control channel closed"),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidMessage(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sensor-scd30"))]
+mod tests {
+    use super::*;
+    use crate::scd30::{self, Scd30};
+
+    #[test]
+    fn sends_json_control_messages() {
+        // use a local registry, so that this doesn't race with the sensor
+        // managers tested elsewhere, which register in `CONTROLS`.
+        let controls = Controls::new();
+        let (_client, mut actor) = controls.channel::<Scd30>(0x61);
+        let handler = async move {
+            while let Some(msg) = actor.next_request().await {
+                let rsp = match msg.request() {
+                    scd30::ControlMessage::ForceCalibrate { ppm } if *ppm < 400 => {
+                        Err(anyhow::anyhow!("{ppm} ppm is too low"))
+                    }
+                    _ => Ok(()),
+                };
+                let _ = msg.respond(rsp);
+            }
+        };
+        let test = async {
+            assert!(matches!(
+                controls.send("scd30", br#""soft_reset""#).await,
+                Err(ControlError::NotRunning)
+            ));
+            let label = SensorLabel::new(Scd30::NAME, 0x61);
+            controls.set_running(label, true);
+
+            assert!(matches!(
+                controls.send("nonexistent", b"null").await,
+                Err(ControlError::NotFound)
+            ));
+            assert!(matches!(
+                controls
+                    .send("scd30", br#"{"force_calibrate": {"ppm": 420}}"#)
+                    .await,
+                Ok(1)
+            ));
+            assert!(matches!(
+                controls.send("SCD30", br#""soft_reset""#).await,
+                Ok(1)
+            ));
+            assert!(matches!(
+                controls.send("scd30", br#"{"calibrate": 420}"#).await,
+                Err(ControlError::InvalidMessage(_))
+            ));
+            match controls
+                .send("scd30", br#"{"force_calibrate": {"ppm": 10}}"#)
+                .await
+            {
+                Err(ControlError::Sensor { label: l, error }) => {
+                    assert_eq!(l, label);
+                    assert_eq!(error.to_string(), "10 ppm is too low");
+                }
+                res => panic!("expected a sensor error, got {res:?}"),
+            }
+        };
+
+        futures::pin_mut!(handler, test);
+        futures::executor::block_on(futures::future::select(handler, test));
+    }
+}
@@ -0,0 +1,223 @@
+//! I2C bus scanning and sensor auto-detection.
+//!
+//! Rather than assuming that every sensor type compiled into the firmware is
+//! actually connected, the [`Scanner`] probes each sensor's known I2C
+//! addresses and identifies the part by reading a chip ID or feature set
+//! register. A [`Manager`](super::Manager) won't try to bring up a sensor
+//! until the scanner has detected it, so missing sensors don't hammer the bus
+//! with initialization retries. The scanner periodically rescans the bus for
+//! sensors that haven't been detected yet, so that boards which are plugged in
+//! after the device has started are picked up.
+//!
+//! Each of a sensor's possible addresses is probed separately, so multiple
+//! instances of the same kind of sensor may be detected.
+use super::AsyncSensor;
+use crate::{metrics::SensorLabel, Delay, I2cBus, I2cRef};
+use anyhow::anyhow;
+use embassy_time::{Duration, Timer};
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write},
+};
+use futures::task::AtomicWaker;
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+use tinymetrics::registry::RegistryMap;
+
+/// Scans the I2C bus for known sensors.
+pub struct Scanner {
+    busman: &'static I2cBus,
+    interval: Duration,
+    probes: Vec<Probe>,
+}
+
+/// Tracks whether a sensor instance has been detected.
+#[derive(Debug, Default)]
+pub struct Detected {
+    detected: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// Sensor instances that have been detected on the bus.
+pub static DETECTED: RegistryMap<SensorLabel, Detected, 16> = RegistryMap::new();
+
+struct Probe {
+    label: SensorLabel,
+    probe: fn(&mut I2cRef<'static>, u8) -> anyhow::Result<bool>,
+    detected: &'static Detected,
+}
+
+const TARGET: &str = "eclss::scan";
+
+// === impl Scanner ===
+
+impl Scanner {
+    pub fn new(busman: &'static I2cBus, interval: Duration) -> Self {
+        Self {
+            busman,
+            interval,
+            probes: Vec::new(),
+        }
+    }
+
+    /// Adds a sensor type to the set of sensors this scanner looks for.
+    ///
+    /// Every address in [`AsyncSensor::ADDRESSES`] will be probed.
+    pub fn register<S: AsyncSensor>(&mut self) -> anyhow::Result<&mut Self> {
+        for &addr in S::ADDRESSES {
+            let label = SensorLabel::new(S::NAME, addr);
+            let detected = DETECTED
+                .get_or_register_default(label)
+                .ok_or_else(|| anyhow!("insufficient space in detected map for {label}"))?;
+            self.probes.push(Probe {
+                label,
+                probe: S::probe,
+                detected,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Probes the bus for every registered sensor instance that hasn't been
+    /// detected yet.
+    pub fn scan(&self) {
+        let mut i2c = self.busman.acquire_i2c();
+        for probe in &self.probes {
+            if probe.detected.is_detected() {
+                continue;
+            }
+
+            let SensorLabel {
+                sensor, address, ..
+            } = probe.label;
+            match (probe.probe)(&mut i2c, address) {
+                Ok(true) => {
+                    log::info!(target: TARGET, "detected {sensor} at {address:#04x}");
+                    probe.detected.set_detected();
+                }
+                Ok(false) => {
+                    log::info!(target: TARGET, "device at {address:#04x} is not a {sensor}")
+                }
+                // most likely, nothing is there (the address was NACKed).
+                Err(error) => {
+                    log::debug!(target: TARGET, "no {sensor} at {address:#04x}: {error}")
+                }
+            }
+        }
+    }
+
+    /// Periodically rescans the bus until every registered sensor has been
+    /// detected.
+    pub async fn run(self) -> anyhow::Result<()> {
+        loop {
+            self.scan();
+
+            if self.probes.iter().all(|probe| probe.detected.is_detected()) {
+                log::info!(target: TARGET, "all sensors detected; done scanning");
+                return Ok(());
+            }
+
+            Timer::after(self.interval).await;
+        }
+    }
+}
+
+// === impl Detected ===
+
+impl Detected {
+    #[must_use]
+    pub fn is_detected(&self) -> bool {
+        self.detected.load(Ordering::Acquire)
+    }
+
+    /// Waits until the sensor has been detected.
+    pub fn wait(&self) -> impl Future<Output = ()> + '_ {
+        futures::future::poll_fn(|cx| {
+            if self.is_detected() {
+                return Poll::Ready(());
+            }
+
+            self.waker.register(cx.waker());
+            // check again, in case the sensor was detected while we were
+            // registering the waker.
+            if self.is_detected() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+
+    fn set_detected(&self) {
+        self.detected.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+// === probe helpers ===
+
+/// Returns `true` if anything ACKs `addr`.
+///
+/// This performs an address-only write, which doesn't send any data to the
+/// device, so it's safe to use regardless of what the device is doing.
+pub(crate) fn is_acked(i2c: &mut I2cRef<'static>, addr: u8) -> bool {
+    i2c.write(addr, &[]).is_ok()
+}
+
+/// Sends a command to a Sensirion sensor and reads back a single CRC-checked
+/// word.
+///
+/// This is used for reading identification registers (such as firmware
+/// versions or feature sets) when probing Sensirion parts.
+pub(crate) fn sensirion_read_word(
+    i2c: &mut I2cRef<'static>,
+    addr: u8,
+    cmd: u16,
+    delay_ms: u8,
+) -> anyhow::Result<u16> {
+    i2c.write(addr, &cmd.to_be_bytes())
+        .map_err(|error| anyhow!("failed to write command {cmd:#06x}: {error:?}"))?;
+    Delay.delay_ms(delay_ms);
+    let mut buf = [0; 3];
+    i2c.read(addr, &mut buf)
+        .map_err(|error| anyhow!("failed to read response to {cmd:#06x}: {error:?}"))?;
+    let crc = sensirion_crc8(&buf[..2]);
+    anyhow::ensure!(
+        crc == buf[2],
+        "CRC mismatch in response to {cmd:#06x} (expected {crc:#04x}, got {:#04x})",
+        buf[2]
+    );
+    Ok(u16::from_be_bytes([buf[0], buf[1]]))
+}
+
+/// Computes the CRC-8 checksum used by Sensirion sensors (polynomial 0x31,
+/// initialization 0xFF).
+pub(crate) fn sensirion_crc8(data: &[u8]) -> u8 {
+    const POLYNOMIAL: u8 = 0x31;
+    let mut crc = 0xFF;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 0x80 != 0 {
+                crc = (crc << 1) ^ POLYNOMIAL;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensirion_crc8_matches_datasheet() {
+        // example from the SCD30 and SGP30 datasheets
+        assert_eq!(sensirion_crc8(&[0xBE, 0xEF]), 0x92);
+    }
+}
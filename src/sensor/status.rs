@@ -1,66 +1,189 @@
-use serde::{Deserialize, Serialize, Serializer};
+use embassy_time::{Duration, Instant};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use std::{
+    collections::BTreeMap,
     fmt,
-    sync::atomic::{AtomicU8, Ordering},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
 };
 
 /// Represents the status of an I2C sensor.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Status {
-    /// A sensor of this type has never been initialized. It is likely that a
-    /// sensor of this type is not connected to the bus.
+    /// A sensor of this type has never been detected on the bus. It is likely
+    /// that a sensor of this type is not connected.
     Missing,
 
+    /// The sensor has been detected on the bus, and is being initialized.
+    Initializing,
+
+    /// The sensor has been initialized, but its readings aren't meaningful
+    /// yet (such as while the SGP30 is in its initialization phase, or while
+    /// the PMSA003I's fan is spinning up).
+    WarmingUp,
+
+    /// The sensor is healthy and its readings are reported, but it's still
+    /// calibrating itself, so they may be less accurate than usual.
+    Calibrating,
+
     /// The sensor is connected and healthy.
     Up,
 
-    /// The sensor has previously been brought up successfully, but it is no
-    /// longer healthy.
-    // TODO(eliza): can we distinguish between I2C bus disconnection and other
-    // errors?
-    Down,
+    /// The sensor has stopped responding on the I2C bus (its address is not
+    /// ACKed). It was most likely disconnected.
+    BusError,
+
+    /// The sensor is still responding on the I2C bus, but talking to it
+    /// failed (for example, due to a checksum mismatch or an unexpected
+    /// response).
+    ProtocolError,
+
+    /// The sensor has been disabled, and isn't being polled until it's
+    /// resumed.
+    Disabled,
 }
 
 impl Status {
+    const ALL: [Status; 8] = [
+        Status::Missing,
+        Status::Initializing,
+        Status::WarmingUp,
+        Status::Calibrating,
+        Status::Up,
+        Status::BusError,
+        Status::ProtocolError,
+        Status::Disabled,
+    ];
+
     fn from_u8(u: u8) -> Self {
-        match u {
-            u if u == Status::Missing as u8 => Status::Missing,
-            u if u == Status::Up as u8 => Status::Up,
-            u if u == Status::Down as u8 => Status::Down,
+        Self::ALL
+            .get(u as usize)
+            .copied()
             // Weird status, assume missing?
-            _ => Status::Missing,
-        }
+            .unwrap_or(Status::Missing)
+    }
+
+    /// Returns `true` if the sensor is healthy and its readings should be
+    /// reported.
+    #[must_use]
+    pub fn is_up(self) -> bool {
+        matches!(self, Status::Up | Status::Calibrating)
+    }
+
+    /// Returns `true` if the sensor was brought up, but has since failed.
+    #[must_use]
+    pub fn is_error(self) -> bool {
+        matches!(self, Status::BusError | Status::ProtocolError)
     }
 }
 
-pub struct StatusCell(AtomicU8);
+/// A sensor instance's [`Status`], along with diagnostic details about its
+/// recent history.
+///
+/// When serialized, this includes the diagnostics, so that a misbehaving
+/// sensor can be triaged from `/sensors/status.json`.
+pub struct StatusCell {
+    status: AtomicU8,
+    diagnostics: Mutex<Diagnostics>,
+}
+
+/// Identifying details reported by a sensor's driver, such as its firmware
+/// version or serial number.
+pub type Identity = BTreeMap<&'static str, String>;
+
+struct Diagnostics {
+    /// The most recent error, if the sensor has failed since it was last
+    /// polled successfully.
+    last_error: Option<String>,
+    last_success: Option<Instant>,
+    consecutive_failures: usize,
+    /// How long the sensor manager is backing off for, if it's backing off.
+    backoff: Option<Duration>,
+    polls: usize,
+    identity: Identity,
+}
 
 impl StatusCell {
     pub const fn new() -> Self {
-        Self(AtomicU8::new(Status::Missing as u8))
+        Self {
+            status: AtomicU8::new(Status::Missing as u8),
+            diagnostics: Mutex::new(Diagnostics {
+                last_error: None,
+                last_success: None,
+                consecutive_failures: 0,
+                backoff: None,
+                polls: 0,
+                identity: BTreeMap::new(),
+            }),
+        }
     }
 
     pub fn set_status(&self, status: Status) -> Status {
-        let prev = self.0.swap(status as u8, Ordering::AcqRel);
+        let prev = self.status.swap(status as u8, Ordering::AcqRel);
         Status::from_u8(prev)
     }
 
     #[must_use]
     pub fn status(&self) -> Status {
-        Status::from_u8(self.0.load(Ordering::Acquire))
+        Status::from_u8(self.status.load(Ordering::Acquire))
+    }
+
+    /// Records that the sensor was polled successfully.
+    pub fn record_success(&self) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        diagnostics.last_error = None;
+        diagnostics.last_success = Some(Instant::now());
+        diagnostics.consecutive_failures = 0;
+        diagnostics.backoff = None;
+        diagnostics.polls += 1;
+    }
+
+    /// Records that talking to the sensor failed with `error`, and that its
+    /// manager is backing off for `backoff` before trying again.
+    pub fn record_error(&self, error: &anyhow::Error, backoff: Duration) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        diagnostics.last_error = Some(format!("{error:#}"));
+        diagnostics.consecutive_failures += 1;
+        diagnostics.backoff = Some(backoff);
+    }
+
+    /// Sets the identifying details reported by the sensor's driver.
+    pub fn set_identity(&self, identity: Identity) {
+        self.diagnostics.lock().unwrap().identity = identity;
     }
 }
 
 impl fmt::Debug for StatusCell {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("StatusCell").field(&self.status()).finish()
+        f.debug_struct("StatusCell")
+            .field("status", &self.status())
+            .finish_non_exhaustive()
     }
 }
 
 impl Serialize for StatusCell {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        self.status().serialize(serializer)
+        let diagnostics = self.diagnostics.lock().unwrap();
+        let mut state = serializer.serialize_struct("StatusCell", 7)?;
+        state.serialize_field("status", &self.status())?;
+        state.serialize_field("last_error", &diagnostics.last_error)?;
+        state.serialize_field(
+            "secs_since_last_success",
+            &diagnostics
+                .last_success
+                .map(|last_success| last_success.elapsed().as_secs()),
+        )?;
+        state.serialize_field("consecutive_failures", &diagnostics.consecutive_failures)?;
+        state.serialize_field(
+            "backoff_ms",
+            &diagnostics.backoff.map(|backoff| backoff.as_millis()),
+        )?;
+        state.serialize_field("polls", &diagnostics.polls)?;
+        state.serialize_field("identity", &diagnostics.identity)?;
+        state.end()
     }
 }
 
@@ -69,3 +192,50 @@ impl Default for StatusCell {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_roundtrip_through_cell() {
+        let cell = StatusCell::new();
+        for status in Status::ALL {
+            cell.set_status(status);
+            assert_eq!(cell.status(), status);
+        }
+    }
+
+    #[test]
+    fn serializes_diagnostics() {
+        let cell = StatusCell::new();
+        cell.set_status(Status::Up);
+        cell.set_identity(Identity::from([("firmware", "3.66".to_string())]));
+        cell.record_success();
+        cell.record_error(
+            &anyhow::anyhow!("CRC mismatch").context("failed to read measurement"),
+            Duration::from_secs(2),
+        );
+        cell.record_error(&anyhow::anyhow!("NACK"), Duration::from_secs(4));
+
+        let json = serde_json::to_value(&cell).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "status": "Up",
+                "last_error": "NACK",
+                "secs_since_last_success": 0,
+                "consecutive_failures": 2,
+                "backoff_ms": 4000,
+                "polls": 1,
+                "identity": { "firmware": "3.66" },
+            })
+        );
+
+        cell.record_success();
+        let json = serde_json::to_value(&cell).unwrap();
+        assert_eq!(json["last_error"], serde_json::Value::Null);
+        assert_eq!(json["consecutive_failures"], 0);
+        assert_eq!(json["polls"], 2);
+    }
+}
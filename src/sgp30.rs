@@ -1,44 +1,190 @@
 use crate::{
+    compensation::COMPENSATION,
+    config::SensorConfig,
     metrics::{Gauge, SensorLabel},
-    sensor::Sensor,
-    I2cBus, I2cRef, SensorMetrics,
+    nvs::{Nvs, Partition},
+    sensor::{scan, Identity, Sensor, Status},
+    Delay, I2cBus, I2cRef, SensorMetrics,
 };
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use embassy_time::Duration;
-use esp_idf_hal::delay::Ets;
-use std::{num::Wrapping, time::Instant};
-use tinymetrics::GaugeFamily;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    num::Wrapping,
+    sync::{Mutex, OnceLock},
+    time::{Instant, SystemTime},
+};
 
 pub struct Sgp30 {
-    sensor: sgp30::Sgp30<I2cRef<'static>, Ets>,
+    sensor: sgp30::Sgp30<I2cRef<'static>, Delay>,
     eco2_gauge: &'static Gauge,
     tvoc_gauge: &'static Gauge,
-    abs_humidity: &'static GaugeFamily<'static, 4, SensorLabel>,
+    h2_raw_gauge: &'static Gauge,
+    ethanol_raw_gauge: &'static Gauge,
+    /// An absolute humidity (in g/m^3) to compensate for, overriding the
+    /// humidity measured by other sensors.
+    humidity_override: Option<f32>,
     started_at: Instant,
     polls: Wrapping<usize>,
     init: bool,
+    last_baseline_save: Instant,
+    identity: Identity,
+}
+
+/// Persists the SGP30's dynamic baseline in NVS, so that it doesn't have to
+/// be re-learned after every reboot.
+///
+/// The baseline algorithm takes about 12 hours to converge, so a fresh
+/// baseline isn't saved until the sensor has been running for that long.
+/// After that, the baseline is saved every hour. A stored baseline is only
+/// restored if it's less than a week old, per the datasheet.
+struct BaselineStore {
+    nvs: Mutex<Nvs>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlMessage {
+    /// Reads the sensor's current baseline into [`BASELINE`].
+    GetBaseline,
+    /// Sets the sensor's baseline.
+    SetBaseline(Baseline),
+    /// Resets the baseline algorithm, discarding the current and stored
+    /// baselines. A new baseline will be learned from scratch.
+    ResetBaseline,
+    /// Compensates for the given absolute humidity (in g/m^3), rather than
+    /// the humidity measured by other sensors. If this is `None`, the
+    /// humidity measured by other sensors is used again.
+    ///
+    /// Humidities the sensor can't compensate for are rejected when the
+    /// message is deserialized (see [`check_abs_humidity`]).
+    SetHumidity {
+        #[serde(default, deserialize_with = "deserialize_abs_humidity")]
+        abs_humidity: Option<f32>,
+    },
+    /// Measures the raw H2 and ethanol signals into [`RAW_SIGNALS`] and the
+    /// raw signal gauges.
+    MeasureRawSignals,
 }
 
+/// The SGP30 takes absolute humidity as an 8.8 fixed-point number of g/m^3,
+/// so it can only compensate for humidities less than this.
+const MAX_ABS_HUMIDITY: f32 = 256.0;
+
+/// The SGP30's baseline compensation values.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, serde::Deserialize)]
+pub struct Baseline {
+    pub co2eq: u16,
+    pub tvoc: u16,
+}
+
+/// Raw sensor signals, as measured by [`ControlMessage::MeasureRawSignals`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct RawSignals {
+    pub h2: u16,
+    pub ethanol: u16,
+}
+
+/// A baseline, and when it was saved.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct StoredBaseline {
+    co2eq: u16,
+    tvoc: u16,
+    /// When the baseline was saved, as a UNIX timestamp.
+    saved_at: u64,
+}
+
+/// Whether the SGP30 is running on a restored or fresh baseline.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum BaselineStatus {
+    /// The sensor hasn't been initialized yet, or the clock hasn't been set,
+    /// so the age of the stored baseline can't be checked yet.
+    Pending,
+    /// A baseline saved at `saved_at` (a UNIX timestamp) was restored.
+    Restored { saved_at: u64 },
+    /// A baseline was set by a [`ControlMessage::SetBaseline`].
+    Set,
+    /// No recent baseline was stored, so a new baseline is being learned.
+    Fresh,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BaselineInfo {
+    #[serde(flatten)]
+    pub status: BaselineStatus,
+    /// When the baseline was last saved, as a UNIX timestamp.
+    pub last_saved_at: Option<u64>,
+    /// The baseline that was most recently read from, or written to, the
+    /// sensor.
+    pub baseline: Option<Baseline>,
+}
+
+/// The SGP30's current baseline status.
+pub static BASELINE: Mutex<BaselineInfo> = Mutex::new(BaselineInfo {
+    status: BaselineStatus::Pending,
+    last_saved_at: None,
+    baseline: None,
+});
+
+/// The most recently measured raw signals.
+pub static RAW_SIGNALS: Mutex<Option<RawSignals>> = Mutex::new(None);
+
+static BASELINE_STORE: OnceLock<BaselineStore> = OnceLock::new();
+
+/// How long a fresh baseline must be learned before it's saved.
+const BASELINE_CONVERGENCE: std::time::Duration = std::time::Duration::from_secs(12 * 60 * 60);
+/// How often the baseline is saved.
+const BASELINE_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// How old a stored baseline may be and still be restored.
+const BASELINE_MAX_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
 const NAME: &'static str = "SGP30";
 
 impl Sensor for Sgp30 {
-    type ControlMessage = ();
+    type ControlMessage = ControlMessage;
 
     const NAME: &'static str = NAME;
+    // the adafruit breakout board has this I2C address.
+    const ADDRESSES: &'static [u8] = &[0x58];
 
-    fn init(busman: &'static I2cBus, metrics: &'static SensorMetrics) -> anyhow::Result<Self> {
-        // the adafruit breakout board has this I2C address.
-        const ADDR: u8 = 0x58;
+    fn probe(i2c: &mut I2cRef<'static>, addr: u8) -> anyhow::Result<bool> {
+        // read the feature set register. the upper 4 bits are the product
+        // type, which is 0 for the SGP30.
+        let feature_set = scan::sensirion_read_word(i2c, addr, 0x202F, 10)?;
+        log::debug!(target: NAME, "probe: feature set {feature_set:#06x}");
+        Ok(feature_set >> 12 == 0)
+    }
 
-        log::info!(target: NAME, "connecting to {NAME}...");
+    fn init(
+        busman: &'static I2cBus,
+        addr: u8,
+        metrics: &'static SensorMetrics,
+        config: &SensorConfig,
+    ) -> anyhow::Result<Self> {
+        if let Some(secs) = config.poll_interval_secs {
+            log::warn!(target: NAME, "{NAME} must be polled every second, ignoring configured poll interval of {secs} seconds");
+        }
+
+        let label = SensorLabel::new(NAME, addr);
+        log::info!(target: NAME, "connecting to {label}...");
         let i2c = busman.acquire_i2c();
-        let mut sensor = sgp30::Sgp30::new(i2c, ADDR, Ets);
+        let mut sensor = sgp30::Sgp30::new(i2c, addr, Delay);
 
         let version = sensor
             .get_feature_set()
             .map_err(|error| anyhow!("failed to get {NAME} feature set: {error:?}"))?;
         log::info!(target: NAME, "connected to {NAME}: version: {version:?}");
 
+        let serial = sensor
+            .serial()
+            .map_err(|error| anyhow!("failed to read {NAME} serial number: {error:?}"))?;
+        let serial = serial
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        log::info!(target: NAME, "{NAME} serial number: {serial}");
+
         // run the self-test
         let selftest = sensor
             .selftest()
@@ -52,14 +198,23 @@ impl Sensor for Sgp30 {
             .init()
             .map_err(|error| anyhow!("failed to initialize {NAME}: {error:?}"))?;
 
+        // the baseline algorithm restarts when the sensor is initialized, so
+        // check for a stored baseline again.
+        BASELINE.lock().unwrap().status = BaselineStatus::Pending;
+
+        let now = Instant::now();
         Ok(Self {
             sensor,
-            eco2_gauge: metrics.eco2.register(Self::LABEL).unwrap(),
-            tvoc_gauge: metrics.tvoc.register(Self::LABEL).unwrap(),
-            abs_humidity: &metrics.abs_humidity,
+            eco2_gauge: metrics.eco2.register(label).unwrap(),
+            tvoc_gauge: metrics.tvoc.register(label).unwrap(),
+            h2_raw_gauge: metrics.h2_raw.register(label).unwrap(),
+            ethanol_raw_gauge: metrics.ethanol_raw.register(label).unwrap(),
+            humidity_override: None,
             polls: Wrapping(0),
-            started_at: Instant::now(),
+            started_at: now,
             init: true,
+            last_baseline_save: now,
+            identity: Identity::from([("feature_set", format!("{version:?}")), ("serial", serial)]),
         })
     }
 
@@ -73,6 +228,10 @@ impl Sensor for Sgp30 {
             .map_err(|error| anyhow!("failed to read {NAME} measurement: {error:?}"))?;
         self.polls += 1;
 
+        if let Err(error) = self.maintain_baseline() {
+            log::warn!(target: NAME, "{error:#}");
+        }
+
         // the SGP30 has a 15-second initialization phase after startup, during which it
         // calibrates itself. while the sensor is initializing, all measurements will
         // read 400 ppm eCO2 and 0 ppb tVOC. we don't want to report these values, so
@@ -99,25 +258,157 @@ impl Sensor for Sgp30 {
             return Ok(());
         }
 
-        let humidity = {
-            let mut count = 0;
-            let sum: f32 = self
-                .abs_humidity
-                .metrics()
-                .iter()
-                .map(|(_, gauge)| {
-                    count += 1;
-                    gauge.value() as f32
-                })
-                .sum();
-            sum / count as f32
+        let Some(humidity) = self
+            .humidity_override
+            .or_else(|| COMPENSATION.abs_humidity.get())
+        else {
+            // no recent humidity readings...
+            return Ok(());
         };
 
-        if humidity == 0.0 {
-            // no humidity readings yet...
-            return Ok(());
+        self.set_humidity(humidity)
+    }
+
+    fn poll_interval(&self) -> Duration {
+        // per https://docs.rs/sgp30/latest/sgp30/index.html#doing-measurements,
+        // the sensor MUST be polled every second, or else its dynamic baseline
+        // calibration thingy gets messed up. per the datasheet, doing a reading
+        // takes 12 ms, so the actual poll interval is less than 1 second.
+        Duration::from_secs(1) - Duration::from_millis(12)
+    }
+
+    fn status(&self) -> Status {
+        if self.init {
+            return Status::WarmingUp;
+        }
+
+        // until a baseline is restored, the baseline algorithm is learning a
+        // new baseline from scratch, which takes about 12 hours.
+        let learning = matches!(
+            BASELINE.lock().unwrap().status,
+            BaselineStatus::Pending | BaselineStatus::Fresh
+        );
+        if learning && self.started_at.elapsed() < BASELINE_CONVERGENCE {
+            Status::Calibrating
+        } else {
+            Status::Up
         }
+    }
 
+    fn identity(&self) -> Identity {
+        self.identity.clone()
+    }
+
+    fn handle_control_message(&mut self, msg: &Self::ControlMessage) -> anyhow::Result<()> {
+        match msg {
+            ControlMessage::GetBaseline => {
+                let sgp30::Baseline { co2eq, tvoc } = self
+                    .sensor
+                    .get_baseline()
+                    .map_err(|error| anyhow!("failed to read {NAME} baseline: {error:?}"))?;
+                let baseline = Baseline { co2eq, tvoc };
+                log::info!(target: NAME, "current baseline: {baseline:?}");
+                BASELINE.lock().unwrap().baseline = Some(baseline);
+            }
+            &ControlMessage::SetBaseline(baseline) => {
+                self.sensor
+                    .set_baseline(&sgp30::Baseline {
+                        co2eq: baseline.co2eq,
+                        tvoc: baseline.tvoc,
+                    })
+                    .map_err(|error| {
+                        anyhow!("failed to set {NAME} baseline to {baseline:?}: {error:?}")
+                    })?;
+                log::info!(target: NAME, "set baseline to {baseline:?}");
+                let mut info = BASELINE.lock().unwrap();
+                info.status = BaselineStatus::Set;
+                info.baseline = Some(baseline);
+            }
+            ControlMessage::ResetBaseline => {
+                // re-initializing the sensor restarts the baseline algorithm.
+                self.sensor
+                    .init()
+                    .map_err(|error| anyhow!("failed to reset {NAME} baseline: {error:?}"))?;
+                if let Some(store) = BASELINE_STORE.get() {
+                    store.clear()?;
+                }
+                self.polls = Wrapping(0);
+                self.started_at = Instant::now();
+                self.last_baseline_save = self.started_at;
+                self.init = true;
+                *BASELINE.lock().unwrap() = BaselineInfo {
+                    status: BaselineStatus::Fresh,
+                    last_saved_at: None,
+                    baseline: None,
+                };
+                log::info!(target: NAME, "reset baseline");
+            }
+            &ControlMessage::SetHumidity { abs_humidity } => {
+                match abs_humidity {
+                    Some(humidity) => {
+                        // don't store an override that would be rejected
+                        // again on every poll.
+                        check_abs_humidity(humidity)?;
+                        self.set_humidity(humidity)?;
+                    }
+                    None => log::info!(target: NAME, "cleared absolute humidity override"),
+                }
+                self.humidity_override = abs_humidity;
+            }
+            ControlMessage::MeasureRawSignals => {
+                let sgp30::RawSignals { h2, ethanol } = self
+                    .sensor
+                    .measure_raw_signals()
+                    .map_err(|error| anyhow!("failed to measure {NAME} raw signals: {error:?}"))?;
+                log::info!(target: NAME, "raw signals: H2: {h2}, ethanol: {ethanol}");
+                self.h2_raw_gauge.set_value(h2.into());
+                self.ethanol_raw_gauge.set_value(ethanol.into());
+                *RAW_SIGNALS.lock().unwrap() = Some(RawSignals { h2, ethanol });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the SGP30 can compensate for an absolute humidity of
+/// `abs_humidity` g/m^3.
+pub fn check_abs_humidity(abs_humidity: f32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (0.0..MAX_ABS_HUMIDITY).contains(&abs_humidity),
+        "absolute humidity must be at least 0 and less than {MAX_ABS_HUMIDITY} g/m³ (got {abs_humidity})"
+    );
+    Ok(())
+}
+
+/// Deserializes an optional absolute humidity, rejecting humidities that the
+/// SGP30 can't compensate for.
+pub fn deserialize_abs_humidity<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let abs_humidity = Option::<f32>::deserialize(deserializer)?;
+    if let Some(abs_humidity) = abs_humidity {
+        check_abs_humidity(abs_humidity).map_err(serde::de::Error::custom)?;
+    }
+    Ok(abs_humidity)
+}
+
+/// Opens the NVS namespace used to persist the SGP30's baseline.
+///
+/// If this isn't called before the SGP30 is brought up, the baseline won't be
+/// persisted.
+pub fn init_baseline_store(partition: Partition) -> anyhow::Result<()> {
+    let store = BaselineStore::new(partition)?;
+    if BASELINE_STORE.set(store).is_err() {
+        log::warn!(target: NAME, "baseline store already initialized");
+    }
+    Ok(())
+}
+
+impl Sgp30 {
+    /// Sets the absolute humidity (in g/m^3) that the sensor compensates for.
+    fn set_humidity(&mut self, humidity: f32) -> anyhow::Result<()> {
         match sgp30::Humidity::from_f32(humidity) {
             Ok(val) => {
                 self.sensor.set_humidity(Some(&val)).map_err(|error| {
@@ -135,16 +426,277 @@ impl Sensor for Sgp30 {
         Ok(())
     }
 
-    fn poll_interval(&self) -> Duration {
-        // per https://docs.rs/sgp30/latest/sgp30/index.html#doing-measurements,
-        // the sensor MUST be polled every second, or else its dynamic baseline
-        // calibration thingy gets messed up. per the datasheet, doing a reading
-        // takes 12 ms, so the actual poll interval is less than 1 second.
-        Duration::from_secs(1) - Duration::from_millis(12)
+    /// Restores a stored baseline once the clock has been set, and saves the
+    /// current baseline periodically.
+    fn maintain_baseline(&mut self) -> anyhow::Result<()> {
+        let Some(store) = BASELINE_STORE.get() else {
+            return Ok(());
+        };
+        // without a wall-clock time, we can't tell how old a stored baseline is,
+        // or record when a baseline was saved.
+        let Some(now) = unix_time() else {
+            return Ok(());
+        };
+
+        let status = BASELINE.lock().unwrap().status;
+        match status {
+            BaselineStatus::Pending => {
+                let status = self.load_baseline(store, now);
+                BASELINE.lock().unwrap().status = status;
+                self.last_baseline_save = Instant::now();
+            }
+            BaselineStatus::Fresh if self.started_at.elapsed() < BASELINE_CONVERGENCE => {}
+            _ if self.last_baseline_save.elapsed() < BASELINE_SAVE_INTERVAL => {}
+            _ => {
+                let sgp30::Baseline { co2eq, tvoc } = self
+                    .sensor
+                    .get_baseline()
+                    .map_err(|error| anyhow!("failed to read {NAME} baseline: {error:?}"))?;
+                let stored = StoredBaseline {
+                    co2eq,
+                    tvoc,
+                    saved_at: now,
+                };
+                self.last_baseline_save = Instant::now();
+                store.save(&stored)?;
+                let mut info = BASELINE.lock().unwrap();
+                info.last_saved_at = Some(now);
+                info.baseline = Some(Baseline { co2eq, tvoc });
+                log::info!(target: NAME, "saved baseline {stored:?}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores the baseline stored in `store`, if it's recent enough,
+    /// returning the resulting [`BaselineStatus`].
+    ///
+    /// If the stored baseline can't be loaded or restored, it's erased, so
+    /// that a fresh baseline is learned (and eventually saved) instead of
+    /// retrying the bad one on every poll.
+    fn load_baseline(&mut self, store: &BaselineStore, now: u64) -> BaselineStatus {
+        match self.restore_baseline(store, now) {
+            Ok(status) => status,
+            Err(error) => {
+                log::warn!(target: NAME, "{error:#}; erasing it and learning a new baseline");
+                if let Err(error) = store.clear() {
+                    log::warn!(target: NAME, "{error:#}");
+                }
+                BaselineStatus::Fresh
+            }
+        }
+    }
+
+    fn restore_baseline(
+        &mut self,
+        store: &BaselineStore,
+        now: u64,
+    ) -> anyhow::Result<BaselineStatus> {
+        let status = match store.load()? {
+            Some(stored) if stored.is_recent(now) => {
+                self.sensor
+                    .set_baseline(&sgp30::Baseline {
+                        co2eq: stored.co2eq,
+                        tvoc: stored.tvoc,
+                    })
+                    .map_err(|error| {
+                        anyhow!("failed to restore stored {NAME} baseline: {error:?}")
+                    })?;
+                log::info!(target: NAME, "restored baseline {stored:?}");
+                BASELINE.lock().unwrap().baseline = Some(Baseline {
+                    co2eq: stored.co2eq,
+                    tvoc: stored.tvoc,
+                });
+                BaselineStatus::Restored {
+                    saved_at: stored.saved_at,
+                }
+            }
+            Some(stored) => {
+                log::info!(target: NAME, "stored baseline {stored:?} is too old; learning a new baseline");
+                BaselineStatus::Fresh
+            }
+            None => {
+                log::info!(target: NAME, "no stored baseline; learning a new baseline");
+                BaselineStatus::Fresh
+            }
+        };
+        Ok(status)
+    }
+}
+
+/// Returns the current UNIX time, or `None` if the clock hasn't been set by
+/// SNTP yet.
+fn unix_time() -> Option<u64> {
+    // 2023-01-01T00:00:00Z. if the clock says it's earlier than this, it
+    // probably hasn't been set yet.
+    const CLOCK_SET_AFTER: u64 = 1_672_531_200;
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|since_epoch| since_epoch.as_secs())
+        .filter(|&secs| secs > CLOCK_SET_AFTER)
+}
+
+// === impl BaselineStore ===
+
+impl BaselineStore {
+    const NAMESPACE: &'static str = "eclss";
+    const KEY: &'static str = "sgp30_baseline";
+
+    fn new(partition: Partition) -> anyhow::Result<Self> {
+        let nvs = Nvs::new(partition, Self::NAMESPACE, true)
+            .context("failed to open SGP30 baseline NVS namespace")?;
+        Ok(Self {
+            nvs: Mutex::new(nvs),
+        })
+    }
+
+    fn load(&self) -> anyhow::Result<Option<StoredBaseline>> {
+        let mut buf = [0; StoredBaseline::LEN];
+        let nvs = self.nvs.lock().unwrap();
+        let Some(bytes) = nvs
+            .get_raw(Self::KEY, &mut buf)
+            .context("failed to read SGP30 baseline from NVS")?
+        else {
+            return Ok(None);
+        };
+        StoredBaseline::from_bytes(bytes).map(Some)
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        self.nvs
+            .lock()
+            .unwrap()
+            .remove(Self::KEY)
+            .context("failed to remove SGP30 baseline from NVS")?;
+        Ok(())
     }
 
-    fn handle_control_message(&mut self, _: &Self::ControlMessage) -> anyhow::Result<()> {
-        // TODO(eliza): calibrate with absolute humidity?
-        anyhow::bail!("not yet implemented")
+    fn save(&self, baseline: &StoredBaseline) -> anyhow::Result<()> {
+        self.nvs
+            .lock()
+            .unwrap()
+            .set_raw(Self::KEY, &baseline.to_bytes())
+            .context("failed to save SGP30 baseline to NVS")?;
+        Ok(())
+    }
+}
+
+// === impl StoredBaseline ===
+
+impl StoredBaseline {
+    const LEN: usize = 2 + 2 + 8;
+
+    fn is_recent(&self, now: u64) -> bool {
+        now.saturating_sub(self.saved_at) < BASELINE_MAX_AGE_SECS
+    }
+
+    fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0..2].copy_from_slice(&self.co2eq.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.tvoc.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.saved_at.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let bytes: &[u8; Self::LEN] = bytes.try_into().with_context(|| {
+            format!(
+                "stored SGP30 baseline must be {} bytes, but it was {} bytes",
+                Self::LEN,
+                bytes.len()
+            )
+        })?;
+        Ok(Self {
+            co2eq: u16::from_le_bytes([bytes[0], bytes[1]]),
+            tvoc: u16::from_le_bytes([bytes[2], bytes[3]]),
+            saved_at: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_baseline_roundtrips() {
+        let baseline = StoredBaseline {
+            co2eq: 0x8a3f,
+            tvoc: 0x8b12,
+            saved_at: 1_700_000_000,
+        };
+        assert_eq!(
+            StoredBaseline::from_bytes(&baseline.to_bytes()).unwrap(),
+            baseline
+        );
+        assert!(StoredBaseline::from_bytes(&baseline.to_bytes()[..4]).is_err());
+    }
+
+    #[test]
+    fn only_recent_baselines_are_restored() {
+        let baseline = StoredBaseline {
+            co2eq: 0x8a3f,
+            tvoc: 0x8b12,
+            saved_at: 1_700_000_000,
+        };
+        assert!(baseline.is_recent(1_700_000_000 + 60 * 60));
+        assert!(baseline.is_recent(1_700_000_000 + BASELINE_MAX_AGE_SECS - 1));
+        assert!(!baseline.is_recent(1_700_000_000 + BASELINE_MAX_AGE_SECS));
+    }
+
+    #[test]
+    fn out_of_range_humidity_overrides_are_rejected() {
+        let set_humidity = |json: &str| match serde_json::from_str::<ControlMessage>(&format!(
+            r#"{{"set_humidity":{{"abs_humidity":{json}}}}}"#
+        )) {
+            Ok(ControlMessage::SetHumidity { abs_humidity }) => Ok(abs_humidity),
+            Ok(msg) => panic!("unexpected message {msg:?}"),
+            Err(error) => Err(error),
+        };
+        assert_eq!(set_humidity("8.5").unwrap(), Some(8.5));
+        assert_eq!(set_humidity("0").unwrap(), Some(0.0));
+        assert_eq!(set_humidity("null").unwrap(), None);
+        assert!(set_humidity("-1").is_err());
+        assert!(set_humidity("256").is_err());
+        assert!(set_humidity("300.5").is_err());
+        assert!(matches!(
+            serde_json::from_str::<ControlMessage>(r#"{"set_humidity":{}}"#),
+            Ok(ControlMessage::SetHumidity { abs_humidity: None })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "espidf"))]
+    fn unreadable_baselines_are_discarded() {
+        use crate::sim;
+
+        let bus = sim::SimBus::new();
+        bus.attach(sim::sgp30::ADDR, sim::Sgp30::new());
+        let metrics: &'static SensorMetrics = Box::leak(Box::new(SensorMetrics::new()));
+        let mut sensor = Sgp30::init(
+            bus.leak_bus_manager(),
+            sim::sgp30::ADDR,
+            metrics,
+            &SensorConfig::default(),
+        )
+        .unwrap();
+
+        let store = BaselineStore::new(Partition::new()).unwrap();
+        store
+            .nvs
+            .lock()
+            .unwrap()
+            .set_raw(BaselineStore::KEY, &[1, 2, 3, 4])
+            .unwrap();
+        assert!(store.load().is_err());
+
+        assert_eq!(
+            sensor.load_baseline(&store, 1_700_000_000),
+            BaselineStatus::Fresh
+        );
+        // the bad entry is erased, so it isn't retried.
+        assert_eq!(store.load().unwrap(), None);
     }
 }
@@ -0,0 +1,282 @@
+//! A simulated I2C bus, for testing the sensor stack on the host.
+//!
+//! When the crate isn't built for the ESP32, [`SharedI2c`](crate::SharedI2c)
+//! is a [`SimBus`] rather than the ESP-IDF I2C driver. Devices are attached to
+//! the bus at an address, and respond to the same I2C transactions as the real
+//! parts, so the real sensor drivers, the bus [`Scanner`], and the sensor
+//! [`Manager`] all run unmodified against them.
+//!
+//! The bus type is selected by the build target, not by a type parameter, so
+//! the simulated bus is only available in host builds, and host builds can't
+//! use any other bus.
+//!
+//! Each device model is a cheaply-cloneable handle, so a test can keep a
+//! handle to a device after attaching it to the bus, and use it to script the
+//! device's measurements. Devices can be [detached](SimBus::detach) and
+//! re-attached to simulate hot-unplugging, and the whole bus can be
+//! [stuck](SimBus::set_stuck) until it's [recovered](Recover).
+//!
+//! [`Scanner`]: crate::sensor::Scanner
+//! [`Manager`]: crate::sensor::Manager
+use crate::bus::Recover;
+use embedded_hal::blocking::{delay, i2c};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub mod bme680;
+pub mod nvs;
+pub mod pmsa003i;
+pub mod scd30;
+mod sensirion;
+pub mod sgp30;
+
+pub use self::{bme680::Bme680, pmsa003i::Pmsa003i, scd30::Scd30, sgp30::Sgp30};
+
+/// A simulated I2C bus.
+///
+/// Cloning a `SimBus` returns another handle to the same bus.
+#[derive(Clone, Default)]
+pub struct SimBus {
+    devices: Arc<Mutex<BTreeMap<u8, Box<dyn Device>>>>,
+    stuck: Arc<AtomicBool>,
+    recoveries: Arc<AtomicUsize>,
+}
+
+/// A simulated I2C device.
+pub trait Device: Send {
+    /// Handles a write transaction addressed to this device.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), I2cError>;
+
+    /// Handles a read transaction addressed to this device.
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), I2cError>;
+}
+
+/// Errors returned by the [`SimBus`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum I2cError {
+    /// Nothing is attached at `addr`.
+    Nack { addr: u8 },
+    /// The device didn't understand the transaction.
+    Protocol(&'static str),
+    /// The bus is stuck, so every transaction times out.
+    Stuck,
+}
+
+/// A delay that doesn't actually wait, since simulated devices respond
+/// instantly.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Delay;
+
+// === impl SimBus ===
+
+impl SimBus {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches `device` to the bus at `addr`, replacing whatever was there.
+    pub fn attach(&self, addr: u8, device: impl Device + 'static) {
+        self.devices.lock().unwrap().insert(addr, Box::new(device));
+    }
+
+    /// Detaches whatever is at `addr` from the bus, as though it had been
+    /// unplugged. Subsequent transactions to `addr` will be NACKed.
+    pub fn detach(&self, addr: u8) {
+        self.devices.lock().unwrap().remove(&addr);
+    }
+
+    /// Sets whether the bus is stuck, as though a device were holding SDA low.
+    ///
+    /// While the bus is stuck, every transaction fails until the bus is
+    /// [recovered](Recover).
+    pub fn set_stuck(&self, stuck: bool) {
+        self.stuck.store(stuck, Ordering::Release);
+    }
+
+    /// Returns the number of times the bus has been recovered.
+    #[must_use]
+    pub fn recoveries(&self) -> usize {
+        self.recoveries.load(Ordering::Acquire)
+    }
+
+    /// Returns a [`crate::I2cBus`] for this bus, as the sensor stack expects.
+    #[must_use]
+    pub fn leak_bus_manager(&self) -> &'static crate::I2cBus {
+        Box::leak(Box::new(shared_bus::BusManager::new(self.clone())))
+    }
+
+    fn with_device<T>(
+        &self,
+        addr: u8,
+        f: impl FnOnce(&mut dyn Device) -> Result<T, I2cError>,
+    ) -> Result<T, I2cError> {
+        if self.stuck.load(Ordering::Acquire) {
+            return Err(I2cError::Stuck);
+        }
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.get_mut(&addr).ok_or(I2cError::Nack { addr })?;
+        f(device.as_mut())
+    }
+}
+
+impl fmt::Debug for SimBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let devices = self.devices.lock().unwrap();
+        f.debug_struct("SimBus")
+            .field("devices", &devices.keys().collect::<Vec<_>>())
+            .field("stuck", &self.stuck.load(Ordering::Acquire))
+            .finish()
+    }
+}
+
+impl Recover for SimBus {
+    fn is_held(&mut self) -> bool {
+        self.stuck.load(Ordering::Acquire)
+    }
+
+    fn recover(&mut self) -> anyhow::Result<()> {
+        self.recoveries.fetch_add(1, Ordering::AcqRel);
+        self.stuck.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl i2c::Write for SimBus {
+    type Error = I2cError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |device| {
+            // an address-only write just checks that the device ACKs.
+            if bytes.is_empty() {
+                return Ok(());
+            }
+            device.write(bytes)
+        })
+    }
+}
+
+impl i2c::Read for SimBus {
+    type Error = I2cError;
+
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |device| device.read(buf))
+    }
+}
+
+impl i2c::WriteRead for SimBus {
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |device| {
+            device.write(bytes)?;
+            device.read(buf)
+        })
+    }
+}
+
+// === impl I2cError ===
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nack { addr } => write!(f, "NACK from {addr:#04x}"),
+            Self::Protocol(msg) => write!(f, "protocol error: {msg}"),
+            Self::Stuck => f.write_str("bus is stuck"),
+        }
+    }
+}
+
+impl std::error::Error for I2cError {}
+
+// === impl Delay ===
+
+macro_rules! impl_delay {
+    ($($t:ty),+) => {
+        $(
+            impl delay::DelayMs<$t> for Delay {
+                fn delay_ms(&mut self, _: $t) {}
+            }
+
+            impl delay::DelayUs<$t> for Delay {
+                fn delay_us(&mut self, _: $t) {}
+            }
+        )+
+    };
+}
+
+impl_delay!(u8, u16, u32);
+
+/// Implements [`Device`] for a model handle that wraps its state in an
+/// `Arc<Mutex<...>>`.
+macro_rules! impl_device_handle {
+    ($handle:ty) => {
+        impl $crate::sim::Device for $handle {
+            fn write(&mut self, bytes: &[u8]) -> Result<(), $crate::sim::I2cError> {
+                self.0.lock().unwrap().write(bytes)
+            }
+
+            fn read(&mut self, buf: &mut [u8]) -> Result<(), $crate::sim::I2cError> {
+                self.0.lock().unwrap().read(buf)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_device_handle;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::blocking::i2c::{Read, Write};
+
+    struct Echo(Vec<u8>);
+
+    impl Device for Echo {
+        fn write(&mut self, bytes: &[u8]) -> Result<(), I2cError> {
+            self.0 = bytes.to_vec();
+            Ok(())
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<(), I2cError> {
+            buf.copy_from_slice(&self.0[..buf.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nacks_detached_devices() {
+        let mut bus = SimBus::new();
+        let mut buf = [0; 2];
+        assert_eq!(bus.read(0x42, &mut buf), Err(I2cError::Nack { addr: 0x42 }));
+
+        bus.attach(0x42, Echo(Vec::new()));
+        bus.write(0x42, &[1, 2]).unwrap();
+        bus.read(0x42, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+
+        bus.detach(0x42);
+        assert_eq!(bus.write(0x42, &[1]), Err(I2cError::Nack { addr: 0x42 }));
+    }
+
+    #[test]
+    fn stuck_until_recovered() {
+        let mut bus = SimBus::new();
+        bus.attach(0x42, Echo(Vec::new()));
+        assert!(!bus.is_held());
+        bus.set_stuck(true);
+        assert!(bus.is_held());
+        assert_eq!(bus.write(0x42, &[1]), Err(I2cError::Stuck));
+
+        bus.recover().unwrap();
+        assert!(!bus.is_held());
+        assert_eq!(bus.recoveries(), 1);
+        bus.write(0x42, &[1]).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! A simulated Bosch BME680 environmental sensor.
+//!
+//! The BME680 is a register-based device: writing a single byte sets the
+//! register pointer, writing more bytes writes `(register, value)` pairs, and
+//! reads return consecutive registers starting from the pointer. Writing
+//! forced mode to `ctrl_meas` performs a measurement, filling the data
+//! registers with the scripted raw ADC values and setting `new_data`.
+//!
+//! The calibration registers are filled with coefficients from a real sensor,
+//! so the driver's compensation produces plausible (if not exactly
+//! predictable) readings.
+use super::{impl_device_handle, I2cError};
+use std::sync::{Arc, Mutex};
+
+/// A handle to a simulated BME680.
+#[derive(Clone, Debug)]
+pub struct Bme680(Arc<Mutex<State>>);
+
+#[derive(Debug)]
+struct State {
+    regs: [u8; 256],
+    pointer: u8,
+    adc: Adc,
+    heater_configs: usize,
+}
+
+/// Raw ADC values reported by a simulated [`Bme680`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Adc {
+    /// 20-bit raw temperature.
+    pub temp: u32,
+    /// 20-bit raw pressure.
+    pub press: u32,
+    /// 16-bit raw humidity.
+    pub hum: u16,
+    /// 10-bit raw gas resistance.
+    pub gas: u16,
+    /// 4-bit gas resistance range.
+    pub gas_range: u8,
+}
+
+/// The primary and secondary I2C addresses.
+pub const ADDRS: [u8; 2] = [0x76, 0x77];
+
+const REG_MEAS_STATUS: u8 = 0x1D;
+const REG_PRESS_MSB: u8 = 0x1F;
+const REG_TEMP_MSB: u8 = 0x22;
+const REG_HUM_MSB: u8 = 0x25;
+const REG_GAS_R_MSB: u8 = 0x2A;
+const REG_RES_HEAT_0: u8 = 0x5A;
+const REG_CTRL_MEAS: u8 = 0x74;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_RESET: u8 = 0xE0;
+
+const CHIP_ID: u8 = 0x61;
+const SOFT_RESET: u8 = 0xB6;
+const MODE_MASK: u8 = 0b11;
+const MODE_FORCED: u8 = 0b01;
+const NEW_DATA: u8 = 0x80;
+const GAS_VALID: u8 = 0x20;
+const HEAT_STAB: u8 = 0x10;
+
+/// Calibration coefficients, as `(register, value)` pairs.
+const CALIBRATION: &[(u8, u8)] = &[
+    // par_t2 = 26203
+    (0x8A, 0x5B),
+    (0x8B, 0x66),
+    // par_t3 = 3
+    (0x8C, 0x03),
+    // par_p1 = 35977
+    (0x8E, 0x89),
+    (0x8F, 0x8C),
+    // par_p2 = -10335
+    (0x90, 0xA1),
+    (0x91, 0xD7),
+    // par_p3 = 88
+    (0x92, 0x58),
+    // par_p4 = 8060
+    (0x94, 0x7C),
+    (0x95, 0x1F),
+    // par_p5 = -109
+    (0x96, 0x93),
+    (0x97, 0xFF),
+    // par_p7 = 33, par_p6 = 30
+    (0x98, 0x21),
+    (0x99, 0x1E),
+    // par_p8 = -3118
+    (0x9C, 0xD2),
+    (0x9D, 0xF3),
+    // par_p9 = -3131
+    (0x9E, 0xC5),
+    (0x9F, 0xF3),
+    // par_p10 = 30
+    (0xA0, 0x1E),
+    // par_h2 = 1010, par_h1 = 842 (sharing a register)
+    (0xE1, 0x3F),
+    (0xE2, 0x2A),
+    (0xE3, 0x34),
+    // par_h3..=par_h7
+    (0xE4, 0x00),
+    (0xE5, 0x2D),
+    (0xE6, 0x14),
+    (0xE7, 0x78),
+    (0xE8, 0x9C),
+    // par_t1 = 26028
+    (0xE9, 0xAC),
+    (0xEA, 0x65),
+    // par_gh2 = -5969
+    (0xEB, 0xAF),
+    (0xEC, 0xE8),
+    // par_gh1 = -30
+    (0xED, 0xE2),
+    // par_gh3 = 18
+    (0xEE, 0x12),
+    // res_heat_val = 50
+    (0x00, 0x32),
+    // res_heat_range = 1
+    (0x02, 0x10),
+    // range_sw_err = 0
+    (0x04, 0x00),
+];
+
+// === impl Bme680 ===
+
+impl Bme680 {
+    #[must_use]
+    pub fn new() -> Self {
+        let mut state = State {
+            regs: [0; 256],
+            pointer: 0,
+            adc: Adc::default(),
+            heater_configs: 0,
+        };
+        state.reset();
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    /// Sets the raw ADC values reported by the next measurement.
+    pub fn set_adc(&self, adc: Adc) {
+        self.0.lock().unwrap().adc = adc;
+    }
+
+    /// Returns the number of times the gas sensor heater's first profile
+    /// (`res_heat_0`) has been configured.
+    #[must_use]
+    pub fn heater_configs(&self) -> usize {
+        self.0.lock().unwrap().heater_configs
+    }
+}
+
+impl Default for Bme680 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_device_handle!(Bme680);
+
+// === impl Adc ===
+
+impl Default for Adc {
+    /// Raw values that work out to roughly 26 degrees Celcius.
+    fn default() -> Self {
+        Self {
+            temp: 500_000,
+            press: 400_000,
+            hum: 25_000,
+            gas: 512,
+            gas_range: 4,
+        }
+    }
+}
+
+// === impl State ===
+
+impl State {
+    fn reset(&mut self) {
+        self.regs = [0; 256];
+        for &(reg, value) in CALIBRATION {
+            self.regs[reg as usize] = value;
+        }
+        self.regs[REG_CHIP_ID as usize] = CHIP_ID;
+    }
+
+    fn measure(&mut self) {
+        let Adc {
+            temp,
+            press,
+            hum,
+            gas,
+            gas_range,
+        } = self.adc;
+        let twenty_bit = |value: u32| [(value >> 12) as u8, (value >> 4) as u8, (value << 4) as u8];
+        self.set_regs(REG_PRESS_MSB, &twenty_bit(press));
+        self.set_regs(REG_TEMP_MSB, &twenty_bit(temp));
+        self.set_regs(REG_HUM_MSB, &hum.to_be_bytes());
+        self.set_regs(
+            REG_GAS_R_MSB,
+            &[
+                (gas >> 2) as u8,
+                ((gas & 0b11) << 6) as u8 | GAS_VALID | HEAT_STAB | (gas_range & 0xF),
+            ],
+        );
+        self.regs[REG_MEAS_STATUS as usize] = NEW_DATA;
+        // the sensor goes back to sleep after a forced measurement.
+        self.regs[REG_CTRL_MEAS as usize] &= !MODE_MASK;
+    }
+
+    fn set_regs(&mut self, start: u8, values: &[u8]) {
+        let start = start as usize;
+        self.regs[start..start + values.len()].copy_from_slice(values);
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), I2cError> {
+        match bytes {
+            [] => Err(I2cError::Protocol("empty BME680 write")),
+            &[reg] => {
+                self.pointer = reg;
+                Ok(())
+            }
+            pairs if pairs.len() % 2 == 0 => {
+                for pair in pairs.chunks(2) {
+                    let (reg, value) = (pair[0], pair[1]);
+                    match reg {
+                        REG_RESET if value == SOFT_RESET => self.reset(),
+                        REG_RESET => {}
+                        REG_RES_HEAT_0 => {
+                            self.regs[reg as usize] = value;
+                            self.heater_configs += 1;
+                        }
+                        REG_CTRL_MEAS => {
+                            self.regs[reg as usize] = value;
+                            if value & MODE_MASK == MODE_FORCED {
+                                self.measure();
+                            }
+                        }
+                        // reading the data registers is read-only
+                        REG_MEAS_STATUS..=REG_GAS_R_MSB => {}
+                        _ => self.regs[reg as usize] = value,
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(I2cError::Protocol(
+                "BME680 writes must be (register, value) pairs",
+            )),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), I2cError> {
+        for byte in buf {
+            *byte = self.regs[self.pointer as usize];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
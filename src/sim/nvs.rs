@@ -0,0 +1,142 @@
+//! An in-memory stand-in for ESP-IDF's NVS library.
+//!
+//! This implements the subset of `EspNvs`'s interface that the crate uses, so
+//! that code which persists settings can run on the host.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// An in-memory NVS partition.
+///
+/// Cloning a `MemPartition` returns another handle to the same partition, so
+/// values written through one [`MemNvs`] are visible to every other `MemNvs`
+/// opened on the same namespace.
+#[derive(Clone, Debug, Default)]
+pub struct MemPartition {
+    values: Arc<Mutex<BTreeMap<(String, String), Vec<u8>>>>,
+}
+
+/// A namespace in a [`MemPartition`].
+#[derive(Debug)]
+pub struct MemNvs {
+    partition: MemPartition,
+    namespace: String,
+    read_write: bool,
+}
+
+/// Errors returned by [`MemNvs`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NvsError {
+    /// The namespace was opened read-only.
+    ReadOnly,
+    /// The buffer passed to [`MemNvs::get_raw`] is too small for the value.
+    BufferTooSmall { needed: usize },
+}
+
+// === impl MemPartition ===
+
+impl MemPartition {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// === impl MemNvs ===
+
+impl MemNvs {
+    pub fn new(
+        partition: MemPartition,
+        namespace: &str,
+        read_write: bool,
+    ) -> Result<Self, NvsError> {
+        Ok(Self {
+            partition,
+            namespace: namespace.to_string(),
+            read_write,
+        })
+    }
+
+    pub fn get_raw<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, NvsError> {
+        let values = self.partition.values.lock().unwrap();
+        let Some(value) = values.get(&self.key(key)) else {
+            return Ok(None);
+        };
+        let buf = buf.get_mut(..value.len()).ok_or(NvsError::BufferTooSmall {
+            needed: value.len(),
+        })?;
+        buf.copy_from_slice(value);
+        Ok(Some(buf))
+    }
+
+    pub fn set_raw(&mut self, key: &str, value: &[u8]) -> Result<bool, NvsError> {
+        self.check_writable()?;
+        self.partition
+            .values
+            .lock()
+            .unwrap()
+            .insert(self.key(key), value.to_vec());
+        Ok(true)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<bool, NvsError> {
+        self.check_writable()?;
+        let removed = self.partition.values.lock().unwrap().remove(&self.key(key));
+        Ok(removed.is_some())
+    }
+
+    fn key(&self, key: &str) -> (String, String) {
+        (self.namespace.clone(), key.to_string())
+    }
+
+    fn check_writable(&self) -> Result<(), NvsError> {
+        if self.read_write {
+            Ok(())
+        } else {
+            Err(NvsError::ReadOnly)
+        }
+    }
+}
+
+// === impl NvsError ===
+
+impl fmt::Display for NvsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly => f.write_str("NVS namespace is read-only"),
+            Self::BufferTooSmall { needed } => {
+                write!(f, "buffer too small for NVS value ({needed} bytes)")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NvsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_share_a_partition() {
+        let partition = MemPartition::new();
+        let mut a = MemNvs::new(partition.clone(), "eclss", true).unwrap();
+        let b = MemNvs::new(partition.clone(), "eclss", false).unwrap();
+        let other = MemNvs::new(partition, "other", true).unwrap();
+
+        assert!(a.set_raw("key", b"hello").unwrap());
+        let mut buf = [0; 8];
+        assert_eq!(b.get_raw("key", &mut buf).unwrap(), Some(&b"hello"[..]));
+        assert_eq!(other.get_raw("key", &mut buf).unwrap(), None);
+        assert_eq!(
+            b.get_raw("key", &mut [0; 2]),
+            Err(NvsError::BufferTooSmall { needed: 5 })
+        );
+
+        assert!(a.remove("key").unwrap());
+        assert!(!a.remove("key").unwrap());
+        assert_eq!(b.get_raw("key", &mut buf).unwrap(), None);
+    }
+}
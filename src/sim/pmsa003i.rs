@@ -0,0 +1,119 @@
+//! A simulated Plantower PMSA003I particulate matter sensor.
+use super::{impl_device_handle, I2cError};
+use std::sync::{Arc, Mutex};
+
+/// A handle to a simulated PMSA003I.
+#[derive(Clone, Debug)]
+pub struct Pmsa003i(Arc<Mutex<State>>);
+
+#[derive(Debug)]
+struct State {
+    /// The data words of the packet: standard PM1.0, PM2.5, and PM10.0
+    /// concentrations, environmental PM1.0, PM2.5, and PM10.0 concentrations,
+    /// and particle counts for 0.3, 0.5, 1.0, 2.5, 5.0, and 10.0um.
+    words: [u16; 12],
+    version: u8,
+    error_code: u8,
+}
+
+pub const ADDR: u8 = 0x12;
+
+const PACKET_LEN: usize = 32;
+
+// === impl Pmsa003i ===
+
+impl Pmsa003i {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            words: [0; 12],
+            version: 0x80,
+            error_code: 0,
+        })))
+    }
+
+    /// Sets the PM1.0, PM2.5, and PM10.0 concentrations (in ug/m^3) that the
+    /// sensor measures.
+    pub fn set_concentrations(&self, pm1_0: u16, pm2_5: u16, pm10_0: u16) {
+        let words = &mut self.0.lock().unwrap().words;
+        words[0..3].copy_from_slice(&[pm1_0, pm2_5, pm10_0]);
+        words[3..6].copy_from_slice(&[pm1_0, pm2_5, pm10_0]);
+    }
+
+    /// Sets the particle counts (per 0.1L of air) for particles of at least
+    /// 0.3, 0.5, 1.0, 2.5, 5.0, and 10.0um.
+    pub fn set_counts(&self, counts: [u16; 6]) {
+        self.0.lock().unwrap().words[6..].copy_from_slice(&counts);
+    }
+
+    /// Sets the error code the sensor sends.
+    pub fn set_error_code(&self, code: u8) {
+        self.0.lock().unwrap().error_code = code;
+    }
+}
+
+impl Default for Pmsa003i {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_device_handle!(Pmsa003i);
+
+// === impl State ===
+
+impl State {
+    fn packet(&self) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0..2].copy_from_slice(&[0x42, 0x4d]);
+        // the frame length, which doesn't include the magic or the length
+        packet[2..4].copy_from_slice(&(PACKET_LEN as u16 - 4).to_be_bytes());
+        for (i, word) in self.words.iter().enumerate() {
+            packet[4 + i * 2..6 + i * 2].copy_from_slice(&word.to_be_bytes());
+        }
+        packet[28] = self.version;
+        packet[29] = self.error_code;
+        let checksum = packet[..PACKET_LEN - 2]
+            .iter()
+            .map(|&byte| byte as u16)
+            .sum::<u16>();
+        packet[PACKET_LEN - 2..].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    fn write(&mut self, _: &[u8]) -> Result<(), I2cError> {
+        Err(I2cError::Protocol("the PMSA003I is read-only"))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), I2cError> {
+        let packet = self.packet();
+        if buf.len() > packet.len() {
+            return Err(I2cError::Protocol("read past the end of the packet"));
+        }
+        buf.copy_from_slice(&packet[..buf.len()]);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sensor-pmsa003i"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_readable_by_the_driver() {
+        let bus = crate::sim::SimBus::new();
+        let sim = Pmsa003i::new();
+        sim.set_concentrations(1, 2, 3);
+        sim.set_counts([60, 50, 40, 30, 20, 10]);
+        bus.attach(ADDR, sim.clone());
+
+        let reading = ::pmsa003i::Pmsa003i::new(bus.clone()).read().unwrap();
+        assert_eq!(reading.concentrations.pm2_5, 2);
+        assert_eq!(reading.concentrations.pm10_0_standard, 3);
+        assert_eq!(reading.counts.particles_0_3um, 60);
+        assert_eq!(reading.counts.particles_10_0um, 10);
+
+        sim.set_error_code(1);
+        assert!(::pmsa003i::Pmsa003i::new(bus.clone()).read().is_err());
+    }
+}
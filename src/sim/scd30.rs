@@ -0,0 +1,159 @@
+//! A simulated Sensirion SCD30 CO2 sensor.
+use super::{
+    impl_device_handle,
+    sensirion::{f32_words, Commands, Sensirion},
+    I2cError,
+};
+use std::sync::{Arc, Mutex};
+
+/// A handle to a simulated SCD30.
+#[derive(Clone, Debug)]
+pub struct Scd30(Arc<Mutex<Sensirion<State>>>);
+
+#[derive(Debug)]
+struct State {
+    firmware: u16,
+    measuring: bool,
+    data_ready: bool,
+    settings_readable: bool,
+    ambient_pressure_mbar: u16,
+    measurement_interval_secs: u16,
+    auto_calibration: bool,
+    temp_offset: u16,
+    altitude_m: u16,
+    frc_ppm: u16,
+    co2: f32,
+    temp: f32,
+    rh: f32,
+}
+
+pub const ADDR: u8 = 0x61;
+
+// === impl Scd30 ===
+
+impl Scd30 {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Sensirion::new(State {
+            firmware: 0x0342,
+            measuring: false,
+            data_ready: true,
+            settings_readable: true,
+            ambient_pressure_mbar: 0,
+            measurement_interval_secs: 2,
+            auto_calibration: true,
+            temp_offset: 0,
+            altitude_m: 0,
+            frc_ppm: 400,
+            co2: 420.0,
+            temp: 20.0,
+            rh: 50.0,
+        }))))
+    }
+
+    /// Sets the CO2 (ppm), temperature (degrees Celcius), and relative
+    /// humidity (%) that the sensor measures.
+    pub fn set_measurement(&self, co2: f32, temp: f32, rh: f32) {
+        let state = &mut self.0.lock().unwrap().commands;
+        state.co2 = co2;
+        state.temp = temp;
+        state.rh = rh;
+    }
+
+    /// Sets whether the sensor reports that it has data ready, while it's
+    /// measuring.
+    pub fn set_data_ready(&self, ready: bool) {
+        self.0.lock().unwrap().commands.data_ready = ready;
+    }
+
+    /// Sets whether the sensor's settings can be read back. While they can't,
+    /// reading a setting fails, but setting it still succeeds.
+    pub fn set_settings_readable(&self, readable: bool) {
+        self.0.lock().unwrap().commands.settings_readable = readable;
+    }
+
+    /// Returns the ambient pressure (in millibars) that the sensor was last
+    /// told to compensate for.
+    #[must_use]
+    pub fn ambient_pressure_mbar(&self) -> u16 {
+        self.0.lock().unwrap().commands.ambient_pressure_mbar
+    }
+
+    /// Returns the sensor's measurement interval, in seconds.
+    #[must_use]
+    pub fn measurement_interval_secs(&self) -> u16 {
+        self.0.lock().unwrap().commands.measurement_interval_secs
+    }
+}
+
+impl Default for Scd30 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_device_handle!(Scd30);
+
+// === impl State ===
+
+impl Commands for State {
+    fn command(&mut self, cmd: u16, args: &[u16]) -> Result<Vec<u16>, I2cError> {
+        /// Commands that set a setting if they have an argument, and read it
+        /// back if they don't.
+        fn setting(value: &mut u16, args: &[u16], readable: bool) -> Result<Vec<u16>, I2cError> {
+            match args {
+                [arg] => {
+                    *value = *arg;
+                    Ok(Vec::new())
+                }
+                _ if readable => Ok(vec![*value]),
+                _ => Err(I2cError::Protocol("SCD30 settings can't be read")),
+            }
+        }
+
+        let readable = self.settings_readable;
+
+        let rsp = match cmd {
+            // trigger continuous measurement
+            0x0010 => {
+                self.ambient_pressure_mbar = args.first().copied().unwrap_or(0);
+                self.measuring = true;
+                Vec::new()
+            }
+            // stop continuous measurement
+            0x0104 => {
+                self.measuring = false;
+                Vec::new()
+            }
+            0x4600 => setting(&mut self.measurement_interval_secs, args, readable)?,
+            // get data ready status
+            0x0202 => vec![(self.measuring && self.data_ready) as u16],
+            // read measurement
+            0x0300 => {
+                if !self.measuring {
+                    return Err(I2cError::Protocol("SCD30 is not measuring"));
+                }
+                [self.co2, self.temp, self.rh]
+                    .into_iter()
+                    .flat_map(f32_words)
+                    .collect()
+            }
+            0x5306 => {
+                let mut asc = self.auto_calibration as u16;
+                let rsp = setting(&mut asc, args, readable)?;
+                self.auto_calibration = asc != 0;
+                rsp
+            }
+            0x5204 => setting(&mut self.frc_ppm, args, readable)?,
+            0x5403 => setting(&mut self.temp_offset, args, readable)?,
+            0x5102 => setting(&mut self.altitude_m, args, readable)?,
+            // read firmware version
+            0xD100 => vec![self.firmware],
+            // soft reset. the SCD30 remembers its settings (including whether
+            // it was measuring) across resets.
+            0xD304 => Vec::new(),
+            _ => return Err(I2cError::Protocol("unknown SCD30 command")),
+        };
+        Ok(rsp)
+    }
+}
@@ -0,0 +1,103 @@
+//! The command-based protocol shared by Sensirion sensors (the SCD30 and
+//! SGP30).
+//!
+//! Every transaction starts with a write of a 16-bit command, optionally
+//! followed by 16-bit arguments. Commands that return data are followed by a
+//! read of one or more 16-bit words. Every word (in either direction) is
+//! followed by a CRC-8 checksum.
+use super::I2cError;
+use crate::sensor::scan::sensirion_crc8;
+
+/// A Sensirion sensor's command set.
+pub(super) trait Commands {
+    /// Handles `cmd` with the given arguments, returning the words to send
+    /// back when the response is read.
+    fn command(&mut self, cmd: u16, args: &[u16]) -> Result<Vec<u16>, I2cError>;
+}
+
+/// Implements the Sensirion transaction framing for a [`Commands`].
+#[derive(Debug, Default)]
+pub(super) struct Sensirion<C> {
+    pub(super) commands: C,
+    response: Vec<u8>,
+}
+
+impl<C: Commands> Sensirion<C> {
+    pub(super) fn new(commands: C) -> Self {
+        Self {
+            commands,
+            response: Vec::new(),
+        }
+    }
+
+    pub(super) fn write(&mut self, bytes: &[u8]) -> Result<(), I2cError> {
+        let (cmd, args) = match bytes {
+            [hi, lo, args @ ..] if args.len() % 3 == 0 => (u16::from_be_bytes([*hi, *lo]), args),
+            _ => return Err(I2cError::Protocol("malformed Sensirion command")),
+        };
+
+        let args = args
+            .chunks(3)
+            .map(|word| {
+                if sensirion_crc8(&word[..2]) != word[2] {
+                    return Err(I2cError::Protocol("argument CRC mismatch"));
+                }
+                Ok(u16::from_be_bytes([word[0], word[1]]))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let words = self.commands.command(cmd, &args)?;
+        self.response = words
+            .into_iter()
+            .flat_map(|word| {
+                let [hi, lo] = word.to_be_bytes();
+                [hi, lo, sensirion_crc8(&[hi, lo])]
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub(super) fn read(&mut self, buf: &mut [u8]) -> Result<(), I2cError> {
+        if buf.len() > self.response.len() {
+            return Err(I2cError::Protocol("read past the end of the response"));
+        }
+        buf.copy_from_slice(&self.response[..buf.len()]);
+        self.response.drain(..buf.len());
+        Ok(())
+    }
+}
+
+/// Splits an `f32` into the two big-endian words the SCD30 sends it as.
+pub(super) fn f32_words(value: f32) -> [u16; 2] {
+    let [a, b, c, d] = value.to_be_bytes();
+    [u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl Commands for Echo {
+        fn command(&mut self, cmd: u16, args: &[u16]) -> Result<Vec<u16>, I2cError> {
+            Ok(std::iter::once(cmd).chain(args.iter().copied()).collect())
+        }
+    }
+
+    #[test]
+    fn frames_commands_and_responses() {
+        let mut sensirion = Sensirion::new(Echo);
+        // command 0xBEEF with argument 0xBEEF (whose CRC is 0x92, per the
+        // datasheet)
+        sensirion.write(&[0xBE, 0xEF, 0xBE, 0xEF, 0x92]).unwrap();
+        let mut buf = [0; 6];
+        sensirion.read(&mut buf).unwrap();
+        assert_eq!(buf, [0xBE, 0xEF, 0x92, 0xBE, 0xEF, 0x92]);
+
+        // bad argument CRC
+        assert!(sensirion.write(&[0xBE, 0xEF, 0xBE, 0xEF, 0x00]).is_err());
+        // the response was already read
+        assert!(sensirion.read(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! A simulated Sensirion SGP30 tVOC sensor.
+use super::{
+    impl_device_handle,
+    sensirion::{Commands, Sensirion},
+    I2cError,
+};
+use std::sync::{Arc, Mutex};
+
+/// A handle to a simulated SGP30.
+#[derive(Clone, Debug)]
+pub struct Sgp30(Arc<Mutex<Sensirion<State>>>);
+
+#[derive(Debug)]
+struct State {
+    initialized: bool,
+    co2eq_ppm: u16,
+    tvoc_ppb: u16,
+    baseline: [u16; 2],
+    abs_humidity: u16,
+    h2_raw: u16,
+    ethanol_raw: u16,
+}
+
+pub const ADDR: u8 = 0x58;
+
+/// Product type 0 (SGP30), product version 0x22.
+const FEATURE_SET: u16 = 0x0022;
+/// The value returned by a passing self-test.
+const SELFTEST_OK: u16 = 0xD400;
+
+// === impl Sgp30 ===
+
+impl Sgp30 {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Sensirion::new(State {
+            initialized: false,
+            co2eq_ppm: 400,
+            tvoc_ppb: 0,
+            baseline: [0; 2],
+            abs_humidity: 0,
+            h2_raw: 13_000,
+            ethanol_raw: 18_000,
+        }))))
+    }
+
+    /// Sets the eCO2 (ppm) and tVOC (ppb) that the sensor measures.
+    pub fn set_measurement(&self, co2eq_ppm: u16, tvoc_ppb: u16) {
+        let state = &mut self.0.lock().unwrap().commands;
+        state.co2eq_ppm = co2eq_ppm;
+        state.tvoc_ppb = tvoc_ppb;
+    }
+
+    /// Returns the sensor's current baseline, as `[co2eq, tvoc]`.
+    #[must_use]
+    pub fn baseline(&self) -> [u16; 2] {
+        self.0.lock().unwrap().commands.baseline
+    }
+
+    /// Returns the absolute humidity the sensor is compensating for, in the
+    /// sensor's 8.8 fixed-point g/m^3 format.
+    #[must_use]
+    pub fn abs_humidity(&self) -> u16 {
+        self.0.lock().unwrap().commands.abs_humidity
+    }
+}
+
+impl Default for Sgp30 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_device_handle!(Sgp30);
+
+// === impl State ===
+
+impl Commands for State {
+    fn command(&mut self, cmd: u16, args: &[u16]) -> Result<Vec<u16>, I2cError> {
+        let rsp = match (cmd, args) {
+            // init air quality
+            (0x2003, []) => {
+                self.initialized = true;
+                self.baseline = [0; 2];
+                Vec::new()
+            }
+            // measure air quality. like the real sensor, this reads 400 ppm
+            // and 0 ppb until the sensor has been initialized.
+            (0x2008, []) if self.initialized => vec![self.co2eq_ppm, self.tvoc_ppb],
+            (0x2008, []) => vec![400, 0],
+            (0x2015, []) => self.baseline.to_vec(),
+            // the baseline is written in the opposite order from how it's read
+            // (tVOC first), per the datasheet.
+            (0x201E, &[tvoc, co2eq]) => {
+                self.baseline = [co2eq, tvoc];
+                Vec::new()
+            }
+            (0x2061, &[humidity]) => {
+                self.abs_humidity = humidity;
+                Vec::new()
+            }
+            (0x2032, []) => vec![SELFTEST_OK],
+            (0x202F, []) => vec![FEATURE_SET],
+            (0x2050, []) => vec![self.h2_raw, self.ethanol_raw],
+            // get serial ID
+            (0x3682, []) => vec![0x0000, 0x0123, 0x4567],
+            _ => return Err(I2cError::Protocol("unknown SGP30 command")),
+        };
+        Ok(rsp)
+    }
+}